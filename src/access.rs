@@ -0,0 +1,89 @@
+use crate::config::AccessConfig;
+use crate::error::Result;
+use crate::routing::parse_cidr;
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 客户端来源 IP 访问控制
+///
+/// 命中 deny 则拒绝；allow 非空时只接受命中 allow 的客户端
+pub struct ClientAccess {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    rejected: AtomicU64,
+}
+
+impl ClientAccess {
+    pub fn new(config: &AccessConfig) -> Result<Self> {
+        Ok(Self {
+            allow: config.allow.iter().map(|cidr| parse_cidr(cidr)).collect::<Result<_>>()?,
+            deny: config.deny.iter().map(|cidr| parse_cidr(cidr)).collect::<Result<_>>()?,
+            rejected: AtomicU64::new(0),
+        })
+    }
+
+    /// 检查客户端是否允许连接，拒绝时计数
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+
+        let allowed = !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)));
+
+        if !allowed {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+
+        allowed
+    }
+
+    /// 获取被拒绝的连接总数
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(allow: &[&str], deny: &[&str]) -> ClientAccess {
+        ClientAccess::new(&AccessConfig {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_default_allows_all() {
+        let access = access(&[], &[]);
+        assert!(access.check("203.0.113.1".parse().unwrap()));
+        assert!(access.check("::1".parse().unwrap()));
+        assert_eq!(access.rejected_count(), 0);
+    }
+
+    #[test]
+    fn test_allow_and_deny() {
+        let access = access(&["10.0.0.0/8", "::1"], &["10.1.0.0/16"]);
+
+        assert!(access.check("10.2.3.4".parse().unwrap()));
+        assert!(access.check("::1".parse().unwrap()));
+        assert!(access.check("::ffff:10.2.3.4".parse().unwrap()));
+        assert!(!access.check("10.1.2.3".parse().unwrap()));
+        assert!(!access.check("192.168.1.1".parse().unwrap()));
+        assert_eq!(access.rejected_count(), 2);
+    }
+
+    #[test]
+    fn test_invalid_cidr() {
+        let config = AccessConfig {
+            allow: vec!["not-an-ip".to_string()],
+            deny: Vec::new(),
+        };
+        assert!(ClientAccess::new(&config).is_err());
+    }
+}
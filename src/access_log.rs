@@ -0,0 +1,237 @@
+use crate::config::{AccessLogConfig, LogRotation};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// 按大小或按天轮转的访问日志文件
+///
+/// 已轮转的文件依次命名为 `<path>.1`、`<path>.2` ……，序号越小越新，
+/// 启用压缩时追加 `.gz` 后缀；超出保留数量的文件被删除
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: LogRotation,
+    max_size: u64,
+    max_files: usize,
+    compress: bool,
+    file: File,
+    size: u64,
+    day: u64,
+}
+
+impl RotatingFile {
+    pub fn open(config: &AccessLogConfig) -> io::Result<Self> {
+        let path = PathBuf::from(&config.path);
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = open_append(&path)?;
+        let metadata = file.metadata()?;
+        // 以文件最后修改时间所在的日期为准，重启后跨天的旧文件也会被轮转
+        let day = metadata.modified().map(day_of).unwrap_or_else(|_| today());
+
+        Ok(Self {
+            path,
+            rotation: config.rotation,
+            max_size: config.max_size_mb * 1024 * 1024,
+            max_files: config.max_files,
+            compress: config.compress,
+            file,
+            size: metadata.len(),
+            day,
+        })
+    }
+
+    /// 写入 `incoming` 字节前是否需要轮转
+    fn should_rotate(&self, incoming: usize, today: u64) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        match self.rotation {
+            LogRotation::Daily => today != self.day,
+            LogRotation::Size => self.max_size > 0 && self.size + incoming as u64 > self.max_size,
+            LogRotation::Never => false,
+        }
+    }
+
+    /// 第 `index` 个已轮转文件的路径
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        if self.compress {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // 依次后移已轮转的文件，最旧的被覆盖
+            remove_if_exists(&self.rotated_path(self.max_files))?;
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+
+            if self.compress {
+                let mut plain = self.path.clone().into_os_string();
+                plain.push(".1");
+                let plain = PathBuf::from(plain);
+                fs::rename(&self.path, &plain)?;
+                compress_file(&plain, &self.rotated_path(1))?;
+                fs::remove_file(&plain)?;
+            } else {
+                fs::rename(&self.path, self.rotated_path(1))?;
+            }
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let today = today();
+        if self.should_rotate(buf.len(), today) {
+            // 轮转失败时继续写入当前文件，不丢弃访问日志
+            if let Err(e) = self.rotate() {
+                eprintln!("Failed to rotate access log {:?}: {}", self.path, e);
+            }
+        }
+        self.day = today;
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn compress_file(from: &Path, to: &Path) -> io::Result<()> {
+    let mut input = File::open(from)?;
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()
+}
+
+/// 自 UNIX 纪元起的 UTC 天数
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / SECS_PER_DAY)
+        .unwrap_or_default()
+}
+
+fn today() -> u64 {
+    day_of(SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yun-socket-proxy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config(dir: &Path, rotation: LogRotation, compress: bool) -> AccessLogConfig {
+        AccessLogConfig {
+            path: dir.join("access.log").to_string_lossy().into_owned(),
+            rotation,
+            max_size_mb: 1,
+            max_files: 2,
+            compress,
+            ..AccessLogConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_size_rotation_keeps_max_files() {
+        let dir = temp_dir("access-size");
+        let mut file = RotatingFile::open(&config(&dir, LogRotation::Size, false)).unwrap();
+        file.max_size = 10;
+
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("access.log")).unwrap(), "dddddddd\n");
+        assert_eq!(fs::read_to_string(dir.join("access.log.1")).unwrap(), "cccccccc\n");
+        assert_eq!(fs::read_to_string(dir.join("access.log.2")).unwrap(), "bbbbbbbb\n");
+        assert!(!dir.join("access.log.3").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_daily_rotation_with_compression() {
+        let dir = temp_dir("access-daily");
+        let mut file = RotatingFile::open(&config(&dir, LogRotation::Daily, true)).unwrap();
+
+        file.write_all(b"yesterday\n").unwrap();
+        assert!(!file.should_rotate(6, file.day));
+
+        // 模拟跨天
+        file.day -= 1;
+        file.write_all(b"today\n").unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("access.log")).unwrap(), "today\n");
+        assert!(!dir.join("access.log.1").exists());
+
+        let mut content = String::new();
+        GzDecoder::new(File::open(dir.join("access.log.1.gz")).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "yesterday\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_never_rotate() {
+        let dir = temp_dir("access-never");
+        let mut file = RotatingFile::open(&config(&dir, LogRotation::Never, false)).unwrap();
+        file.max_size = 1;
+        file.day = 0;
+
+        file.write_all(b"first\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("access.log")).unwrap(), "first\nsecond\n");
+        assert!(!dir.join("access.log.1").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
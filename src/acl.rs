@@ -0,0 +1,263 @@
+use crate::config::AclConfig;
+use crate::error::{ProxyError, Result};
+use crate::protocol::Address;
+use crate::routing::{parse_cidr, parse_port_spec};
+use ipnet::IpNet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use tokio::net::lookup_host;
+use tracing::{debug, warn};
+
+/// 目标地址访问控制
+///
+/// 判定顺序：命中 allow 即放行；否则命中 deny 或（启用 block_private 时）
+/// 属于回环、链路本地、内网等地址则拒绝；其余放行
+pub struct DestinationAcl {
+    block_private: bool,
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    allow_ports: Vec<RangeInclusive<u16>>,
+    deny_ports: Vec<RangeInclusive<u16>>,
+}
+
+impl DestinationAcl {
+    pub fn new(config: &AclConfig) -> Result<Self> {
+        let parse_nets = |cidrs: &[String]| cidrs.iter().map(|cidr| parse_cidr(cidr)).collect::<Result<Vec<_>>>();
+        let parse_ports = |ports: &[crate::config::PortSpec]| {
+            ports.iter().map(parse_port_spec).collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            block_private: config.block_private,
+            allow: parse_nets(&config.allow)?,
+            deny: parse_nets(&config.deny)?,
+            allow_ports: parse_ports(&config.allow_ports)?,
+            deny_ports: parse_ports(&config.deny_ports)?,
+        })
+    }
+
+    /// 检查目标端口是否允许
+    pub fn is_port_allowed(&self, port: u16) -> bool {
+        !self.deny_ports.iter().any(|range| range.contains(&port))
+            && (self.allow_ports.is_empty() || self.allow_ports.iter().any(|range| range.contains(&port)))
+    }
+
+    /// 检查目标 IP 是否允许
+    pub fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        let ip = canonical_ip(ip);
+
+        if self.allow.iter().any(|net| net.contains(&ip)) {
+            return true;
+        }
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        !(self.block_private && is_private(ip))
+    }
+
+    /// 检查解析后的目标地址是否允许
+    pub fn is_allowed(&self, addr: &SocketAddr) -> bool {
+        self.is_port_allowed(addr.port()) && self.is_ip_allowed(addr.ip())
+    }
+
+    /// 解析目标地址并过滤掉不允许访问的结果
+    ///
+    /// 域名解析出的所有地址都会逐一检查，调用方只应连接返回的地址
+    pub async fn resolve(&self, address: &Address) -> Result<Vec<SocketAddr>> {
+        if !self.is_port_allowed(address.port()) {
+            warn!("Destination port {} denied by ACL", address.port());
+            return Err(ProxyError::ConnectionNotAllowed);
+        }
+
+        let resolved: Vec<SocketAddr> = match address.socket_addr() {
+            Some(addr) => vec![addr],
+            None => lookup_host(address.to_string())
+                .await
+                .map_err(|e| {
+                    debug!("Failed to resolve {}: {}", address, e);
+                    ProxyError::HostUnreachable
+                })?
+                .collect(),
+        };
+
+        let allowed: Vec<SocketAddr> = resolved.iter().copied().filter(|addr| self.is_allowed(addr)).collect();
+        if allowed.is_empty() {
+            if resolved.is_empty() {
+                return Err(ProxyError::HostUnreachable);
+            }
+            warn!("Destination {} ({:?}) denied by ACL", address, resolved);
+            return Err(ProxyError::ConnectionNotAllowed);
+        }
+
+        Ok(allowed)
+    }
+}
+
+/// IPv4 映射的 IPv6 地址按 IPv4 处理
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// 判断是否为回环、链路本地、内网或其他非公网地址
+pub fn is_private(ip: IpAddr) -> bool {
+    match canonical_ip(ip) {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => is_private_v6(ip),
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || octets[0] == 0 // 0.0.0.0/8
+        || (octets[0] == 100 && (octets[1] & 0xC0) == 64) // 100.64.0.0/10 运营商级 NAT
+        || (octets[0] == 198 && (octets[1] & 0xFE) == 18) // 198.18.0.0/15 基准测试
+        || octets[0] >= 240 // 240.0.0.0/4 保留
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || (segments[0] & 0xFE00) == 0xFC00 // fc00::/7 唯一本地地址
+        || (segments[0] & 0xFFC0) == 0xFE80 // fe80::/10 链路本地
+        || (segments[0] & 0xFFC0) == 0xFEC0 // fec0::/10 站点本地（已废弃）
+        || (segments[0] == 0x64 && segments[1] == 0xFF9B && segments[2] == 1) // 64:ff9b:1::/48 本地 NAT64
+        || embedded_ipv4(ip).is_some_and(is_private_v4)
+}
+
+/// 取出 NAT64、6to4 和 IPv4 兼容地址中内嵌的 IPv4 地址
+///
+/// 这些地址经转换后实际访问内嵌的 IPv4，须按 IPv4 检查
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let v4 = |high: u16, low: u16| Ipv4Addr::from(((high as u32) << 16) | low as u32);
+    match ip.segments() {
+        [0x64, 0xFF9B, 0, 0, 0, 0, high, low] => Some(v4(high, low)), // 64:ff9b::/96 NAT64
+        [0x2002, high, low, ..] => Some(v4(high, low)), // 2002::/16 6to4
+        [0, 0, 0, 0, 0, 0, high, low] => Some(v4(high, low)), // ::a.b.c.d IPv4 兼容
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PortSpec;
+
+    fn acl(config: AclConfig) -> DestinationAcl {
+        DestinationAcl::new(&config).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_default_blocks_private() {
+        let acl = acl(AclConfig::default());
+
+        for blocked in [
+            "127.0.0.1",
+            "169.254.169.254",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!acl.is_ip_allowed(ip(blocked)), "{} should be blocked", blocked);
+        }
+
+        for allowed in ["8.8.8.8", "1.1.1.1", "2606:4700::1111", "172.32.0.1"] {
+            assert!(acl.is_ip_allowed(ip(allowed)), "{} should be allowed", allowed);
+        }
+    }
+
+    #[test]
+    fn test_embedded_ipv4() {
+        let acl = acl(AclConfig::default());
+
+        // NAT64
+        for blocked in ["64:ff9b::7f00:1", "64:ff9b::a00:1", "64:ff9b::a9fe:a9fe", "64:ff9b:1::808:808"] {
+            assert!(!acl.is_ip_allowed(ip(blocked)), "{} should be blocked", blocked);
+        }
+        assert!(acl.is_ip_allowed(ip("64:ff9b::808:808")));
+
+        // 6to4
+        for blocked in ["2002:7f00:1::", "2002:a00:1::1", "2002:a9fe:a9fe::"] {
+            assert!(!acl.is_ip_allowed(ip(blocked)), "{} should be blocked", blocked);
+        }
+        assert!(acl.is_ip_allowed(ip("2002:808:808::1")));
+
+        // IPv4 兼容
+        for blocked in ["::127.0.0.1", "::10.0.0.1", "::169.254.169.254"] {
+            assert!(!acl.is_ip_allowed(ip(blocked)), "{} should be blocked", blocked);
+        }
+        assert!(acl.is_ip_allowed(ip("::8.8.8.8")));
+    }
+
+    #[test]
+    fn test_allow_overrides_deny_and_private() {
+        let acl = acl(AclConfig {
+            allow: vec!["10.0.0.5".to_string()],
+            deny: vec!["8.8.8.0/24".to_string(), "10.0.0.0/8".to_string()],
+            ..AclConfig::default()
+        });
+
+        assert!(acl.is_ip_allowed(ip("10.0.0.5")));
+        assert!(!acl.is_ip_allowed(ip("10.0.0.6")));
+        assert!(!acl.is_ip_allowed(ip("8.8.8.8")));
+        assert!(acl.is_ip_allowed(ip("8.8.4.4")));
+    }
+
+    #[test]
+    fn test_block_private_disabled() {
+        let acl = acl(AclConfig {
+            block_private: false,
+            ..AclConfig::default()
+        });
+        assert!(acl.is_ip_allowed(ip("127.0.0.1")));
+        assert!(acl.is_ip_allowed(ip("192.168.1.1")));
+    }
+
+    #[test]
+    fn test_ports() {
+        let acl = acl(AclConfig {
+            allow_ports: vec![PortSpec::Single(80), PortSpec::Range("443-450".to_string())],
+            deny_ports: vec![PortSpec::Single(445)],
+            ..AclConfig::default()
+        });
+
+        assert!(acl.is_port_allowed(80));
+        assert!(acl.is_port_allowed(443));
+        assert!(!acl.is_port_allowed(445));
+        assert!(!acl.is_port_allowed(22));
+        assert!(acl.is_allowed(&"1.1.1.1:443".parse().unwrap()));
+        assert!(!acl.is_allowed(&"1.1.1.1:22".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_checks_resolved_addresses() {
+        let acl = acl(AclConfig::default());
+
+        let result = acl.resolve(&Address::Domain("localhost".to_string(), 80)).await;
+        assert!(matches!(result, Err(ProxyError::ConnectionNotAllowed)));
+
+        let result = acl.resolve(&Address::Ipv4(Ipv4Addr::new(8, 8, 8, 8), 53)).await;
+        assert_eq!(result.unwrap(), vec!["8.8.8.8:53".parse().unwrap()]);
+    }
+}
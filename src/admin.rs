@@ -0,0 +1,152 @@
+use crate::auth::{BanTarget, LockoutTracker};
+use crate::error::{ProxyError, Result};
+use crate::protocol;
+use crate::session::SessionRegistry;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+/// 管理 HTTP 服务
+///
+/// - `GET /sessions` 列出活动会话
+/// - `DELETE /sessions/{id}` 终止指定会话
+/// - `DELETE /users/{user}/sessions` 终止指定用户的所有会话
+/// - `GET /bans` 列出认证失败锁定中的 IP 和用户
+///
+/// 请求头须在 `head_timeout` 内读完，与代理端口的握手超时一致
+pub async fn serve(
+    listener: TcpListener,
+    token: String,
+    sessions: SessionRegistry,
+    lockout: Arc<LockoutTracker>,
+    head_timeout: Duration,
+) {
+    loop {
+        let (mut stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept admin connection: {}", e);
+                continue;
+            }
+        };
+
+        let token = token.clone();
+        let sessions = sessions.clone();
+        let lockout = lockout.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(&mut stream, &token, &sessions, &lockout, head_timeout).await {
+                debug!("Admin request from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_request(
+    stream: &mut TcpStream,
+    token: &str,
+    sessions: &SessionRegistry,
+    lockout: &LockoutTracker,
+    head_timeout: Duration,
+) -> Result<()> {
+    let (head, _) = protocol::http::read_request_head_within(stream, head_timeout).await?;
+
+    let authorized = head
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|provided| bool::from(provided.trim().as_bytes().ct_eq(token.as_bytes())));
+    if !authorized {
+        return protocol::http::send_response(stream, 401, "Unauthorized", &[("WWW-Authenticate", "Bearer")]).await;
+    }
+
+    let path = head.target.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = head.method.to_ascii_uppercase();
+
+    match (method.as_str(), segments.as_slice()) {
+        ("GET", ["sessions"]) => send_json(stream, &sessions.list()).await,
+        ("DELETE", ["sessions", id]) => match id.parse::<u64>() {
+            Ok(id) if sessions.kill(id) => {
+                info!("Admin killed session {}", id);
+                send_json(stream, &json!({ "killed": 1 })).await
+            }
+            _ => protocol::http::send_response(stream, 404, "Not Found", &[]).await,
+        },
+        ("DELETE", ["users", user, "sessions"]) => match percent_decode(user) {
+            Some(user) => {
+                let killed = sessions.kill_user(&user);
+                info!("Admin killed {} sessions of user {}", killed, user);
+                send_json(stream, &json!({ "killed": killed })).await
+            }
+            None => protocol::http::send_response(stream, 400, "Bad Request", &[]).await,
+        },
+        ("GET", ["bans"]) => send_json(stream, &bans(lockout)).await,
+        _ => protocol::http::send_response(stream, 404, "Not Found", &[]).await,
+    }
+}
+
+/// 解码路径段中的 `%XX` 转义，转义无效或结果不是 UTF-8 时返回 None
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = segment.bytes();
+    let mut decoded = Vec::with_capacity(segment.len());
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+        let mut hex = || (bytes.next()? as char).to_digit(16);
+        decoded.push((hex()? * 16 + hex()?) as u8);
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// 当前封禁列表
+fn bans(lockout: &LockoutTracker) -> Vec<serde_json::Value> {
+    lockout
+        .bans()
+        .into_iter()
+        .map(|ban| {
+            let (kind, target) = match ban.target {
+                BanTarget::Ip(ip) => ("ip", ip.to_string()),
+                BanTarget::User(user) => ("user", user),
+            };
+            json!({ "type": kind, "target": target, "remaining_secs": ban.remaining.as_secs() })
+        })
+        .collect()
+}
+
+/// 发送 JSON 响应后关闭连接
+async fn send_json<T: Serialize>(stream: &mut TcpStream, body: &T) -> Result<()> {
+    let body = serde_json::to_string(body)
+        .map_err(|e| ProxyError::Protocol(format!("Failed to serialize admin response: {}", e)))?;
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("alice").as_deref(), Some("alice"));
+        assert_eq!(percent_decode("alice%40example.com").as_deref(), Some("alice@example.com"));
+        assert_eq!(percent_decode("%E5%BC%A0%e4%b8%89").as_deref(), Some("\u{5f20}\u{4e09}"));
+        assert_eq!(percent_decode("a%2Fb").as_deref(), Some("a/b"));
+        assert!(percent_decode("bad%2").is_none());
+        assert!(percent_decode("bad%zz").is_none());
+        assert!(percent_decode("%+1").is_none());
+        assert!(percent_decode("%ff").is_none());
+    }
+}
@@ -0,0 +1,346 @@
+use crate::auth::{Authenticator, Identity};
+use crate::config::LockoutConfig;
+use crate::error::{ProxyError, Result};
+use futures::future::BoxFuture;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// 超过该数量时清理已过期的记录
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// 封禁对象
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BanTarget {
+    Ip(IpAddr),
+    User(String),
+}
+
+/// 封禁记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    pub target: BanTarget,
+    /// 剩余封禁时长
+    pub remaining: Duration,
+}
+
+#[derive(Default)]
+struct Entry {
+    /// 时间窗口内的失败时刻
+    failures: VecDeque<Instant>,
+    banned_until: Option<Instant>,
+}
+
+/// 认证失败跟踪
+///
+/// 时间窗口内失败次数达到上限后，在封禁时长内拒绝该客户端 IP 和/或用户名
+pub struct LockoutTracker {
+    enabled: bool,
+    max_failures: usize,
+    window: Duration,
+    ban: Duration,
+    per_ip: bool,
+    per_user: bool,
+    ips: Mutex<HashMap<IpAddr, Entry>>,
+    users: Mutex<HashMap<String, Entry>>,
+}
+
+impl LockoutTracker {
+    pub fn new(config: &LockoutConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            max_failures: config.max_failures.max(1) as usize,
+            window: Duration::from_secs(config.window_secs),
+            ban: Duration::from_secs(config.ban_secs),
+            per_ip: config.per_ip,
+            per_user: config.per_user,
+            ips: Mutex::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 客户端 IP 是否处于封禁中
+    pub fn is_ip_banned(&self, ip: IpAddr) -> bool {
+        self.enabled && self.per_ip && is_banned(&self.ips, &ip)
+    }
+
+    /// 用户名是否处于封禁中
+    pub fn is_user_banned(&self, username: &str) -> bool {
+        self.enabled && self.per_user && is_banned(&self.users, username)
+    }
+
+    /// 记录一次认证失败，达到上限时封禁
+    pub fn record_failure(&self, ip: IpAddr, username: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.per_ip && self.add_failure(&self.ips, ip) {
+            warn!("Banned client {} for {:?} after repeated authentication failures", ip, self.ban);
+        }
+        if self.per_user && self.add_failure(&self.users, username.to_string()) {
+            warn!("Banned user {} for {:?} after repeated authentication failures", username, self.ban);
+        }
+    }
+
+    /// 认证成功后清除该用户的失败计数
+    ///
+    /// 不清除客户端 IP 的计数，否则持有一个有效账号即可不断重置 IP 锁定、继续猜测其他账号
+    pub fn record_success(&self, username: &str) {
+        if self.enabled && self.per_user {
+            self.users.lock().unwrap().remove(username);
+        }
+    }
+
+    /// 当前生效的封禁列表
+    pub fn bans(&self) -> Vec<Ban> {
+        let now = Instant::now();
+        let active = |until: &Option<Instant>| until.filter(|until| *until > now).map(|until| until - now);
+
+        let mut bans: Vec<Ban> = self
+            .ips
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(ip, entry)| {
+                active(&entry.banned_until).map(|remaining| Ban {
+                    target: BanTarget::Ip(*ip),
+                    remaining,
+                })
+            })
+            .collect();
+        bans.extend(self.users.lock().unwrap().iter().filter_map(|(username, entry)| {
+            active(&entry.banned_until).map(|remaining| Ban {
+                target: BanTarget::User(username.clone()),
+                remaining,
+            })
+        }));
+
+        bans
+    }
+
+    /// 记录失败，返回是否因此触发封禁
+    fn add_failure<K: Hash + Eq>(&self, map: &Mutex<HashMap<K, Entry>>, key: K) -> bool {
+        let now = Instant::now();
+        let mut map = map.lock().unwrap();
+        if map.len() >= PRUNE_THRESHOLD {
+            let window = self.window;
+            map.retain(|_, entry| {
+                entry.banned_until.is_some_and(|until| until > now)
+                    || entry.failures.back().is_some_and(|last| now.duration_since(*last) < window)
+            });
+        }
+
+        let entry = map.entry(key).or_default();
+        if entry.banned_until.is_some_and(|until| until > now) {
+            return false;
+        }
+
+        while entry
+            .failures
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.window)
+        {
+            entry.failures.pop_front();
+        }
+        entry.failures.push_back(now);
+
+        if entry.failures.len() >= self.max_failures {
+            entry.failures.clear();
+            entry.banned_until = Some(now + self.ban);
+            return true;
+        }
+
+        false
+    }
+}
+
+fn is_banned<K, Q>(map: &Mutex<HashMap<K, Entry>>, key: &Q) -> bool
+where
+    K: Hash + Eq + std::borrow::Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+{
+    map.lock()
+        .unwrap()
+        .get(key)
+        .and_then(|entry| entry.banned_until)
+        .is_some_and(|until| until > Instant::now())
+}
+
+/// 带失败锁定的认证后端
+///
+/// 被封禁的 IP 或用户直接拒绝，不再调用内部后端
+pub struct LockoutAuthenticator {
+    inner: Arc<dyn Authenticator>,
+    tracker: Arc<LockoutTracker>,
+}
+
+impl LockoutAuthenticator {
+    pub fn new(inner: Arc<dyn Authenticator>, tracker: Arc<LockoutTracker>) -> Self {
+        Self { inner, tracker }
+    }
+}
+
+impl Authenticator for LockoutAuthenticator {
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+        client_ip: IpAddr,
+    ) -> BoxFuture<'a, Result<Identity>> {
+        Box::pin(async move {
+            if self.tracker.is_ip_banned(client_ip) || self.tracker.is_user_banned(username) {
+                debug!("Rejected authentication for banned user {} from {}", username, client_ip);
+                return Err(ProxyError::AuthFailed);
+            }
+
+            let result = self.inner.authenticate(username, password, client_ip).await;
+            match &result {
+                Ok(_) => self.tracker.record_success(username),
+                // 后端故障不计入失败次数
+                Err(ProxyError::AuthFailed) => self.tracker.record_failure(client_ip, username),
+                Err(_) => {}
+            }
+
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(per_ip: bool, per_user: bool) -> LockoutTracker {
+        LockoutTracker::new(&LockoutConfig {
+            enabled: true,
+            max_failures: 3,
+            window_secs: 60,
+            ban_secs: 300,
+            per_ip,
+            per_user,
+        })
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ban_after_max_failures() {
+        let tracker = tracker(true, true);
+
+        tracker.record_failure(ip(1), "alice");
+        tracker.record_failure(ip(1), "alice");
+        assert!(!tracker.is_ip_banned(ip(1)));
+        assert!(!tracker.is_user_banned("alice"));
+
+        tracker.record_failure(ip(1), "alice");
+        assert!(tracker.is_ip_banned(ip(1)));
+        assert!(tracker.is_user_banned("alice"));
+        assert!(!tracker.is_ip_banned(ip(2)));
+        assert!(!tracker.is_user_banned("bob"));
+        assert_eq!(tracker.bans().len(), 2);
+
+        tokio::time::advance(Duration::from_secs(301)).await;
+        assert!(!tracker.is_ip_banned(ip(1)));
+        assert!(!tracker.is_user_banned("alice"));
+        assert!(tracker.bans().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_outside_window() {
+        let tracker = tracker(true, false);
+
+        tracker.record_failure(ip(1), "alice");
+        tracker.record_failure(ip(1), "alice");
+        tokio::time::advance(Duration::from_secs(61)).await;
+        tracker.record_failure(ip(1), "alice");
+        assert!(!tracker.is_ip_banned(ip(1)));
+
+        tracker.record_failure(ip(1), "alice");
+        tracker.record_failure(ip(1), "alice");
+        assert!(tracker.is_ip_banned(ip(1)));
+        assert_eq!(
+            tracker.bans(),
+            vec![Ban {
+                target: BanTarget::Ip(ip(1)),
+                remaining: Duration::from_secs(300),
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_success_resets_and_per_user_only() {
+        let tracker = tracker(false, true);
+
+        tracker.record_failure(ip(1), "alice");
+        tracker.record_failure(ip(2), "alice");
+        tracker.record_success("alice");
+        tracker.record_failure(ip(4), "alice");
+        assert!(!tracker.is_user_banned("alice"));
+
+        // 来自不同 IP 的失败累计到同一用户
+        tracker.record_failure(ip(5), "alice");
+        tracker.record_failure(ip(6), "alice");
+        assert!(tracker.is_user_banned("alice"));
+        assert!(!tracker.is_ip_banned(ip(6)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_success_keeps_ip_failures() {
+        let tracker = tracker(true, true);
+
+        // 用有效账号登录不能重置 IP 的失败计数
+        tracker.record_failure(ip(1), "bob");
+        tracker.record_failure(ip(1), "carol");
+        tracker.record_success("alice");
+        tracker.record_failure(ip(1), "dave");
+        assert!(tracker.is_ip_banned(ip(1)));
+        assert!(!tracker.is_user_banned("bob"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let tracker = LockoutTracker::new(&LockoutConfig {
+            max_failures: 1,
+            ..LockoutConfig::default()
+        });
+        tracker.record_failure(ip(1), "alice");
+        assert!(!tracker.is_ip_banned(ip(1)));
+        assert!(!tracker.is_user_banned("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_lockout_authenticator() {
+        use crate::auth::UserStore;
+        use crate::config::{AuthConfig, UserCredential};
+
+        let users = UserStore::new(&AuthConfig {
+            users: vec![UserCredential {
+                username: "alice".to_string(),
+                password: "secret".to_string(),
+            }],
+            ..AuthConfig::default()
+        })
+        .unwrap();
+        let tracker = Arc::new(tracker(true, true));
+        let auth = LockoutAuthenticator::new(Arc::new(users), tracker.clone());
+
+        for _ in 0..3 {
+            assert!(auth.authenticate("alice", "wrong", ip(1)).await.is_err());
+        }
+        assert!(tracker.is_ip_banned(ip(1)));
+
+        // 封禁期间正确的密码也被拒绝
+        assert!(auth.authenticate("alice", "secret", ip(2)).await.is_err());
+    }
+}
@@ -0,0 +1,41 @@
+pub mod lockout;
+pub mod password;
+pub mod users;
+pub mod webhook;
+
+pub use lockout::{BanTarget, LockoutAuthenticator, LockoutTracker};
+pub use users::UserStore;
+pub use webhook::WebhookAuthenticator;
+
+use crate::error::Result;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// 认证通过的用户身份
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub username: String,
+    /// 认证后端附带的属性
+    pub attributes: HashMap<String, String>,
+}
+
+impl Identity {
+    pub fn new(username: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            attributes: HashMap::new(),
+        }
+    }
+}
+
+/// 认证后端
+pub trait Authenticator: Send + Sync {
+    /// 校验用户名和密码，凭据错误时返回 `ProxyError::AuthFailed`
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+        client_ip: IpAddr,
+    ) -> BoxFuture<'a, Result<Identity>>;
+}
@@ -0,0 +1,260 @@
+use crate::error::{ProxyError, Result};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::Engine;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+/// 可识别的哈希前缀
+const HASH_PREFIXES: &[&str] = &["$argon2", "$2a$", "$2b$", "$2x$", "$2y$", "$1$", "$apr1$", "$5$", "$6$", "{SHA}"];
+
+/// crypt 风格的 base64 字母表
+const CRYPT_BASE64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// 密码哈希算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Argon2,
+    Bcrypt,
+    Sha512Crypt,
+}
+
+/// 判断存储的密码是否为哈希格式
+pub fn is_hashed(stored: &str) -> bool {
+    HASH_PREFIXES.iter().any(|prefix| stored.starts_with(prefix))
+}
+
+/// 检查存储的密码格式
+///
+/// 以 `$` 或 `{` 开头却不是可识别哈希的值多半是不支持的哈希格式，按明文处理会让哈希串本身成为密码，直接拒绝
+pub fn check_format(stored: &str) -> Result<()> {
+    if (stored.starts_with('$') || stored.starts_with('{')) && !is_hashed(stored) {
+        let scheme = stored[1..].split(['$', '}']).next().unwrap_or_default();
+        return Err(ProxyError::Config(format!("Unsupported password hash format: {}", scheme)));
+    }
+    Ok(())
+}
+
+/// 校验密码
+///
+/// 根据前缀识别 argon2（`$argon2`）、bcrypt（`$2a$`/`$2b$`/`$2y$`）、
+/// md5-crypt（`$1$`/`$apr1$`）、sha-crypt（`$5$`/`$6$`）和 `{SHA}` 哈希；
+/// 不以 `$`、`{` 开头的按明文处理，无法识别的哈希一律校验失败；比较均为常量时间
+pub fn verify_password(stored: &str, password: &str) -> bool {
+    if check_format(stored).is_err() {
+        return false;
+    }
+    if !is_hashed(stored) {
+        return constant_time_eq(stored, password);
+    }
+
+    if stored.starts_with("$argon2") {
+        return PasswordHash::new(stored)
+            .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok());
+    }
+
+    if stored.starts_with("$2") {
+        return bcrypt::verify(password, stored).unwrap_or(false);
+    }
+
+    let computed = if stored.starts_with("$5$") {
+        // sha256-crypt 仅用于校验已有哈希
+        #[allow(deprecated)]
+        pwhash::sha256_crypt::hash_with(stored, password).ok()
+    } else if stored.starts_with("$6$") {
+        pwhash::sha512_crypt::hash_with(stored, password).ok()
+    } else if let Some(rest) = stored.strip_prefix("$apr1$") {
+        salt_of(rest).map(|salt| apr1_crypt(salt, password))
+    } else if stored.starts_with("$1$") {
+        // md5-crypt 同样仅用于校验已有哈希
+        #[allow(deprecated)]
+        pwhash::md5_crypt::hash_with(stored, password).ok()
+    } else {
+        let digest = Sha1::digest(password.as_bytes());
+        Some(format!("{{SHA}}{}", base64::engine::general_purpose::STANDARD.encode(digest)))
+    };
+
+    computed.is_some_and(|computed| constant_time_eq(stored, &computed))
+}
+
+/// 生成密码哈希
+pub fn hash_password(password: &str, algorithm: HashAlgorithm) -> Result<String> {
+    match algorithm {
+        HashAlgorithm::Argon2 => {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|e| ProxyError::Config(format!("Failed to hash password: {}", e)))
+        }
+        HashAlgorithm::Bcrypt => bcrypt::hash(password, bcrypt::DEFAULT_COST)
+            .map_err(|e| ProxyError::Config(format!("Failed to hash password: {}", e))),
+        HashAlgorithm::Sha512Crypt => pwhash::sha512_crypt::hash(password)
+            .map_err(|e| ProxyError::Config(format!("Failed to hash password: {}", e))),
+    }
+}
+
+/// `$apr1$` 的盐：`$` 之前的部分，最多 8 个字节；截断位置落在多字节字符中间时返回 None
+fn salt_of(rest: &str) -> Option<&str> {
+    let salt = rest.split('$').next().unwrap_or_default();
+    salt.get(..salt.len().min(8))
+}
+
+/// Apache 的 md5-crypt 变体（`$apr1$`），pwhash 不支持，与 `$1$` 只有魔数不同
+fn apr1_crypt(salt: &str, password: &str) -> String {
+    const MAGIC: &str = "$apr1$";
+    let password = password.as_bytes();
+    let salt = salt.as_bytes();
+
+    let mut alternate = Md5::new();
+    alternate.update(password);
+    alternate.update(salt);
+    alternate.update(password);
+    let alternate = alternate.finalize();
+
+    let mut digest = Md5::new();
+    digest.update(password);
+    digest.update(MAGIC.as_bytes());
+    digest.update(salt);
+    for chunk in (0..password.len()).step_by(16) {
+        digest.update(&alternate[..(password.len() - chunk).min(16)]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            digest.update([0u8]);
+        } else {
+            digest.update(&password[..1]);
+        }
+        length >>= 1;
+    }
+    let mut result = digest.finalize();
+
+    for round in 0..1000 {
+        let mut digest = Md5::new();
+        if round & 1 == 1 {
+            digest.update(password);
+        } else {
+            digest.update(result);
+        }
+        if round % 3 != 0 {
+            digest.update(salt);
+        }
+        if round % 7 != 0 {
+            digest.update(password);
+        }
+        if round & 1 == 1 {
+            digest.update(result);
+        } else {
+            digest.update(password);
+        }
+        result = digest.finalize();
+    }
+
+    let mut encoded = String::with_capacity(22);
+    let mut push = |value: u32, count: usize| {
+        let mut value = value;
+        for _ in 0..count {
+            encoded.push(CRYPT_BASE64[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push((result[a] as u32) << 16 | (result[b] as u32) << 8 | result[c] as u32, 4);
+    }
+    push(result[11] as u32, 2);
+
+    format!("{}{}${}", MAGIC, String::from_utf8_lossy(salt), encoded)
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plaintext() {
+        assert!(!is_hashed("secret"));
+        assert!(verify_password("secret", "secret"));
+        assert!(!verify_password("secret", "Secret"));
+        assert!(!verify_password("secret", ""));
+    }
+
+    #[test]
+    fn test_argon2() {
+        let hash = hash_password("secret", HashAlgorithm::Argon2).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(is_hashed(&hash));
+        assert!(verify_password(&hash, "secret"));
+        assert!(!verify_password(&hash, "wrong"));
+    }
+
+    #[test]
+    fn test_bcrypt() {
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        assert!(is_hashed(&hash));
+        assert!(verify_password(&hash, "secret"));
+        assert!(!verify_password(&hash, "wrong"));
+    }
+
+    #[test]
+    fn test_sha_crypt() {
+        let hash = hash_password("secret", HashAlgorithm::Sha512Crypt).unwrap();
+        assert!(hash.starts_with("$6$"));
+        assert!(verify_password(&hash, "secret"));
+        assert!(!verify_password(&hash, "wrong"));
+
+        #[allow(deprecated)]
+        let hash = pwhash::sha256_crypt::hash("secret").unwrap();
+        assert!(hash.starts_with("$5$"));
+        assert!(verify_password(&hash, "secret"));
+        assert!(!verify_password(&hash, "wrong"));
+    }
+
+    #[test]
+    fn test_htpasswd_md5_and_sha() {
+        // htpasswd -m / openssl passwd -apr1 生成
+        let apr1 = "$apr1$5Rn0cYpE$/zWZWkJt4wJfPNvzZcXkB.";
+        assert!(is_hashed(apr1));
+        assert!(verify_password(apr1, "secret"));
+        assert!(!verify_password(apr1, "wrong"));
+        assert!(!verify_password(apr1, apr1));
+
+        let md5 = "$1$abcdefgh$cHJi5PXp/ki/ktXzqlk6I1";
+        assert!(verify_password(md5, "secret"));
+        assert!(!verify_password(md5, "wrong"));
+
+        // htpasswd -s 生成
+        let sha = "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=";
+        assert!(is_hashed(sha));
+        assert!(verify_password(sha, "secret"));
+        assert!(!verify_password(sha, "wrong"));
+        assert!(!verify_password(sha, sha));
+    }
+
+    #[test]
+    fn test_unknown_hash_format() {
+        // 无法识别的哈希格式不能按明文校验通过
+        for stored in ["$sha1$1000$salt$hash", "$y$j9T$salt$hash", "{SSHA}abcdef", "$ecret"] {
+            assert!(check_format(stored).is_err());
+            assert!(!verify_password(stored, stored));
+        }
+        assert!(check_format("secret").is_ok());
+        assert!(check_format("$apr1$5Rn0cYpE$/zWZWkJt4wJfPNvzZcXkB.").is_ok());
+    }
+
+    #[test]
+    fn test_malformed_hash() {
+        assert!(!verify_password("$argon2id$garbage", "secret"));
+        assert!(!verify_password("$2b$garbage", "secret"));
+        assert!(!verify_password("$6$", "secret"));
+        // 盐中的多字节字符跨过第 8 个字节
+        assert!(!verify_password("$apr1$abcdefg\u{e9}$/zWZWkJt4wJfPNvzZcXkB.", "secret"));
+        assert!(!verify_password("$1$\u{4e2d}\u{6587}\u{5bc6}$cHJi5PXp/ki/ktXzqlk6I1", "secret"));
+    }
+}
@@ -0,0 +1,342 @@
+use crate::auth::{password, Authenticator, Identity};
+use crate::config::{AuthConfig, UsersFileMode};
+use crate::error::{ProxyError, Result};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+
+type UserMap = HashMap<String, String>;
+
+/// 当前生效的用户表
+#[derive(Default)]
+struct UserTable {
+    users: UserMap,
+    /// 用户不存在时用于校验的哈希，使其与存在的用户耗时相近，避免通过响应时间枚举用户名
+    dummy_hash: Option<String>,
+}
+
+impl UserTable {
+    fn new(users: UserMap) -> Self {
+        // 取用户名最小的哈希密码，重新加载前保持不变
+        let dummy_hash = users
+            .iter()
+            .filter(|(_, stored)| password::is_hashed(stored))
+            .min_by_key(|(username, _)| username.as_str())
+            .map(|(_, stored)| stored.clone());
+        Self { users, dummy_hash }
+    }
+}
+
+/// 用户库
+///
+/// 合并配置中的内联用户和 htpasswd 用户文件。重新加载时整体替换用户表，
+/// 读取方总是看到完整的旧表或新表，已建立的会话不受影响
+pub struct UserStore {
+    inline: UserMap,
+    file: Option<PathBuf>,
+    mode: UsersFileMode,
+    users: RwLock<Arc<UserTable>>,
+}
+
+impl UserStore {
+    pub fn new(config: &AuthConfig) -> Result<Self> {
+        for user in &config.users {
+            password::check_format(&user.password)
+                .map_err(|e| ProxyError::Config(format!("{} for user {}", e, user.username)))?;
+        }
+        let inline: UserMap = config
+            .users
+            .iter()
+            .map(|user| (user.username.clone(), user.password.clone()))
+            .collect();
+
+        let store = Self {
+            inline,
+            file: config.users_file.clone(),
+            mode: config.users_file_mode,
+            users: RwLock::new(Arc::new(UserTable::default())),
+        };
+        store.reload()?;
+
+        Ok(store)
+    }
+
+    /// 用户文件路径
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// 当前用户数
+    pub fn len(&self) -> usize {
+        self.snapshot().users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 重新读取用户文件并替换用户表，失败时保留原有用户
+    pub fn reload(&self) -> Result<()> {
+        let users = match &self.file {
+            Some(path) => {
+                let file_users = load_users_file(path)?;
+                match self.mode {
+                    UsersFileMode::Merge => {
+                        let mut users = self.inline.clone();
+                        users.extend(file_users);
+                        users
+                    }
+                    UsersFileMode::Replace => file_users,
+                }
+            }
+            None => self.inline.clone(),
+        };
+
+        *self.users.write().unwrap() = Arc::new(UserTable::new(users));
+
+        Ok(())
+    }
+
+    /// 校验用户名和密码
+    ///
+    /// 哈希密码的校验开销较大，放到阻塞线程池中执行。用户不存在时同样校验一个哈希后返回失败
+    pub async fn verify(&self, username: &str, password: &str) -> bool {
+        let table = self.snapshot();
+        let (stored, exists) = match table.users.get(username) {
+            Some(stored) => (stored.clone(), true),
+            None => match &table.dummy_hash {
+                Some(dummy) => (dummy.clone(), false),
+                None => return false,
+            },
+        };
+
+        if !password::is_hashed(&stored) {
+            return password::verify_password(&stored, password);
+        }
+
+        let password = password.to_string();
+        let verified = tokio::task::spawn_blocking(move || password::verify_password(&stored, &password))
+            .await
+            .unwrap_or(false);
+        verified && exists
+    }
+
+    fn snapshot(&self) -> Arc<UserTable> {
+        self.users.read().unwrap().clone()
+    }
+}
+
+impl Authenticator for UserStore {
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+        _client_ip: IpAddr,
+    ) -> BoxFuture<'a, Result<Identity>> {
+        Box::pin(async move {
+            if self.verify(username, password).await {
+                Ok(Identity::new(username))
+            } else {
+                Err(ProxyError::AuthFailed)
+            }
+        })
+    }
+}
+
+/// 定期检查用户文件的修改时间，变更后重新加载
+pub async fn watch_users_file(store: Arc<UserStore>, interval: Duration) {
+    let Some(path) = store.file().map(Path::to_path_buf) else {
+        return;
+    };
+
+    let mut last_modified = modified_time(&path);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let modified = modified_time(&path);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        match store.reload() {
+            Ok(()) => info!("Reloaded users file {:?} ({} users)", path, store.len()),
+            Err(e) => warn!("Failed to reload users file {:?}, keeping previous users: {}", path, e),
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// 读取 htpasswd 格式的用户文件
+fn load_users_file(path: &Path) -> Result<UserMap> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| ProxyError::Config(format!("Failed to read users file {:?}: {}", path, e)))?;
+    let users = parse_htpasswd(&content)?;
+    debug!("Loaded {} users from {:?}", users.len(), path);
+    Ok(users)
+}
+
+/// 解析 htpasswd 内容，忽略空行和 `#` 注释
+fn parse_htpasswd(content: &str) -> Result<UserMap> {
+    let mut users = UserMap::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.split_once(':') {
+            Some((username, stored)) if !username.is_empty() => {
+                password::check_format(stored)
+                    .map_err(|e| ProxyError::Config(format!("{} on line {}", e, i + 1)))?;
+                users.insert(username.to_string(), stored.to_string());
+            }
+            _ => {
+                return Err(ProxyError::Config(format!(
+                    "Invalid users file entry on line {}",
+                    i + 1
+                )));
+            }
+        }
+    }
+
+    Ok(users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserCredential;
+
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("yun-socket-proxy-{}-{}", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn auth_config(file: Option<PathBuf>, mode: UsersFileMode) -> AuthConfig {
+        AuthConfig {
+            users: vec![
+                UserCredential {
+                    username: "alice".to_string(),
+                    password: "inline".to_string(),
+                },
+                UserCredential {
+                    username: "carol".to_string(),
+                    password: "carol".to_string(),
+                },
+            ],
+            users_file: file,
+            users_file_mode: mode,
+            ..AuthConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_htpasswd() {
+        let users = parse_htpasswd("# comment\n\nalice:$2y$05$abc\n bob:secret:with:colons \n").unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users["alice"], "$2y$05$abc");
+        assert_eq!(users["bob"], "secret:with:colons");
+
+        assert!(parse_htpasswd("alice\n").is_err());
+        assert!(parse_htpasswd(":secret\n").is_err());
+
+        // 不支持的哈希格式不能按明文加载
+        assert!(parse_htpasswd("alice:$sha1$1000$salt$hash\n").is_err());
+        assert!(parse_htpasswd("alice:{SSHA}abcdef\n").is_err());
+    }
+
+    #[test]
+    fn test_unsupported_inline_hash() {
+        let mut config = auth_config(None, UsersFileMode::Merge);
+        config.users[1].password = "$y$j9T$salt$hash".to_string();
+        let err = UserStore::new(&config).err().unwrap();
+        assert!(err.to_string().contains("carol"));
+    }
+
+    #[tokio::test]
+    async fn test_htpasswd_md5_entries() {
+        // htpasswd -m 和 htpasswd -s 生成的条目
+        let path = temp_file(
+            "users-md5",
+            "alice:$apr1$5Rn0cYpE$/zWZWkJt4wJfPNvzZcXkB.\nbob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n",
+        );
+        let store = UserStore::new(&auth_config(Some(path.clone()), UsersFileMode::Replace)).unwrap();
+
+        assert!(store.verify("alice", "secret").await);
+        assert!(!store.verify("alice", "$apr1$5Rn0cYpE$/zWZWkJt4wJfPNvzZcXkB.").await);
+        assert!(store.verify("bob", "secret").await);
+        assert!(!store.verify("bob", "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=").await);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unknown_user_checks_dummy_hash() {
+        let path = temp_file("users-dummy", "bob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\ndave:dave\n");
+        let store = UserStore::new(&auth_config(Some(path.clone()), UsersFileMode::Replace)).unwrap();
+
+        let table = store.snapshot();
+        assert_eq!(table.dummy_hash.as_deref(), Some("{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ="));
+        // 与 dummy 哈希匹配的密码也不能以不存在的用户名通过
+        assert!(!store.verify("mallory", "secret").await);
+        assert!(store.verify("bob", "secret").await);
+
+        // 没有哈希密码时不需要 dummy 哈希
+        let store = UserStore::new(&auth_config(None, UsersFileMode::Merge)).unwrap();
+        assert!(store.snapshot().dummy_hash.is_none());
+        assert!(!store.verify("mallory", "inline").await);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_merge_and_replace() {
+        let path = temp_file("users-merge", "alice:file\nbob:bob\n");
+
+        let store = UserStore::new(&auth_config(Some(path.clone()), UsersFileMode::Merge)).unwrap();
+        assert_eq!(store.len(), 3);
+        assert!(store.verify("alice", "file").await);
+        assert!(!store.verify("alice", "inline").await);
+        assert!(store.verify("carol", "carol").await);
+
+        let store = UserStore::new(&auth_config(Some(path.clone()), UsersFileMode::Replace)).unwrap();
+        assert_eq!(store.len(), 2);
+        assert!(!store.verify("carol", "carol").await);
+        assert!(store.verify("bob", "bob").await);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_previous_on_error() {
+        let path = temp_file("users-reload", "bob:old\n");
+        let store = UserStore::new(&auth_config(Some(path.clone()), UsersFileMode::Replace)).unwrap();
+        assert!(store.verify("bob", "old").await);
+
+        std::fs::write(&path, "bob:new\ndave:dave\n").unwrap();
+        store.reload().unwrap();
+        assert!(store.verify("bob", "new").await);
+        assert!(store.verify("dave", "dave").await);
+
+        std::fs::write(&path, "broken line\n").unwrap();
+        assert!(store.reload().is_err());
+        assert!(store.verify("bob", "new").await);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(store.reload().is_err());
+        assert!(UserStore::new(&auth_config(Some(path), UsersFileMode::Merge)).is_err());
+    }
+}
@@ -0,0 +1,552 @@
+use crate::auth::{Authenticator, Identity};
+use crate::config::WebhookConfig;
+use crate::error::{ProxyError, Result};
+use crate::protocol::{http, Address};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, trace, warn};
+
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+const MAX_CACHE_ENTRIES: usize = 4096;
+
+/// 缓存键：用户名、客户端 IP 和密码摘要，缓存中不保存明文密码
+type CacheKey = (String, IpAddr, [u8; 32]);
+
+struct CacheEntry {
+    /// None 表示认证被拒绝
+    identity: Option<Identity>,
+    expires_at: Instant,
+}
+
+/// 认证服务的响应体，均为可选字段
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WebhookResponse {
+    allow: Option<bool>,
+    username: Option<String>,
+    attributes: HashMap<String, serde_json::Value>,
+}
+
+/// HTTP 回调认证
+///
+/// 向认证服务 POST `{"username", "password", "client_ip"}`：
+/// 2xx 表示通过（响应体可带 `allow`、`username`、`attributes`），
+/// 401/403 表示拒绝，其他状态视为后端错误。通过和拒绝的结果都会缓存 TTL 时长。
+/// 请求体含明文密码，`http://` 只允许本机回环地址，远程认证服务须使用 `https://`
+pub struct WebhookAuthenticator {
+    address: Address,
+    path: String,
+    /// `https://` 时的 TLS 连接器
+    tls: Option<(TlsConnector, ServerName<'static>)>,
+    timeout: Duration,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<CacheKey, CacheEntry>>,
+}
+
+impl WebhookAuthenticator {
+    pub fn new(config: &WebhookConfig) -> Result<Self> {
+        let (https, address, path) = parse_url(&config.url)
+            .ok_or_else(|| ProxyError::Config(format!("Invalid webhook URL: {}", config.url)))?;
+
+        let tls = if https {
+            let server_name = match &address {
+                Address::Domain(domain, _) => ServerName::try_from(domain.clone())
+                    .map_err(|_| ProxyError::Config(format!("Invalid webhook URL: {}", config.url)))?,
+                _ => ServerName::from(address.socket_addr().unwrap().ip()),
+            };
+            Some((tls_connector(config.ca_file.as_deref())?, server_name))
+        } else if !is_loopback(&address) {
+            return Err(ProxyError::Config(format!(
+                "Plain http:// webhook URL must point to a loopback address, passwords would be sent in cleartext; use https:// instead: {}",
+                config.url
+            )));
+        } else {
+            None
+        };
+
+        Ok(Self {
+            address,
+            path,
+            tls,
+            timeout: Duration::from_secs(config.timeout_secs),
+            cache_ttl: Duration::from_secs(config.cache_ttl_secs),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn cached(&self, key: &CacheKey) -> Option<Option<Identity>> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(key)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.identity.clone())
+    }
+
+    fn store(&self, key: CacheKey, identity: Option<Identity>) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            cache.retain(|_, entry| entry.expires_at > now);
+        }
+        cache.insert(
+            key,
+            CacheEntry {
+                identity,
+                expires_at: now + self.cache_ttl,
+            },
+        );
+    }
+
+    /// 调用认证服务，返回 None 表示拒绝
+    async fn call(&self, username: &str, password: &str, client_ip: IpAddr) -> Result<Option<Identity>> {
+        let body = serde_json::json!({
+            "username": username,
+            "password": password,
+            "client_ip": client_ip.to_string(),
+        })
+        .to_string();
+
+        let (status, body) = timeout(self.timeout, self.post(&body))
+            .await
+            .map_err(|_| ProxyError::AuthBackend(format!("Request to {} timed out", self.address)))??;
+
+        match status {
+            200..=299 => {
+                let response: WebhookResponse = if body.iter().all(u8::is_ascii_whitespace) {
+                    WebhookResponse::default()
+                } else {
+                    serde_json::from_slice(&body)
+                        .map_err(|e| ProxyError::AuthBackend(format!("Invalid response body: {}", e)))?
+                };
+
+                if response.allow == Some(false) {
+                    return Ok(None);
+                }
+
+                let attributes = response
+                    .attributes
+                    .into_iter()
+                    .map(|(key, value)| match value {
+                        serde_json::Value::String(value) => (key, value),
+                        value => (key, value.to_string()),
+                    })
+                    .collect();
+
+                Ok(Some(Identity {
+                    username: response.username.unwrap_or_else(|| username.to_string()),
+                    attributes,
+                }))
+            }
+            401 | 403 => Ok(None),
+            _ => Err(ProxyError::AuthBackend(format!("{} replied HTTP {}", self.address, status))),
+        }
+    }
+
+    /// 发送 POST 请求，返回状态码和响应体
+    async fn post(&self, body: &str) -> Result<(u16, Vec<u8>)> {
+        let stream = TcpStream::connect(self.address.to_string())
+            .await
+            .map_err(|e| ProxyError::AuthBackend(format!("Failed to connect to {}: {}", self.address, e)))?;
+
+        match &self.tls {
+            Some((connector, server_name)) => {
+                let stream = connector
+                    .connect(server_name.clone(), stream)
+                    .await
+                    .map_err(|e| ProxyError::AuthBackend(format!("TLS handshake with {} failed: {}", self.address, e)))?;
+                self.exchange(stream, body).await
+            }
+            None => self.exchange(stream, body).await,
+        }
+    }
+
+    async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(&self, mut stream: S, body: &str) -> Result<(u16, Vec<u8>)> {
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.address,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        (&mut stream).take(MAX_RESPONSE_SIZE as u64 + 1).read_to_end(&mut response).await?;
+        if response.len() > MAX_RESPONSE_SIZE {
+            return Err(ProxyError::AuthBackend(format!("Response from {} too large", self.address)));
+        }
+
+        parse_response(&response)
+    }
+}
+
+impl Authenticator for WebhookAuthenticator {
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+        client_ip: IpAddr,
+    ) -> BoxFuture<'a, Result<Identity>> {
+        Box::pin(async move {
+            let key = (username.to_string(), client_ip, Sha256::digest(password.as_bytes()).into());
+
+            let identity = match self.cached(&key) {
+                Some(identity) => {
+                    trace!("Webhook auth cache hit for user: {}", username);
+                    identity
+                }
+                None => {
+                    let identity = self.call(username, password, client_ip).await.inspect_err(|e| {
+                        warn!("Webhook authentication for user {} failed: {}", username, e);
+                    })?;
+                    debug!(
+                        "Webhook verdict for user {} from {}: {}",
+                        username,
+                        client_ip,
+                        if identity.is_some() { "allow" } else { "deny" }
+                    );
+                    self.store(key, identity.clone());
+                    identity
+                }
+            };
+
+            identity.ok_or(ProxyError::AuthFailed)
+        })
+    }
+}
+
+/// 解析回调 URL，返回是否为 https、目标地址和请求路径
+fn parse_url(url: &str) -> Option<(bool, Address, String)> {
+    let (https, rest) = if url.get(..8).is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://")) {
+        (true, &url[8..])
+    } else if url.get(..7).is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://")) {
+        (false, &url[7..])
+    } else {
+        return None;
+    };
+
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(pos) if rest[pos..].starts_with('?') => (&rest[..pos], format!("/{}", &rest[pos..])),
+        Some(pos) => (&rest[..pos], rest[pos..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    let address = http::parse_authority(authority, if https { 443 } else { 80 }).ok()?;
+
+    Some((https, address, path))
+}
+
+/// 使用内置根证书和可选的额外 CA 证书创建 TLS 连接器
+fn tls_connector(ca_file: Option<&Path>) -> Result<TlsConnector> {
+    let mut roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(path) = ca_file {
+        let invalid = |e: &dyn std::fmt::Display| ProxyError::Config(format!("Invalid webhook CA file {:?}: {}", path, e));
+        let certs = CertificateDer::pem_file_iter(path)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| invalid(&e))?;
+        if certs.is_empty() {
+            return Err(invalid(&"no certificates found"));
+        }
+        for cert in certs {
+            roots.add(cert).map_err(|e| invalid(&e))?;
+        }
+    }
+
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| ProxyError::Config(format!("Failed to set up TLS: {}", e)))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// 是否为本机回环地址
+fn is_loopback(address: &Address) -> bool {
+    match address {
+        Address::Domain(domain, _) => domain.trim_end_matches('.').eq_ignore_ascii_case("localhost"),
+        _ => address.socket_addr().is_some_and(|addr| addr.ip().is_loopback()),
+    }
+}
+
+/// 解析 HTTP 响应，支持 Content-Length 和 chunked 响应体
+fn parse_response(response: &[u8]) -> Result<(u16, Vec<u8>)> {
+    let invalid = || ProxyError::AuthBackend("Invalid HTTP response".to_string());
+
+    let head_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(invalid)?;
+    let head = std::str::from_utf8(&response[..head_end]).map_err(|_| invalid())?;
+    let mut body = &response[head_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(invalid)?;
+
+    let mut chunked = false;
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = value.trim();
+        if name.trim().eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.trim().eq_ignore_ascii_case("Content-Length") {
+            let length: usize = value.parse().map_err(|_| invalid())?;
+            body = body.get(..length).ok_or_else(invalid)?;
+        }
+    }
+
+    if chunked {
+        return Ok((status, decode_chunked(body).ok_or_else(invalid)?));
+    }
+
+    Ok((status, body.to_vec()))
+}
+
+fn decode_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size_line = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// 本地认证服务：alice/secret 通过，bob/secret 由响应体拒绝，其余返回 403
+    async fn start_auth_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                answer(stream).await;
+            }
+        });
+
+        (format!("http://{}/auth", addr), requests)
+    }
+
+    /// 以 localhost 自签名证书提供 HTTPS 的认证服务，返回 URL 和证书 PEM
+    async fn start_https_auth_server() -> (String, String) {
+        use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+        use tokio_rustls::rustls::ServerConfig;
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der()));
+        let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    answer(stream).await;
+                }
+            }
+        });
+
+        (format!("https://localhost:{}/auth", port), certified.cert.pem())
+    }
+
+    /// 处理一次认证请求
+    async fn answer<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) {
+        let (head, mut body) = http::read_request_head(&mut stream).await.unwrap();
+        let length: usize = head.header("Content-Length").unwrap().parse().unwrap();
+        while body.len() < length {
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            body.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(head.method, "POST");
+        assert_eq!(head.target, "/auth");
+
+        let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(request["client_ip"], "127.0.0.1");
+        let response = match (request["username"].as_str(), request["password"].as_str()) {
+            (Some("alice"), Some("secret")) => {
+                let body = r#"{"allow":true,"username":"Alice","attributes":{"group":"staff","level":3}}"#;
+                format!(
+                    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                    body.len(),
+                    body
+                )
+            }
+            (Some("bob"), Some("secret")) => {
+                "HTTP/1.1 200 OK\r\nContent-Length: 15\r\n\r\n{\"allow\":false}".to_string()
+            }
+            (Some("error"), _) => "HTTP/1.1 500 Internal Server Error\r\n\r\n".to_string(),
+            _ => "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n".to_string(),
+        };
+        stream.write_all(response.as_bytes()).await.unwrap();
+        let _ = stream.shutdown().await;
+    }
+
+    fn authenticator(url: String, cache_ttl_secs: u64) -> WebhookAuthenticator {
+        WebhookAuthenticator::new(&WebhookConfig {
+            url,
+            ca_file: None,
+            timeout_secs: 5,
+            cache_ttl_secs,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_webhook_verdicts() {
+        let (url, _) = start_auth_server().await;
+        let auth = authenticator(url, 0);
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        let identity = auth.authenticate("alice", "secret", ip).await.unwrap();
+        assert_eq!(identity.username, "Alice");
+        assert_eq!(identity.attributes["group"], "staff");
+        assert_eq!(identity.attributes["level"], "3");
+
+        assert!(matches!(auth.authenticate("alice", "wrong", ip).await, Err(ProxyError::AuthFailed)));
+        assert!(matches!(auth.authenticate("bob", "secret", ip).await, Err(ProxyError::AuthFailed)));
+        assert!(matches!(auth.authenticate("error", "x", ip).await, Err(ProxyError::AuthBackend(_))));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_cache() {
+        let (url, requests) = start_auth_server().await;
+        let auth = authenticator(url, 60);
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        for _ in 0..3 {
+            assert!(auth.authenticate("alice", "secret", ip).await.is_ok());
+            assert!(auth.authenticate("alice", "wrong", ip).await.is_err());
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // 后端错误不缓存
+        assert!(auth.authenticate("error", "x", ip).await.is_err());
+        assert!(auth.authenticate("error", "x", ip).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/auth", listener.local_addr().unwrap());
+        drop(listener);
+
+        let auth = authenticator(url, 60);
+        let result = auth.authenticate("alice", "secret", IpAddr::from([127, 0, 0, 1])).await;
+        assert!(matches!(result, Err(ProxyError::AuthBackend(_))));
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        let new = |url: &str| {
+            WebhookAuthenticator::new(&WebhookConfig {
+                url: url.to_string(),
+                ..WebhookConfig::default()
+            })
+        };
+
+        assert!(new("ftp://auth.example.com/").is_err());
+        // 明文密码不能发往非回环地址
+        assert!(new("http://auth.example.com/auth").is_err());
+        assert!(new("http://10.0.0.5:8000/auth").is_err());
+
+        assert!(new("https://auth.example.com/auth").is_ok());
+        assert!(new("https://10.0.0.5:8443/auth").is_ok());
+        assert!(new("http://127.0.0.1:8000/auth").is_ok());
+        assert!(new("http://[::1]:8000/auth").is_ok());
+        assert!(new("http://localhost:8000/auth").is_ok());
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let (https, address, path) = parse_url("https://auth.example.com/auth?x=1").unwrap();
+        assert!(https);
+        assert_eq!(address, Address::Domain("auth.example.com".to_string(), 443));
+        assert_eq!(path, "/auth?x=1");
+
+        let (https, address, path) = parse_url("HTTP://127.0.0.1").unwrap();
+        assert!(!https);
+        assert_eq!(address.port(), 80);
+        assert_eq!(path, "/");
+    }
+
+    #[tokio::test]
+    async fn test_webhook_https() {
+        let (url, pem) = start_https_auth_server().await;
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        // 证书不在信任列表中时握手失败
+        let auth = authenticator(url.clone(), 0);
+        assert!(matches!(auth.authenticate("alice", "secret", ip).await, Err(ProxyError::AuthBackend(_))));
+
+        let ca_file = std::env::temp_dir().join(format!("yun-socket-proxy-webhook-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca_file, pem).unwrap();
+        let auth = WebhookAuthenticator::new(&WebhookConfig {
+            url,
+            ca_file: Some(ca_file.clone()),
+            timeout_secs: 5,
+            cache_ttl_secs: 0,
+        })
+        .unwrap();
+        assert_eq!(auth.authenticate("alice", "secret", ip).await.unwrap().username, "Alice");
+        assert!(matches!(auth.authenticate("alice", "wrong", ip).await, Err(ProxyError::AuthFailed)));
+
+        let _ = std::fs::remove_file(ca_file);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let (status, body) = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}trailing").unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, b"{}");
+
+        let (_, body) = parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"{}");
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_response(b"garbage\r\n\r\n").is_err());
+    }
+}
@@ -1,8 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Config {
     #[serde(default)]
     pub server: ServerConfig,
@@ -14,6 +15,24 @@ pub struct Config {
     pub logging: LoggingConfig,
     #[serde(default)]
     pub limits: LimitsConfig,
+    #[serde(default)]
+    pub bind: BindConfig,
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    #[serde(default)]
+    pub acl: AclConfig,
+    #[serde(default)]
+    pub access: AccessConfig,
+    #[serde(default)]
+    pub lockout: LockoutConfig,
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -24,8 +43,37 @@ pub struct ServerConfig {
     pub port: u16,
     #[serde(default = "default_max_connections")]
     pub max_connections: usize,
+    /// 连接目标服务器的超时时间
     #[serde(default = "default_connection_timeout")]
     pub connection_timeout_secs: u64,
+    /// 握手和认证阶段的超时时间，0 表示不限制
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout_secs: u64,
+    /// 转发时双向都没有数据的最长时间，0 表示不限制
+    #[serde(default)]
+    pub idle_timeout_secs: u64,
+    /// 会话最长持续时间，0 表示不限制
+    #[serde(default)]
+    pub max_session_secs: u64,
+    /// 并发连接数达到上限时的处理方式
+    #[serde(default)]
+    pub connection_limit_policy: ConnectionLimitPolicy,
+    /// wait 策略下排队等待的最长时间，超时后按 reject 处理
+    #[serde(default = "default_connection_queue_timeout")]
+    pub connection_queue_timeout_ms: u64,
+}
+
+/// 并发连接数达到上限时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionLimitPolicy {
+    /// 按协议返回失败响应（SOCKS GeneralFailure / HTTP 503）后关闭
+    #[default]
+    Reject,
+    /// 排队等待空闲许可
+    Wait,
+    /// 直接关闭连接
+    Close,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -36,11 +84,65 @@ pub struct AuthConfig {
     pub methods: Vec<String>,
     #[serde(default)]
     pub users: Vec<UserCredential>,
+    /// htpasswd 格式的用户文件（每行 `用户名:密码或哈希`）
+    #[serde(default)]
+    pub users_file: Option<PathBuf>,
+    /// 用户文件与 users 的合并方式
+    #[serde(default)]
+    pub users_file_mode: UsersFileMode,
+    /// 检查用户文件变更的间隔，0 表示不自动重新加载
+    #[serde(default = "default_users_file_reload")]
+    pub users_file_reload_secs: u64,
+    /// 认证后端
+    #[serde(default)]
+    pub backend: AuthBackend,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+}
+
+/// 认证后端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthBackend {
+    /// 使用 users 和 users_file 中的用户
+    #[default]
+    Local,
+    /// 调用外部 HTTP 认证服务
+    Webhook,
+}
+
+/// HTTP 回调认证配置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebhookConfig {
+    /// 认证服务地址，支持 https://；http:// 只能指向本机回环地址
+    #[serde(default)]
+    pub url: String,
+    /// 额外信任的 CA 证书文件（PEM），用于内部 CA 签发证书的认证服务
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    /// 请求超时时间
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout_secs: u64,
+    /// 认证结果缓存时间，0 表示不缓存
+    #[serde(default = "default_webhook_cache_ttl")]
+    pub cache_ttl_secs: u64,
+}
+
+/// 用户文件与内联用户的合并方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsersFileMode {
+    /// 与 users 合并，同名用户以文件为准
+    #[default]
+    Merge,
+    /// 只使用文件中的用户
+    Replace,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UserCredential {
     pub username: String,
+    /// 明文密码或 argon2/bcrypt/sha-crypt 哈希
     pub password: String,
 }
 
@@ -60,16 +162,326 @@ pub struct PerformanceConfig {
 pub struct LoggingConfig {
     #[serde(default = "default_log_level")]
     pub level: String,
-    #[serde(default = "default_log_format")]
-    pub format: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// 是否在会话结束时输出访问日志（target 为 access）
+    #[serde(default = "default_true")]
+    pub access_log: bool,
+    /// 访问日志文件输出，与诊断日志分开配置
+    #[serde(default)]
+    pub access: AccessLogConfig,
+}
+
+/// 访问日志文件配置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AccessLogConfig {
+    /// 访问日志文件路径，为空时访问日志随诊断日志输出到标准输出
+    #[serde(default)]
+    pub path: String,
+    /// 访问日志格式
+    #[serde(default = "default_access_log_format")]
+    pub format: LogFormat,
+    /// 轮转方式
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// size 轮转时单个文件的最大大小（MB）
+    #[serde(default = "default_access_log_max_size")]
+    pub max_size_mb: u64,
+    /// 保留的已轮转文件数，0 表示轮转时直接删除
+    #[serde(default = "default_access_log_max_files")]
+    pub max_files: usize,
+    /// 是否用 gzip 压缩已轮转的文件
+    #[serde(default)]
+    pub compress: bool,
+}
+
+/// 日志文件轮转方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// 按天（UTC）轮转
+    #[default]
+    Daily,
+    /// 按文件大小轮转
+    Size,
+    /// 不轮转
+    Never,
+}
+
+/// 日志输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// 人类可读的文本格式
+    #[default]
+    Pretty,
+    /// 每行一个 JSON 对象
+    Json,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LimitsConfig {
+    /// 每个客户端 IP 的最大并发连接数，0 表示不限制
+    #[serde(default)]
+    pub max_connections_per_ip: usize,
+    /// 每个认证用户的最大并发连接数，0 表示不限制
+    #[serde(default)]
+    pub max_connections_per_user: usize,
+    /// 每个目标主机的最大并发连接数，0 表示不限制
+    #[serde(default)]
+    pub max_connections_per_destination: usize,
+    /// 全局每秒最大新连接数，0 表示不限制
     #[serde(default = "default_max_connections_per_sec")]
     pub max_connections_per_sec: u32,
+    /// 每个客户端 IP 每秒最大新连接数，0 表示不限制
+    #[serde(default)]
+    pub max_connections_per_sec_per_ip: u32,
+    /// 新连接的突发上限，0 表示与每秒速率相同
+    #[serde(default)]
+    pub connection_burst: u32,
+    /// 超出速率的新连接的处理方式
+    #[serde(default)]
+    pub rate_limit_policy: RateLimitPolicy,
+    /// delay 策略下的最长等待时间，超过则丢弃
+    #[serde(default = "default_max_accept_delay")]
+    pub max_accept_delay_ms: u64,
+    /// 单个连接的带宽上限（字节/秒），0 表示不限制
     #[serde(default)]
     pub max_bandwidth_per_connection: u64,
+    /// 单个连接的上行（客户端 -> 目标）带宽上限，0 表示使用 max_bandwidth_per_connection
+    #[serde(default)]
+    pub max_upload_per_connection: u64,
+    /// 单个连接的下行（目标 -> 客户端）带宽上限，0 表示使用 max_bandwidth_per_connection
+    #[serde(default)]
+    pub max_download_per_connection: u64,
+    /// 同一用户所有连接共享的带宽上限，0 表示不限制
+    #[serde(default)]
+    pub max_bandwidth_per_user: u64,
+    #[serde(default)]
+    pub max_upload_per_user: u64,
+    #[serde(default)]
+    pub max_download_per_user: u64,
+    /// 全部连接共享的带宽上限，0 表示不限制
+    #[serde(default)]
+    pub max_bandwidth_total: u64,
+    #[serde(default)]
+    pub max_upload_total: u64,
+    #[serde(default)]
+    pub max_download_total: u64,
+    /// 带宽突发上限（字节），0 表示一秒的流量
+    #[serde(default)]
+    pub bandwidth_burst: u64,
+}
+
+/// 超出连接速率时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitPolicy {
+    /// 直接关闭连接
+    #[default]
+    Drop,
+    /// 延迟处理，直到有可用令牌
+    Delay,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BindConfig {
+    /// BIND 监听地址，为空时使用客户端连入的本地地址
+    #[serde(default)]
+    pub listen_address: String,
+    /// BIND 监听端口范围，均为 0 时由系统分配
+    #[serde(default)]
+    pub port_range_start: u16,
+    #[serde(default)]
+    pub port_range_end: u16,
+    /// 等待对端连入的超时时间
+    #[serde(default = "default_bind_accept_timeout")]
+    pub accept_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UpstreamConfig {
+    /// 上游代理链，按顺序逐跳连接，为空表示直连
+    #[serde(default)]
+    pub chain: Vec<UpstreamProxy>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpstreamProxy {
+    #[serde(rename = "type")]
+    pub proxy_type: UpstreamType,
+    pub address: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamType {
+    Socks5,
+    Http,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RoutingConfig {
+    /// 无规则匹配时的动作，未设置时有上游代理链则走上游，否则直连
+    #[serde(default)]
+    pub default_action: Option<RouteAction>,
+    /// 路由规则，按顺序匹配，第一条命中的规则生效
+    #[serde(default)]
+    pub rules: Vec<RouteRule>,
+}
+
+/// 路由规则
+///
+/// 目标条件（domain_suffix、domain_regex、cidr）之间为“或”，
+/// 与 ports、users 之间为“与”；未设置的条件视为匹配任意值
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RouteRule {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub domain_suffix: Vec<String>,
+    #[serde(default)]
+    pub domain_regex: Vec<String>,
+    #[serde(default)]
+    pub cidr: Vec<String>,
+    #[serde(default)]
+    pub ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub users: Vec<String>,
+    pub action: RouteAction,
+}
+
+/// 端口或端口范围，如 `443` 或 `"8000-9000"`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PortSpec {
+    Single(u16),
+    Range(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteAction {
+    Direct,
+    Upstream,
+    Reject,
+}
+
+/// 目标地址访问控制，在 DNS 解析之后检查
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AclConfig {
+    /// 拒绝访问回环、链路本地、内网等非公网地址
+    #[serde(default = "default_true")]
+    pub block_private: bool,
+    /// 允许访问的目标 CIDR，优先于 deny 和 block_private
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// 拒绝访问的目标 CIDR
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// 允许访问的目标端口，为空表示不限制
+    #[serde(default)]
+    pub allow_ports: Vec<PortSpec>,
+    /// 拒绝访问的目标端口
+    #[serde(default)]
+    pub deny_ports: Vec<PortSpec>,
+}
+
+/// 客户端来源 IP 访问控制，在接受连接后立即检查
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AccessConfig {
+    /// 允许连接的客户端 CIDR，为空表示不限制
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// 拒绝连接的客户端 CIDR，优先于 allow
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// 认证失败锁定
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LockoutConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 时间窗口内允许的最大失败次数，达到后封禁
+    #[serde(default = "default_lockout_max_failures")]
+    pub max_failures: u32,
+    /// 统计失败次数的时间窗口
+    #[serde(default = "default_lockout_window")]
+    pub window_secs: u64,
+    /// 封禁时长
+    #[serde(default = "default_lockout_ban")]
+    pub ban_secs: u64,
+    /// 按客户端 IP 封禁，被封禁的 IP 在接受连接时即被拒绝
+    #[serde(default = "default_true")]
+    pub per_ip: bool,
+    /// 按用户名封禁
+    #[serde(default = "default_true")]
+    pub per_user: bool,
+}
+
+/// 按用户统计的流量配额（上下行合计，按 UTC 自然日/自然月重置）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuotaConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 每个用户每日流量上限（字节），0 表示不限制
+    #[serde(default)]
+    pub daily_bytes: u64,
+    /// 每个用户每月流量上限（字节），0 表示不限制
+    #[serde(default)]
+    pub monthly_bytes: u64,
+    /// 按用户名覆盖默认配额
+    #[serde(default)]
+    pub users: HashMap<String, UserQuota>,
+    /// 用量持久化文件
+    #[serde(default = "default_quota_state_file")]
+    pub state_file: PathBuf,
+    /// 用量写入文件的间隔
+    #[serde(default = "default_quota_flush_interval")]
+    pub flush_interval_secs: u64,
+    /// 配额用尽时中断正在进行的连接
+    #[serde(default)]
+    pub cut_live_sessions: bool,
+}
+
+/// Prometheus 指标
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 指标 HTTP 监听地址
+    #[serde(default = "default_metrics_listen")]
+    pub listen: String,
+    /// 指标路径
+    #[serde(default = "default_metrics_path")]
+    pub path: String,
+}
+
+/// 管理接口
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 管理 HTTP 监听地址
+    #[serde(default = "default_admin_listen")]
+    pub listen: String,
+    /// 访问令牌，请求须携带 `Authorization: Bearer <token>`
+    #[serde(default)]
+    pub token: String,
+}
+
+/// 单个用户的配额，未设置的项使用默认值
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UserQuota {
+    #[serde(default)]
+    pub daily_bytes: Option<u64>,
+    #[serde(default)]
+    pub monthly_bytes: Option<u64>,
 }
 
 // Default values
@@ -89,6 +501,14 @@ fn default_connection_timeout() -> u64 {
     300
 }
 
+fn default_handshake_timeout() -> u64 {
+    10
+}
+
+fn default_connection_queue_timeout() -> u64 {
+    1000
+}
+
 fn default_auth_methods() -> Vec<String> {
     vec!["none".to_string()]
 }
@@ -105,24 +525,72 @@ fn default_log_level() -> String {
     "info".to_string()
 }
 
-fn default_log_format() -> String {
-    "pretty".to_string()
+fn default_access_log_format() -> LogFormat {
+    LogFormat::Json
+}
+
+fn default_access_log_max_size() -> u64 {
+    100
+}
+
+fn default_access_log_max_files() -> usize {
+    7
 }
 
 fn default_max_connections_per_sec() -> u32 {
     100
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            server: ServerConfig::default(),
-            auth: AuthConfig::default(),
-            performance: PerformanceConfig::default(),
-            logging: LoggingConfig::default(),
-            limits: LimitsConfig::default(),
-        }
-    }
+fn default_users_file_reload() -> u64 {
+    5
+}
+
+fn default_webhook_timeout() -> u64 {
+    5
+}
+
+fn default_webhook_cache_ttl() -> u64 {
+    60
+}
+
+fn default_lockout_max_failures() -> u32 {
+    5
+}
+
+fn default_lockout_window() -> u64 {
+    300
+}
+
+fn default_lockout_ban() -> u64 {
+    900
+}
+
+fn default_max_accept_delay() -> u64 {
+    1000
+}
+
+fn default_quota_state_file() -> PathBuf {
+    PathBuf::from("quota.json")
+}
+
+fn default_quota_flush_interval() -> u64 {
+    30
+}
+
+fn default_metrics_listen() -> String {
+    "127.0.0.1:9100".to_string()
+}
+
+fn default_metrics_path() -> String {
+    "/metrics".to_string()
+}
+
+fn default_admin_listen() -> String {
+    "127.0.0.1:9101".to_string()
+}
+
+fn default_bind_accept_timeout() -> u64 {
+    60
 }
 
 impl Default for ServerConfig {
@@ -132,6 +600,11 @@ impl Default for ServerConfig {
             port: default_port(),
             max_connections: default_max_connections(),
             connection_timeout_secs: default_connection_timeout(),
+            handshake_timeout_secs: default_handshake_timeout(),
+            idle_timeout_secs: 0,
+            max_session_secs: 0,
+            connection_limit_policy: ConnectionLimitPolicy::default(),
+            connection_queue_timeout_ms: default_connection_queue_timeout(),
         }
     }
 }
@@ -142,6 +615,22 @@ impl Default for AuthConfig {
             enabled: false,
             methods: default_auth_methods(),
             users: Vec::new(),
+            users_file: None,
+            users_file_mode: UsersFileMode::default(),
+            users_file_reload_secs: default_users_file_reload(),
+            backend: AuthBackend::default(),
+            webhook: WebhookConfig::default(),
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            ca_file: None,
+            timeout_secs: default_webhook_timeout(),
+            cache_ttl_secs: default_webhook_cache_ttl(),
         }
     }
 }
@@ -161,7 +650,22 @@ impl Default for LoggingConfig {
     fn default() -> Self {
         Self {
             level: default_log_level(),
-            format: default_log_format(),
+            format: LogFormat::default(),
+            access_log: true,
+            access: AccessLogConfig::default(),
+        }
+    }
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            format: default_access_log_format(),
+            rotation: LogRotation::default(),
+            max_size_mb: default_access_log_max_size(),
+            max_files: default_access_log_max_files(),
+            compress: false,
         }
     }
 }
@@ -169,8 +673,94 @@ impl Default for LoggingConfig {
 impl Default for LimitsConfig {
     fn default() -> Self {
         Self {
+            max_connections_per_ip: 0,
+            max_connections_per_user: 0,
+            max_connections_per_destination: 0,
             max_connections_per_sec: default_max_connections_per_sec(),
+            max_connections_per_sec_per_ip: 0,
+            connection_burst: 0,
+            rate_limit_policy: RateLimitPolicy::default(),
+            max_accept_delay_ms: default_max_accept_delay(),
             max_bandwidth_per_connection: 0,
+            max_upload_per_connection: 0,
+            max_download_per_connection: 0,
+            max_bandwidth_per_user: 0,
+            max_upload_per_user: 0,
+            max_download_per_user: 0,
+            max_bandwidth_total: 0,
+            max_upload_total: 0,
+            max_download_total: 0,
+            bandwidth_burst: 0,
+        }
+    }
+}
+
+impl Default for BindConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: String::new(),
+            port_range_start: 0,
+            port_range_end: 0,
+            accept_timeout_secs: default_bind_accept_timeout(),
+        }
+    }
+}
+
+impl Default for AclConfig {
+    fn default() -> Self {
+        Self {
+            block_private: default_true(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            allow_ports: Vec::new(),
+            deny_ports: Vec::new(),
+        }
+    }
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_failures: default_lockout_max_failures(),
+            window_secs: default_lockout_window(),
+            ban_secs: default_lockout_ban(),
+            per_ip: default_true(),
+            per_user: default_true(),
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_metrics_listen(),
+            path: default_metrics_path(),
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_admin_listen(),
+            token: String::new(),
+        }
+    }
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            daily_bytes: 0,
+            monthly_bytes: 0,
+            users: HashMap::new(),
+            state_file: default_quota_state_file(),
+            flush_interval_secs: default_quota_flush_interval(),
+            cut_live_sessions: false,
         }
     }
 }
@@ -236,6 +826,40 @@ mod tests {
 
         assert_eq!(config.users.len(), 1);
         assert_eq!(config.users[0].username, "admin");
+        assert!(config.users_file.is_none());
+        assert_eq!(config.users_file_mode, UsersFileMode::Merge);
+        assert_eq!(config.users_file_reload_secs, 5);
+
+        let config: Config = toml::from_str(
+            r#"
+            [auth]
+            enabled = true
+            users_file = "/etc/yun-socket-proxy/htpasswd"
+            users_file_mode = "replace"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.auth.users_file, Some(PathBuf::from("/etc/yun-socket-proxy/htpasswd")));
+        assert_eq!(config.auth.users_file_mode, UsersFileMode::Replace);
+        assert_eq!(config.auth.backend, AuthBackend::Local);
+
+        let config: Config = toml::from_str(
+            r#"
+            [auth]
+            enabled = true
+            backend = "webhook"
+
+            [auth.webhook]
+            url = "https://auth.example.com/auth"
+            ca_file = "/etc/yun-socket-proxy/auth-ca.pem"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.auth.backend, AuthBackend::Webhook);
+        assert_eq!(config.auth.webhook.url, "https://auth.example.com/auth");
+        assert_eq!(config.auth.webhook.ca_file, Some(PathBuf::from("/etc/yun-socket-proxy/auth-ca.pem")));
+        assert_eq!(config.auth.webhook.timeout_secs, 5);
+        assert_eq!(config.auth.webhook.cache_ttl_secs, 60);
     }
 
     #[test]
@@ -253,13 +877,56 @@ mod tests {
         assert_eq!(config.port, 1080);
         assert_eq!(config.max_connections, 10000);
         assert_eq!(config.connection_timeout_secs, 300);
+        assert_eq!(config.handshake_timeout_secs, 10);
+        assert_eq!(config.idle_timeout_secs, 0);
+        assert_eq!(config.max_session_secs, 0);
+        assert_eq!(config.connection_limit_policy, ConnectionLimitPolicy::Reject);
+        assert_eq!(config.connection_queue_timeout_ms, 1000);
+
+        let config: Config = toml::from_str("[server]\nconnection_limit_policy = \"wait\"\n").unwrap();
+        assert_eq!(config.server.connection_limit_policy, ConnectionLimitPolicy::Wait);
     }
 
     #[test]
     fn test_logging_config_defaults() {
         let config = LoggingConfig::default();
         assert_eq!(config.level, "info");
-        assert_eq!(config.format, "pretty");
+        assert_eq!(config.format, LogFormat::Pretty);
+        assert!(config.access_log);
+
+        let config: Config = toml::from_str("[logging]\nlevel = \"debug\"\nformat = \"json\"\naccess_log = false\n").unwrap();
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert!(!config.logging.access_log);
+
+        assert!(toml::from_str::<Config>("[logging]\nformat = \"xml\"\n").is_err());
+    }
+
+    #[test]
+    fn test_access_log_config() {
+        let config = AccessLogConfig::default();
+        assert!(config.path.is_empty());
+        assert_eq!(config.format, LogFormat::Json);
+        assert_eq!(config.rotation, LogRotation::Daily);
+        assert_eq!(config.max_size_mb, 100);
+        assert_eq!(config.max_files, 7);
+        assert!(!config.compress);
+
+        let toml_str = r#"
+            [logging.access]
+            path = "/var/log/yun-socket-proxy/access.log"
+            rotation = "size"
+            max_size_mb = 10
+            max_files = 3
+            compress = true
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let access = &config.logging.access;
+        assert_eq!(access.path, "/var/log/yun-socket-proxy/access.log");
+        assert_eq!(access.rotation, LogRotation::Size);
+        assert_eq!(access.max_size_mb, 10);
+        assert_eq!(access.max_files, 3);
+        assert!(access.compress);
     }
 
     #[test]
@@ -267,5 +934,187 @@ mod tests {
         let config = LimitsConfig::default();
         assert_eq!(config.max_connections_per_sec, 100);
         assert_eq!(config.max_bandwidth_per_connection, 0);
+        assert_eq!(config.max_connections_per_sec_per_ip, 0);
+        assert_eq!(config.rate_limit_policy, RateLimitPolicy::Drop);
+        assert_eq!(config.max_accept_delay_ms, 1000);
+        assert_eq!(config.max_connections_per_ip, 0);
+
+        let config: Config = toml::from_str(
+            "[limits]\nmax_connections_per_sec_per_ip = 10\nrate_limit_policy = \"delay\"\nmax_connections_per_user = 50\n",
+        )
+        .unwrap();
+        assert_eq!(config.limits.max_connections_per_user, 50);
+        assert_eq!(config.limits.max_connections_per_sec, 100);
+        assert_eq!(config.limits.max_connections_per_sec_per_ip, 10);
+        assert_eq!(config.limits.rate_limit_policy, RateLimitPolicy::Delay);
+    }
+
+    #[test]
+    fn test_bind_config() {
+        let config = BindConfig::default();
+        assert!(config.listen_address.is_empty());
+        assert_eq!(config.port_range_start, 0);
+        assert_eq!(config.accept_timeout_secs, 60);
+
+        let config: Config = toml::from_str(
+            "[bind]\nlisten_address = \"10.0.0.1\"\nport_range_start = 40000\nport_range_end = 40100\n",
+        )
+        .unwrap();
+        assert_eq!(config.bind.listen_address, "10.0.0.1");
+        assert_eq!(config.bind.port_range_end, 40100);
+        assert_eq!(config.bind.accept_timeout_secs, 60);
+    }
+
+    #[test]
+    fn test_upstream_config() {
+        assert!(UpstreamConfig::default().chain.is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+            [upstream]
+            chain = [
+                { type = "socks5", address = "10.0.0.1:1080", username = "u", password = "p" },
+                { type = "http", address = "proxy.corp:3128" },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.upstream.chain.len(), 2);
+        assert_eq!(config.upstream.chain[0].proxy_type, UpstreamType::Socks5);
+        assert_eq!(config.upstream.chain[0].username, "u");
+        assert_eq!(config.upstream.chain[1].proxy_type, UpstreamType::Http);
+        assert!(config.upstream.chain[1].password.is_empty());
+
+        let toml_str = toml::to_string(&config).unwrap();
+        let deserialized: Config = toml::from_str(&toml_str).unwrap();
+        assert_eq!(deserialized.upstream.chain.len(), 2);
+    }
+
+    #[test]
+    fn test_routing_config() {
+        let config = RoutingConfig::default();
+        assert!(config.default_action.is_none());
+        assert!(config.rules.is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+            [routing]
+            default_action = "direct"
+
+            [[routing.rules]]
+            name = "block-smtp"
+            ports = [25, "465-587"]
+            action = "reject"
+
+            [[routing.rules]]
+            domain_suffix = ["corp.example.com"]
+            users = ["alice"]
+            action = "upstream"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.routing.default_action, Some(RouteAction::Direct));
+        assert_eq!(config.routing.rules.len(), 2);
+        assert_eq!(
+            config.routing.rules[0].ports,
+            vec![PortSpec::Single(25), PortSpec::Range("465-587".to_string())]
+        );
+        assert_eq!(config.routing.rules[1].action, RouteAction::Upstream);
+        assert!(config.routing.rules[1].name.is_empty());
+    }
+
+    #[test]
+    fn test_acl_config() {
+        let config = AclConfig::default();
+        assert!(config.block_private);
+        assert!(config.allow.is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+            [acl]
+            allow = ["10.0.0.5/32"]
+            deny_ports = [25]
+            "#,
+        )
+        .unwrap();
+        assert!(config.acl.block_private);
+        assert_eq!(config.acl.allow, vec!["10.0.0.5/32"]);
+        assert_eq!(config.acl.deny_ports, vec![PortSpec::Single(25)]);
+    }
+
+    #[test]
+    fn test_lockout_config() {
+        let config = LockoutConfig::default();
+        assert!(!config.enabled);
+        assert_eq!(config.max_failures, 5);
+        assert_eq!(config.window_secs, 300);
+        assert_eq!(config.ban_secs, 900);
+        assert!(config.per_ip && config.per_user);
+
+        let config: Config = toml::from_str(
+            r#"
+            [lockout]
+            enabled = true
+            max_failures = 3
+            per_user = false
+            "#,
+        )
+        .unwrap();
+        assert!(config.lockout.enabled);
+        assert_eq!(config.lockout.max_failures, 3);
+        assert!(config.lockout.per_ip);
+        assert!(!config.lockout.per_user);
+    }
+
+    #[test]
+    fn test_quota_config() {
+        let config = QuotaConfig::default();
+        assert!(!config.enabled);
+        assert_eq!(config.state_file, PathBuf::from("quota.json"));
+        assert_eq!(config.flush_interval_secs, 30);
+
+        let config: Config = toml::from_str(
+            r#"
+            [quota]
+            enabled = true
+            daily_bytes = 1000
+            cut_live_sessions = true
+
+            [quota.users.alice]
+            monthly_bytes = 50000
+            "#,
+        )
+        .unwrap();
+        assert!(config.quota.enabled);
+        assert_eq!(config.quota.daily_bytes, 1000);
+        assert!(config.quota.cut_live_sessions);
+        assert_eq!(config.quota.users["alice"].monthly_bytes, Some(50000));
+        assert_eq!(config.quota.users["alice"].daily_bytes, None);
+    }
+
+    #[test]
+    fn test_metrics_config() {
+        let config = MetricsConfig::default();
+        assert!(!config.enabled);
+        assert_eq!(config.listen, "127.0.0.1:9100");
+        assert_eq!(config.path, "/metrics");
+
+        let config: Config = toml::from_str("[metrics]\nenabled = true\nlisten = \"0.0.0.0:9200\"\n").unwrap();
+        assert!(config.metrics.enabled);
+        assert_eq!(config.metrics.listen, "0.0.0.0:9200");
+        assert_eq!(config.metrics.path, "/metrics");
+    }
+
+    #[test]
+    fn test_admin_config() {
+        let config = AdminConfig::default();
+        assert!(!config.enabled);
+        assert_eq!(config.listen, "127.0.0.1:9101");
+        assert!(config.token.is_empty());
+
+        let config: Config = toml::from_str("[admin]\nenabled = true\ntoken = \"secret\"\n").unwrap();
+        assert!(config.admin.enabled);
+        assert_eq!(config.admin.listen, "127.0.0.1:9101");
+        assert_eq!(config.admin.token, "secret");
     }
 }
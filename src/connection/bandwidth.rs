@@ -0,0 +1,297 @@
+use crate::config::LimitsConfig;
+use crate::connection::rate_limit::TokenBucket;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 超过该数量时清理已无连接使用的用户带宽池
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// 带宽限制（字节/秒，0 表示不限制）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BandwidthLimit {
+    /// 客户端 -> 目标
+    pub upload: u64,
+    /// 目标 -> 客户端
+    pub download: u64,
+    /// 突发上限（字节），0 表示一秒的流量
+    pub burst: u64,
+}
+
+impl BandwidthLimit {
+    /// 未单独设置上下行时使用 `both`
+    pub fn new(both: u64, upload: u64, download: u64, burst: u64) -> Self {
+        let or_both = |limit: u64| if limit > 0 { limit } else { both };
+
+        Self {
+            upload: or_both(upload),
+            download: or_both(download),
+            burst,
+        }
+    }
+
+    /// 单个连接的带宽限制
+    pub fn per_connection(limits: &LimitsConfig) -> Self {
+        Self::new(
+            limits.max_bandwidth_per_connection,
+            limits.max_upload_per_connection,
+            limits.max_download_per_connection,
+            limits.bandwidth_burst,
+        )
+    }
+
+    /// 同一用户所有连接共享的带宽限制
+    pub fn per_user(limits: &LimitsConfig) -> Self {
+        Self::new(
+            limits.max_bandwidth_per_user,
+            limits.max_upload_per_user,
+            limits.max_download_per_user,
+            limits.bandwidth_burst,
+        )
+    }
+
+    /// 全部连接共享的带宽限制
+    pub fn total(limits: &LimitsConfig) -> Self {
+        Self::new(
+            limits.max_bandwidth_total,
+            limits.max_upload_total,
+            limits.max_download_total,
+            limits.bandwidth_burst,
+        )
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.upload == 0 && self.download == 0
+    }
+
+    fn bucket(&self, rate: u64) -> Option<Mutex<TokenBucket>> {
+        if rate == 0 {
+            return None;
+        }
+
+        let burst = if self.burst > 0 { self.burst } else { rate };
+        Some(Mutex::new(TokenBucket::new(rate as f64, burst as f64)))
+    }
+}
+
+/// 转发方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// 客户端 -> 目标
+    Upload,
+    /// 目标 -> 客户端
+    Download,
+}
+
+/// 带宽池，上下行各一个令牌桶，可由多个连接共享
+pub struct BandwidthPool {
+    upload: Option<Mutex<TokenBucket>>,
+    download: Option<Mutex<TokenBucket>>,
+}
+
+impl BandwidthPool {
+    pub fn new(limit: &BandwidthLimit) -> Self {
+        Self {
+            upload: limit.bucket(limit.upload),
+            download: limit.bucket(limit.download),
+        }
+    }
+
+    /// 预留 `bytes` 字节的额度，返回需要等待的时间
+    fn reserve(&self, direction: Direction, bytes: usize) -> Duration {
+        let bucket = match direction {
+            Direction::Upload => &self.upload,
+            Direction::Download => &self.download,
+        };
+
+        match bucket {
+            Some(bucket) => {
+                let mut bucket = bucket.lock().unwrap();
+                let wait = bucket.wait_time(bytes as f64);
+                bucket.consume(bytes as f64);
+                wait
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// 单个连接的限速设置，依次受连接、用户和全局带宽池限制
+///
+/// 每次只为一个读缓冲区预留额度，共享同一带宽池的连接按预留顺序轮流发送，
+/// 从而大致平分带宽
+#[derive(Clone, Default)]
+pub struct Shaping {
+    pools: Vec<Arc<BandwidthPool>>,
+}
+
+impl Shaping {
+    pub fn is_unlimited(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    /// 预留 `bytes` 字节的额度，返回需要等待的时间
+    pub fn reserve(&self, direction: Direction, bytes: usize) -> Duration {
+        self.pools
+            .iter()
+            .map(|pool| pool.reserve(direction, bytes))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// 带宽管理
+///
+/// 为每个连接组合单连接限制、按用户名共享的带宽池和全局带宽池
+pub struct BandwidthManager {
+    per_connection: BandwidthLimit,
+    per_user: BandwidthLimit,
+    total: Option<Arc<BandwidthPool>>,
+    users: Mutex<HashMap<String, Arc<BandwidthPool>>>,
+}
+
+impl BandwidthManager {
+    pub fn new(limits: &LimitsConfig) -> Self {
+        let total = BandwidthLimit::total(limits);
+
+        Self {
+            per_connection: BandwidthLimit::per_connection(limits),
+            per_user: BandwidthLimit::per_user(limits),
+            total: (!total.is_unlimited()).then(|| Arc::new(BandwidthPool::new(&total))),
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 为新连接生成限速设置，`user` 为认证得到的用户名
+    pub fn shaping(&self, user: Option<&str>) -> Shaping {
+        let mut pools = Vec::new();
+
+        if !self.per_connection.is_unlimited() {
+            pools.push(Arc::new(BandwidthPool::new(&self.per_connection)));
+        }
+        if let Some(user) = user.filter(|_| !self.per_user.is_unlimited()) {
+            pools.push(self.user_pool(user));
+        }
+        if let Some(total) = &self.total {
+            pools.push(total.clone());
+        }
+
+        Shaping { pools }
+    }
+
+    fn user_pool(&self, user: &str) -> Arc<BandwidthPool> {
+        let mut users = self.users.lock().unwrap();
+        if let Some(pool) = users.get(user) {
+            return pool.clone();
+        }
+
+        if users.len() >= PRUNE_THRESHOLD {
+            users.retain(|_, pool| Arc::strong_count(pool) > 1);
+        }
+        let pool = Arc::new(BandwidthPool::new(&self.per_user));
+        users.insert(user.to_string(), pool.clone());
+        pool
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_from_config() {
+        let limits = LimitsConfig {
+            max_bandwidth_per_connection: 1000,
+            max_download_per_connection: 5000,
+            max_upload_per_user: 300,
+            max_bandwidth_total: 9000,
+            ..LimitsConfig::default()
+        };
+        assert_eq!(
+            BandwidthLimit::per_connection(&limits),
+            BandwidthLimit {
+                upload: 1000,
+                download: 5000,
+                burst: 0,
+            }
+        );
+        assert_eq!(BandwidthLimit::per_user(&limits).upload, 300);
+        assert_eq!(BandwidthLimit::per_user(&limits).download, 0);
+        assert_eq!(BandwidthLimit::total(&limits).download, 9000);
+        assert!(BandwidthLimit::per_connection(&LimitsConfig::default()).is_unlimited());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shaping_layers() {
+        let manager = BandwidthManager::new(&LimitsConfig {
+            max_bandwidth_per_connection: 1000,
+            max_bandwidth_per_user: 500,
+            ..LimitsConfig::default()
+        });
+
+        assert!(BandwidthManager::new(&LimitsConfig::default()).shaping(Some("alice")).is_unlimited());
+
+        // 未认证连接只受单连接限制
+        let anonymous = manager.shaping(None);
+        assert_eq!(anonymous.reserve(Direction::Upload, 1000), Duration::ZERO);
+        assert_eq!(anonymous.reserve(Direction::Upload, 1000), Duration::from_secs(1));
+
+        // 用户限制更严格时以用户为准
+        let alice = manager.shaping(Some("alice"));
+        assert_eq!(alice.reserve(Direction::Download, 500), Duration::ZERO);
+        assert_eq!(alice.reserve(Direction::Download, 500), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_user_pool_shared() {
+        let manager = BandwidthManager::new(&LimitsConfig {
+            max_bandwidth_per_user: 1000,
+            ..LimitsConfig::default()
+        });
+
+        let first = manager.shaping(Some("alice"));
+        let second = manager.shaping(Some("alice"));
+        let other = manager.shaping(Some("bob"));
+
+        assert_eq!(first.reserve(Direction::Upload, 1000), Duration::ZERO);
+        // 同一用户的第二个连接共享额度
+        assert_eq!(second.reserve(Direction::Upload, 1000), Duration::from_secs(1));
+        assert_eq!(other.reserve(Direction::Upload, 1000), Duration::ZERO);
+        // 上下行额度互相独立
+        assert_eq!(second.reserve(Direction::Download, 1000), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_total_pool_shared() {
+        let manager = BandwidthManager::new(&LimitsConfig {
+            max_download_total: 2000,
+            ..LimitsConfig::default()
+        });
+
+        let first = manager.shaping(None);
+        let second = manager.shaping(Some("bob"));
+        assert_eq!(first.reserve(Direction::Download, 2000), Duration::ZERO);
+        assert_eq!(second.reserve(Direction::Download, 1000), Duration::from_millis(500));
+        assert_eq!(second.reserve(Direction::Upload, 1_000_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_user_pools_pruned() {
+        let manager = BandwidthManager::new(&LimitsConfig {
+            max_bandwidth_per_user: 1000,
+            ..LimitsConfig::default()
+        });
+
+        let active = manager.shaping(Some("active"));
+        for i in 0..PRUNE_THRESHOLD - 1 {
+            drop(manager.shaping(Some(&format!("user{}", i))));
+        }
+        assert_eq!(manager.users.lock().unwrap().len(), PRUNE_THRESHOLD);
+
+        drop(manager.shaping(Some("new")));
+        let users = manager.users.lock().unwrap();
+        assert_eq!(users.len(), 2);
+        assert!(users.contains_key("active"));
+        drop(active);
+    }
+}
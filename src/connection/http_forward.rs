@@ -0,0 +1,360 @@
+use crate::error::{ProxyError, Result};
+use crate::protocol::http::HttpRequestHead;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const MAX_RESPONSE_HEAD_SIZE: usize = 16 * 1024;
+const HEAD_TERMINATOR: &[u8] = b"\r\n\r\n";
+const HOP_BY_HOP_HEADERS: &[&str] = &["Connection", "Keep-Alive", "Proxy-Connection"];
+
+/// 普通 HTTP 转发的单次请求
+///
+/// 包装客户端连接：读方向只放行第一个请求的请求体，之后视为 EOF，
+/// 同一连接上流水线发送的后续请求不会被原样发往第一个目标；
+/// 写方向把响应头改写为 `Connection: close`，客户端不会在这条连接上复用
+pub struct SingleExchange<S> {
+    inner: S,
+    body: RequestBody,
+    /// 尚未处理完最终响应头时缓存已写入的响应数据
+    response_head: Option<Vec<u8>>,
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+/// 请求体的分帧状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestBody {
+    Length(u64),
+    /// 读取块大小
+    ChunkSize(u64),
+    /// 块大小之后的扩展，忽略到行尾
+    ChunkExt(u64),
+    ChunkData(u64),
+    /// 块数据之后的 CRLF
+    ChunkEnd,
+    /// 结束块之后的 trailer，`true` 表示当前行为空
+    Trailer(bool),
+    Done,
+}
+
+impl RequestBody {
+    /// 按请求头确定请求体的长度或分块编码，Content-Length 不合法时返回错误
+    pub fn from_head(head: &HttpRequestHead) -> Result<Self> {
+        let chunked = head
+            .header("Transfer-Encoding")
+            .is_some_and(|value| value.rsplit(',').next().unwrap_or_default().trim().eq_ignore_ascii_case("chunked"));
+        let body = if chunked {
+            RequestBody::ChunkSize(0)
+        } else {
+            match head.header("Content-Length") {
+                Some(length) => {
+                    let length = length
+                        .parse::<u64>()
+                        .map_err(|_| ProxyError::Protocol(format!("Invalid Content-Length: {}", length)))?;
+                    RequestBody::Length(length)
+                }
+                None => RequestBody::Length(0),
+            }
+        };
+        Ok(body.normalized())
+    }
+}
+
+impl<S> SingleExchange<S> {
+    pub fn new(inner: S, body: RequestBody) -> Self {
+        Self {
+            inner,
+            body,
+            response_head: Some(Vec::new()),
+            pending: Vec::new(),
+            pending_pos: 0,
+        }
+    }
+
+    /// 从读请求头时多读入的数据中取出属于请求体的部分
+    pub fn body_prefix<'a>(&mut self, data: &'a [u8]) -> &'a [u8] {
+        let accepted = self.body.consume(data);
+        &data[..accepted]
+    }
+}
+
+impl RequestBody {
+    fn normalized(self) -> Self {
+        match self {
+            RequestBody::Length(0) => RequestBody::Done,
+            body => body,
+        }
+    }
+
+    /// 按分帧状态消费数据，返回属于请求体的字节数
+    fn consume(&mut self, data: &[u8]) -> usize {
+        let mut i = 0;
+        while i < data.len() {
+            match *self {
+                RequestBody::Done => break,
+                RequestBody::Length(remaining) => {
+                    let n = remaining.min((data.len() - i) as u64);
+                    i += n as usize;
+                    *self = RequestBody::Length(remaining - n).normalized();
+                    continue;
+                }
+                RequestBody::ChunkData(remaining) => {
+                    let n = remaining.min((data.len() - i) as u64);
+                    i += n as usize;
+                    *self = if n == remaining { RequestBody::ChunkEnd } else { RequestBody::ChunkData(remaining - n) };
+                    continue;
+                }
+                RequestBody::ChunkSize(size) | RequestBody::ChunkExt(size) => {
+                    // 非法字符交给目标校验，这里只需要找到块的边界
+                    *self = match (data[i], *self) {
+                        (b'\n', _) if size == 0 => RequestBody::Trailer(true),
+                        (b'\n', _) => RequestBody::ChunkData(size),
+                        (byte, RequestBody::ChunkSize(_)) if byte.is_ascii_hexdigit() => {
+                            let digit = (byte as char).to_digit(16).unwrap() as u64;
+                            RequestBody::ChunkSize(size.saturating_mul(16).saturating_add(digit))
+                        }
+                        (b'\r', state) => state,
+                        _ => RequestBody::ChunkExt(size),
+                    };
+                }
+                RequestBody::ChunkEnd => {
+                    if data[i] == b'\n' {
+                        *self = RequestBody::ChunkSize(0);
+                    }
+                }
+                RequestBody::Trailer(empty) => {
+                    *self = match data[i] {
+                        b'\n' if empty => RequestBody::Done,
+                        b'\n' => RequestBody::Trailer(true),
+                        b'\r' => RequestBody::Trailer(empty),
+                        _ => RequestBody::Trailer(false),
+                    };
+                }
+            }
+            i += 1;
+        }
+        i
+    }
+}
+
+/// 改写响应头：去掉逐跳头并加上 `Connection: close`；不是 UTF-8 时返回 None
+fn rewrite_response_head(head: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(head).ok()?;
+    let mut lines = text.trim_end_matches("\r\n").split("\r\n");
+    let mut rewritten = format!("{}\r\n", lines.next()?);
+    for line in lines {
+        let name = line.split(':').next().unwrap_or_default().trim();
+        if HOP_BY_HOP_HEADERS.iter().any(|hop| name.eq_ignore_ascii_case(hop)) {
+            continue;
+        }
+        rewritten.push_str(line);
+        rewritten.push_str("\r\n");
+    }
+    rewritten.push_str("Connection: close\r\n\r\n");
+    Some(rewritten.into_bytes())
+}
+
+/// 是否为 1xx 临时响应，之后还有最终响应头
+fn is_interim(head: &[u8]) -> bool {
+    head.get(9) == Some(&b'1') && head.get(9..12) != Some(b"101")
+}
+
+impl<S: AsyncWrite + Unpin> SingleExchange<S> {
+    /// 把改写后的响应头写出
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pending_pos < self.pending.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.pending_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending_pos += n;
+        }
+        self.pending.clear();
+        self.pending_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// 缓存响应数据直到读到最终响应头，改写后放入待写出缓冲
+    fn buffer_response(&mut self, data: &[u8]) {
+        let Some(buf) = self.response_head.as_mut() else {
+            return;
+        };
+        buf.extend_from_slice(data);
+
+        loop {
+            let buf = self.response_head.as_mut().unwrap();
+            let Some(pos) = buf.windows(HEAD_TERMINATOR.len()).position(|window| window == HEAD_TERMINATOR) else {
+                if buf.len() > MAX_RESPONSE_HEAD_SIZE {
+                    // 不是正常的响应头，不再改写
+                    self.pending.append(buf);
+                    self.response_head = None;
+                }
+                return;
+            };
+
+            let end = pos + HEAD_TERMINATOR.len();
+            let rest = buf.split_off(end);
+            let head = std::mem::replace(buf, rest);
+            if is_interim(&head) {
+                self.pending.extend_from_slice(&head);
+                continue;
+            }
+
+            let head = rewrite_response_head(&head).unwrap_or(head);
+            self.pending.extend_from_slice(&head);
+            let rest = self.response_head.take().unwrap();
+            self.pending.extend_from_slice(&rest);
+            return;
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for SingleExchange<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.body == RequestBody::Done {
+            return Poll::Ready(Ok(()));
+        }
+
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let accepted = self.body.consume(&buf.filled()[before..]);
+        buf.set_filled(before + accepted);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SingleExchange<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(self.poll_drain(cx))?;
+        if self.response_head.is_none() {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        }
+
+        self.buffer_response(buf);
+        // 尽量立即写出；写不完的部分在下次写入、flush 或关闭时继续写
+        if let Poll::Ready(Err(e)) = self.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // 目标在响应头之前关闭时，把已缓存的数据原样写出
+        if let Some(mut buf) = self.response_head.take() {
+            self.pending.append(&mut buf);
+        }
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// 请求体发送完后不关闭目标连接的写端
+///
+/// 部分服务器把半关闭视为客户端断开而放弃请求，目标在响应后会按 `Connection: close` 自行关闭连接
+pub struct KeepWriteOpen<S>(pub S);
+
+impl<S: AsyncRead + Unpin> AsyncRead for KeepWriteOpen<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for KeepWriteOpen<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn head(headers: &[(&str, &str)]) -> HttpRequestHead {
+        HttpRequestHead {
+            method: "POST".to_string(),
+            target: "http://example.com/".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+        }
+    }
+
+    fn exchange_for(headers: &[(&str, &str)]) -> SingleExchange<()> {
+        SingleExchange::new((), RequestBody::from_head(&head(headers)).unwrap())
+    }
+
+    #[test]
+    fn test_body_framing() {
+        let mut body = RequestBody::Length(5);
+        assert_eq!(body.consume(b"helloGET /next"), 5);
+        assert_eq!(body, RequestBody::Done);
+
+        let mut body = RequestBody::ChunkSize(0);
+        let chunked = b"5;ext=a\r\nhello\r\n1A\r\nabcdefghijklmnopqrstuvwxyz\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let mut data = chunked.to_vec();
+        data.extend_from_slice(b"GET /next HTTP/1.1\r\n\r\n");
+        assert_eq!(body.consume(&data), chunked.len());
+        assert_eq!(body, RequestBody::Done);
+
+        // 分段到达
+        let mut body = RequestBody::ChunkSize(0);
+        let consumed: usize = chunked.chunks(3).map(|part| body.consume(part)).sum();
+        assert_eq!(consumed, chunked.len());
+        assert_eq!(body, RequestBody::Done);
+    }
+
+    #[test]
+    fn test_body_prefix() {
+        let mut exchange = exchange_for(&[]);
+        assert!(exchange.body_prefix(b"GET http://other/ HTTP/1.1\r\n\r\n").is_empty());
+
+        let mut exchange = exchange_for(&[("Content-Length", "3")]);
+        assert_eq!(exchange.body_prefix(b"abcGET"), b"abc");
+
+        let mut exchange = exchange_for(&[("Transfer-Encoding", "gzip, chunked")]);
+        assert_eq!(exchange.body_prefix(b"0\r\n\r\nGET"), b"0\r\n\r\n");
+
+        assert!(RequestBody::from_head(&head(&[("Content-Length", "x")])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_single_exchange() {
+        let (client, mut proxy_side) = tokio::io::duplex(1024);
+        let mut exchange = SingleExchange::new(client, RequestBody::Length(4));
+
+        // 请求体之后的流水线请求不会被读出
+        proxy_side.write_all(b"bodyGET http://other/ HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut request = Vec::new();
+        exchange.read_to_end(&mut request).await.unwrap();
+        assert_eq!(request, b"body");
+
+        exchange
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nConnection: keep")
+            .await
+            .unwrap();
+        exchange.write_all(b"-alive\r\nKeep-Alive: timeout=5\r\nContent-Length: 2\r\n\r\nok").await.unwrap();
+        exchange.shutdown().await.unwrap();
+
+        let mut response = String::new();
+        proxy_side.read_to_string(&mut response).await.unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok"
+        );
+    }
+}
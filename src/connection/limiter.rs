@@ -1,5 +1,7 @@
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 /// 连接限制器
@@ -24,17 +26,25 @@ impl ConnectionLimiter {
     /// 获取连接许可
     pub async fn acquire(&self) -> Option<ConnectionGuard> {
         match self.semaphore.clone().acquire_owned().await {
-            Ok(permit) => {
-                self.active_connections.fetch_add(1, Ordering::Relaxed);
-                Some(ConnectionGuard {
-                    _permit: permit,
-                    active_connections: self.active_connections.clone(),
-                })
-            }
+            Ok(permit) => Some(self.guard(permit)),
             Err(_) => None,
         }
     }
 
+    /// 尝试获取连接许可，已达上限时立即返回 None
+    pub fn try_acquire(&self) -> Option<ConnectionGuard> {
+        let permit = self.semaphore.clone().try_acquire_owned().ok()?;
+        Some(self.guard(permit))
+    }
+
+    fn guard(&self, permit: OwnedSemaphorePermit) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            _permit: permit,
+            active_connections: self.active_connections.clone(),
+        }
+    }
+
     /// 获取当前活跃连接数
     pub fn active_count(&self) -> usize {
         self.active_connections.load(Ordering::Relaxed)
@@ -60,6 +70,73 @@ impl Drop for ConnectionGuard {
     }
 }
 
+type KeyedCounts<K> = Arc<Mutex<HashMap<K, usize>>>;
+
+/// 按键限制并发连接数
+///
+/// 用于单客户端 IP、单用户和单目标主机的连接数限制；`max` 为 0 时不限制
+pub struct KeyedLimiter<K> {
+    max: usize,
+    counts: KeyedCounts<K>,
+}
+
+impl<K: Hash + Eq + Clone> KeyedLimiter<K> {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            counts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 尝试为 `key` 获取许可，已达上限时返回 None
+    pub fn try_acquire(&self, key: K) -> Option<KeyedGuard<K>> {
+        if self.max == 0 {
+            return Some(KeyedGuard { inner: None });
+        }
+
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(key.clone()).or_insert(0);
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+
+        Some(KeyedGuard {
+            inner: Some((self.counts.clone(), key)),
+        })
+    }
+
+    /// `key` 当前的连接数
+    pub fn count(&self, key: &K) -> usize {
+        self.counts.lock().unwrap().get(key).copied().unwrap_or(0)
+    }
+
+    pub fn max_count(&self) -> usize {
+        self.max
+    }
+}
+
+/// 按键连接守卫
+///
+/// 丢弃时减少计数，计数归零后移除该键
+pub struct KeyedGuard<K: Hash + Eq> {
+    inner: Option<(KeyedCounts<K>, K)>,
+}
+
+impl<K: Hash + Eq> Drop for KeyedGuard<K> {
+    fn drop(&mut self) {
+        if let Some((counts, key)) = self.inner.take() {
+            let mut counts = counts.lock().unwrap();
+            if let Some(count) = counts.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(&key);
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -115,6 +192,22 @@ mod tests {
         assert!(result.is_err()); // Should timeout
     }
 
+    #[tokio::test]
+    async fn test_connection_limiter_try_acquire() {
+        let limiter = ConnectionLimiter::new(1);
+
+        let guard = limiter.try_acquire();
+        assert!(guard.is_some());
+        assert_eq!(limiter.active_count(), 1);
+
+        // 已满时立即返回
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.active_count(), 1);
+
+        drop(guard);
+        assert!(limiter.try_acquire().is_some());
+    }
+
     #[tokio::test]
     async fn test_connection_limiter_clone() {
         let limiter = ConnectionLimiter::new(5);
@@ -123,4 +216,36 @@ mod tests {
         let _guard = limiter.acquire().await.unwrap();
         assert_eq!(limiter_clone.active_count(), 1);
     }
+
+    #[test]
+    fn test_keyed_limiter() {
+        let limiter = KeyedLimiter::new(2);
+
+        let first = limiter.try_acquire("alice").unwrap();
+        let _second = limiter.try_acquire("alice").unwrap();
+        assert!(limiter.try_acquire("alice").is_none());
+        assert_eq!(limiter.count(&"alice"), 2);
+
+        // 其他键不受影响
+        assert!(limiter.try_acquire("bob").is_some());
+
+        drop(first);
+        assert_eq!(limiter.count(&"alice"), 1);
+        assert!(limiter.try_acquire("alice").is_some());
+    }
+
+    #[test]
+    fn test_keyed_limiter_removes_idle_keys() {
+        let limiter = KeyedLimiter::new(1);
+        drop(limiter.try_acquire("alice"));
+        assert!(limiter.counts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_keyed_limiter_unlimited() {
+        let limiter = KeyedLimiter::new(0);
+        let guards: Vec<_> = (0..100).map(|_| limiter.try_acquire("alice").unwrap()).collect();
+        assert_eq!(guards.len(), 100);
+        assert_eq!(limiter.count(&"alice"), 0);
+    }
 }
@@ -1,5 +1,12 @@
+pub mod bandwidth;
+pub mod http_forward;
 pub mod relay;
 pub mod limiter;
+pub mod rate_limit;
+pub mod udp;
+pub mod upstream;
 
-pub use relay::bidirectional_copy;
-pub use limiter::ConnectionLimiter;
+pub use bandwidth::BandwidthManager;
+pub use relay::{bidirectional_copy, CloseReason, RelayOptions, RelayStats, TrafficMeter};
+pub use limiter::{ConnectionLimiter, KeyedLimiter};
+pub use rate_limit::AcceptRateLimiter;
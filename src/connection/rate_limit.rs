@@ -0,0 +1,254 @@
+use crate::config::{LimitsConfig, RateLimitPolicy};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// 超过该数量时清理已回满的单 IP 令牌桶
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// 令牌桶
+///
+/// 以 `rate` 个/秒的速度补充令牌，最多积累 `burst` 个；
+/// 令牌数可以为负，表示已预支、需要等待补充的量
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// 取得 `amount` 个令牌前需要等待的时间，不消耗令牌
+    pub fn wait_time(&mut self, amount: f64) -> Duration {
+        self.refill();
+
+        let deficit = amount - self.tokens;
+        if deficit <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(deficit / self.rate)
+        }
+    }
+
+    /// 消耗 `amount` 个令牌，不足时预支
+    pub fn consume(&mut self, amount: f64) {
+        self.refill();
+        self.tokens -= amount;
+    }
+
+    /// 令牌是否已回满
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.burst
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_refill = now;
+    }
+}
+
+/// 新连接速率限制
+///
+/// 全局和每个客户端 IP 各有一个令牌桶，两者都有令牌时才接受连接
+pub struct AcceptRateLimiter {
+    global: Option<Mutex<TokenBucket>>,
+    per_ip_rate: u32,
+    per_ip_burst: u32,
+    per_ip: Mutex<HashMap<IpAddr, TokenBucket>>,
+    max_delay: Duration,
+    dropped: AtomicU64,
+}
+
+impl AcceptRateLimiter {
+    pub fn new(config: &LimitsConfig) -> Self {
+        let burst = |rate: u32| if config.connection_burst > 0 { config.connection_burst } else { rate };
+
+        let max_delay = match config.rate_limit_policy {
+            RateLimitPolicy::Drop => Duration::ZERO,
+            RateLimitPolicy::Delay => Duration::from_millis(config.max_accept_delay_ms),
+        };
+
+        let global_rate = config.max_connections_per_sec;
+        let per_ip_rate = config.max_connections_per_sec_per_ip;
+
+        Self {
+            global: (global_rate > 0)
+                .then(|| Mutex::new(TokenBucket::new(global_rate as f64, burst(global_rate) as f64))),
+            per_ip_rate,
+            per_ip_burst: burst(per_ip_rate),
+            per_ip: Mutex::new(HashMap::new()),
+            max_delay,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// 为新连接申请令牌
+    ///
+    /// 返回处理该连接前需要等待的时间；返回 None 表示应丢弃该连接
+    pub fn check(&self, ip: IpAddr) -> Option<Duration> {
+        let mut global = self.global.as_ref().map(|bucket| bucket.lock().unwrap());
+        let mut per_ip = self.per_ip.lock().unwrap();
+
+        if self.per_ip_rate > 0 && per_ip.len() >= PRUNE_THRESHOLD {
+            per_ip.retain(|_, bucket| !bucket.is_full());
+        }
+        let mut ip_bucket = (self.per_ip_rate > 0).then(|| {
+            per_ip
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::new(self.per_ip_rate as f64, self.per_ip_burst as f64))
+        });
+
+        let wait = global
+            .as_mut()
+            .map(|bucket| bucket.wait_time(1.0))
+            .unwrap_or_default()
+            .max(ip_bucket.as_mut().map(|bucket| bucket.wait_time(1.0)).unwrap_or_default());
+
+        if wait > self.max_delay {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        if let Some(bucket) = global.as_mut() {
+            bucket.consume(1.0);
+        }
+        if let Some(bucket) = ip_bucket.as_mut() {
+            bucket.consume(1.0);
+        }
+
+        Some(wait)
+    }
+
+    /// 因超出速率被丢弃的连接数
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(global: u32, per_ip: u32, policy: RateLimitPolicy) -> LimitsConfig {
+        LimitsConfig {
+            max_connections_per_sec: global,
+            max_connections_per_sec_per_ip: per_ip,
+            rate_limit_policy: policy,
+            ..LimitsConfig::default()
+        }
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(10.0, 2.0);
+        assert_eq!(bucket.wait_time(1.0), Duration::ZERO);
+        bucket.consume(1.0);
+        bucket.consume(1.0);
+        assert_eq!(bucket.wait_time(1.0), Duration::from_millis(100));
+
+        // 预支后需要等待更久
+        bucket.consume(1.0);
+        assert_eq!(bucket.wait_time(1.0), Duration::from_millis(200));
+
+        tokio::time::advance(Duration::from_millis(200)).await;
+        assert_eq!(bucket.wait_time(1.0), Duration::ZERO);
+        assert!(!bucket.is_full());
+
+        // 令牌不超过突发上限
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(bucket.is_full());
+        bucket.consume(2.0);
+        assert!(bucket.wait_time(1.0) > Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_drop() {
+        let limiter = AcceptRateLimiter::new(&limits(5, 0, RateLimitPolicy::Drop));
+
+        for i in 0..5 {
+            assert_eq!(limiter.check(ip(i)), Some(Duration::ZERO));
+        }
+        assert_eq!(limiter.check(ip(10)), None);
+        assert_eq!(limiter.dropped_count(), 1);
+
+        tokio::time::advance(Duration::from_millis(200)).await;
+        assert_eq!(limiter.check(ip(10)), Some(Duration::ZERO));
+        assert_eq!(limiter.check(ip(10)), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_ip_drop() {
+        let limiter = AcceptRateLimiter::new(&limits(0, 2, RateLimitPolicy::Drop));
+
+        assert!(limiter.check(ip(1)).is_some());
+        assert!(limiter.check(ip(1)).is_some());
+        assert!(limiter.check(ip(1)).is_none());
+
+        // 其他 IP 不受影响
+        assert!(limiter.check(ip(2)).is_some());
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(limiter.check(ip(1)).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_ip_does_not_consume_global() {
+        let limiter = AcceptRateLimiter::new(&limits(3, 1, RateLimitPolicy::Drop));
+
+        assert!(limiter.check(ip(1)).is_some());
+        // 被单 IP 限制丢弃的连接不消耗全局令牌
+        assert!(limiter.check(ip(1)).is_none());
+        assert!(limiter.check(ip(1)).is_none());
+        assert!(limiter.check(ip(2)).is_some());
+        assert!(limiter.check(ip(3)).is_some());
+        assert!(limiter.check(ip(4)).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_policy() {
+        let limiter = AcceptRateLimiter::new(&LimitsConfig {
+            max_accept_delay_ms: 300,
+            ..limits(10, 0, RateLimitPolicy::Delay)
+        });
+
+        for _ in 0..10 {
+            assert_eq!(limiter.check(ip(1)), Some(Duration::ZERO));
+        }
+        assert_eq!(limiter.check(ip(1)), Some(Duration::from_millis(100)));
+        assert_eq!(limiter.check(ip(1)), Some(Duration::from_millis(200)));
+        assert_eq!(limiter.check(ip(1)), Some(Duration::from_millis(300)));
+        // 超过最长等待时间后丢弃
+        assert_eq!(limiter.check(ip(1)), None);
+
+        tokio::time::advance(Duration::from_millis(300)).await;
+        assert_eq!(limiter.check(ip(1)), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = AcceptRateLimiter::new(&limits(0, 0, RateLimitPolicy::Drop));
+        for _ in 0..1000 {
+            assert_eq!(limiter.check(ip(1)), Some(Duration::ZERO));
+        }
+    }
+}
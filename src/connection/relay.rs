@@ -1,25 +1,612 @@
-use crate::error::Result;
-use tokio::io;
-use tokio::net::TcpStream;
-use tracing::{debug, trace};
+use crate::connection::bandwidth::{Direction, Shaping};
+use crate::error::ProxyError;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::{timeout, timeout_at, Instant};
+use tracing::{debug, info, trace};
+
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// 转发流量计量
+pub trait TrafficMeter: Send + Sync {
+    /// 每转发一段数据后调用，返回 false 时中断转发
+    fn record(&self, direction: Direction, bytes: u64) -> bool;
+}
+
+/// 把转发的字节数记入所有计量，任一返回 false 时返回 false
+pub fn record_traffic(meters: &[Arc<dyn TrafficMeter>], direction: Direction, bytes: u64) -> bool {
+    // 每个计量都要记录，不能短路
+    let mut allowed = true;
+    for meter in meters {
+        allowed &= meter.record(direction, bytes);
+    }
+    allowed
+}
+
+/// 转发选项
+#[derive(Clone, Default)]
+pub struct RelayOptions {
+    pub shaping: Shaping,
+    /// 流量计量，任一返回 false 时中断转发
+    pub meters: Vec<Arc<dyn TrafficMeter>>,
+    /// 双向都没有数据的最长时间
+    pub idle_timeout: Option<Duration>,
+    /// 会话最长持续时间
+    pub max_lifetime: Option<Duration>,
+}
+
+impl RelayOptions {
+    /// 是否可以直接使用 `copy_bidirectional`
+    fn is_passthrough(&self) -> bool {
+        self.shaping.is_unlimited()
+            && self.meters.is_empty()
+            && self.idle_timeout.is_none()
+            && self.max_lifetime.is_none()
+    }
+}
+
+/// 转发结束原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// 双方正常关闭
+    Eof,
+    /// 双向空闲超时，记录空闲更久的方向
+    IdleTimeout(Direction),
+    /// 超过会话最长持续时间
+    LifetimeExceeded,
+    /// 流量配额用尽
+    QuotaExhausted,
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            CloseReason::Eof => "closed",
+            CloseReason::IdleTimeout(Direction::Upload) => "upload idle timeout",
+            CloseReason::IdleTimeout(Direction::Download) => "download idle timeout",
+            CloseReason::LifetimeExceeded => "session lifetime exceeded",
+            CloseReason::QuotaExhausted => "quota exhausted",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// 转发结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayStats {
+    pub client_to_target: u64,
+    pub target_to_client: u64,
+    pub reason: CloseReason,
+}
+
+/// 转发出错，附带出错前已转发的字节数
+#[derive(Debug)]
+pub struct RelayError {
+    pub client_to_target: u64,
+    pub target_to_client: u64,
+    pub error: ProxyError,
+}
+
+impl From<RelayError> for ProxyError {
+    fn from(e: RelayError) -> Self {
+        e.error
+    }
+}
+
+/// 提前结束单向转发的原因
+enum Stop {
+    Io(io::Error),
+    Closed(CloseReason),
+}
+
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Self {
+        Stop::Io(e)
+    }
+}
+
+/// 两个方向各自的最近活动时间
+struct Activity {
+    start: Instant,
+    upload_ms: AtomicU64,
+    download_ms: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            upload_ms: AtomicU64::new(0),
+            download_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn slot(&self, direction: Direction) -> &AtomicU64 {
+        match direction {
+            Direction::Upload => &self.upload_ms,
+            Direction::Download => &self.download_ms,
+        }
+    }
+
+    fn touch(&self, direction: Direction) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.slot(direction).fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    /// 任一方向最近一次有数据的时间
+    fn last(&self) -> Instant {
+        let last_ms = self.upload_ms.load(Ordering::Relaxed).max(self.download_ms.load(Ordering::Relaxed));
+        self.start + Duration::from_millis(last_ms)
+    }
+
+    /// 空闲更久的方向
+    fn quieter(&self) -> Direction {
+        if self.download_ms.load(Ordering::Relaxed) < self.upload_ms.load(Ordering::Relaxed) {
+            Direction::Download
+        } else {
+            Direction::Upload
+        }
+    }
+}
 
 /// 双向数据转发
 ///
-/// 使用 tokio 的零拷贝实现在客户端和目标服务器之间转发数据
-pub async fn bidirectional_copy(
-    mut client: TcpStream,
-    mut target: TcpStream,
-) -> Result<(u64, u64)> {
+/// 不限速、不计量且没有超时限制时使用 tokio 的 `copy_bidirectional`，否则逐段转发。
+/// 出错时同样返回已转发的字节数
+pub async fn bidirectional_copy<A, B>(
+    client: A,
+    target: B,
+    options: &RelayOptions,
+) -> std::result::Result<RelayStats, RelayError>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
     trace!("Starting bidirectional data relay");
 
-    // 使用 tokio 的零拷贝双向转发
-    let (client_to_target, target_to_client) =
-        io::copy_bidirectional(&mut client, &mut target).await?;
+    let stats = if options.is_passthrough() {
+        let mut client = CountWrites::new(client);
+        let mut target = CountWrites::new(target);
+        match io::copy_bidirectional(&mut client, &mut target).await {
+            Ok((client_to_target, target_to_client)) => RelayStats {
+                client_to_target,
+                target_to_client,
+                reason: CloseReason::Eof,
+            },
+            Err(e) => return Err(relay_error(target.written, client.written, e)),
+        }
+    } else {
+        let (mut client_reader, mut client_writer) = io::split(client);
+        let (mut target_reader, mut target_writer) = io::split(target);
+        let (upload, download) = (AtomicU64::new(0), AtomicU64::new(0));
+        let activity = Activity::new();
+
+        let relay = async {
+            let result = tokio::try_join!(
+                copy_limited(&mut client_reader, &mut target_writer, options, Direction::Upload, &upload, &activity),
+                copy_limited(&mut target_reader, &mut client_writer, options, Direction::Download, &download, &activity),
+            );
+            match result {
+                Ok(_) => Ok(CloseReason::Eof),
+                Err(Stop::Closed(reason)) => Ok(reason),
+                Err(Stop::Io(e)) => Err(e),
+            }
+        };
+
+        let result = match options.max_lifetime {
+            Some(lifetime) => timeout(lifetime, relay).await.unwrap_or(Ok(CloseReason::LifetimeExceeded)),
+            None => relay.await,
+        };
 
+        let (client_to_target, target_to_client) = (upload.load(Ordering::Relaxed), download.load(Ordering::Relaxed));
+        match result {
+            Ok(reason) => RelayStats {
+                client_to_target,
+                target_to_client,
+                reason,
+            },
+            Err(e) => return Err(relay_error(client_to_target, target_to_client, e)),
+        }
+    };
+
+    if stats.reason == CloseReason::Eof {
+        debug!(
+            "Connection closed - Client->Target: {} bytes, Target->Client: {} bytes",
+            stats.client_to_target, stats.target_to_client
+        );
+    } else {
+        info!(
+            "Connection closed ({}) - Client->Target: {} bytes, Target->Client: {} bytes",
+            stats.reason, stats.client_to_target, stats.target_to_client
+        );
+    }
+
+    Ok(stats)
+}
+
+fn relay_error(client_to_target: u64, target_to_client: u64, error: io::Error) -> RelayError {
     debug!(
-        "Connection closed - Client->Target: {} bytes, Target->Client: {} bytes",
-        client_to_target, target_to_client
+        "Relay failed ({}) - Client->Target: {} bytes, Target->Client: {} bytes",
+        error, client_to_target, target_to_client
     );
+    RelayError {
+        client_to_target,
+        target_to_client,
+        error: error.into(),
+    }
+}
+
+/// 统计写入字节数的流包装，`copy_bidirectional` 出错时用于取得已转发的字节数
+struct CountWrites<S> {
+    inner: S,
+    written: u64,
+}
+
+impl<S> CountWrites<S> {
+    fn new(inner: S) -> Self {
+        Self { inner, written: 0 }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountWrites<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountWrites<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.written += n as u64;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// 单向限速、计量转发，读到 EOF 后关闭写端
+async fn copy_limited<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    options: &RelayOptions,
+    direction: Direction,
+    total: &AtomicU64,
+    activity: &Activity,
+) -> std::result::Result<(), Stop>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+
+    loop {
+        let n = match options.idle_timeout {
+            Some(idle) => read_until_idle(reader, &mut buf, idle, activity).await?,
+            None => reader.read(&mut buf).await?,
+        };
+        if n == 0 {
+            writer.shutdown().await?;
+            return Ok(());
+        }
+        activity.touch(direction);
+
+        let wait = options.shaping.reserve(direction, n);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        writer.write_all(&buf[..n]).await?;
+        total.fetch_add(n as u64, Ordering::Relaxed);
+
+        if !record_traffic(&options.meters, direction, n as u64) {
+            return Err(Stop::Closed(CloseReason::QuotaExhausted));
+        }
+    }
+}
+
+/// 读取数据；另一方向仍有数据时继续等待，双向都空闲超过 `idle` 时结束
+async fn read_until_idle<R>(
+    reader: &mut R,
+    buf: &mut [u8],
+    idle: Duration,
+    activity: &Activity,
+) -> std::result::Result<usize, Stop>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        match timeout_at(activity.last() + idle, reader.read(buf)).await {
+            Ok(result) => return Ok(result?),
+            Err(_) if activity.last() + idle <= Instant::now() => {
+                return Err(Stop::Closed(CloseReason::IdleTimeout(activity.quieter())));
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LimitsConfig;
+    use crate::connection::bandwidth::BandwidthManager;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    /// 通过限速转发双向发送数据，返回两端收到的数据量和耗时
+    async fn transfer(shaping: Shaping, up: usize, down: usize) -> (usize, usize, Duration) {
+        let (client, mut client_peer) = io::duplex(64 * 1024);
+        let (target, mut target_peer) = io::duplex(64 * 1024);
+        let options = RelayOptions {
+            shaping,
+            ..RelayOptions::default()
+        };
+        let relay = tokio::spawn(async move { bidirectional_copy(client, target, &options).await });
+
+        let start = Instant::now();
+        let client_side = async {
+            client_peer.write_all(&vec![1u8; up]).await.unwrap();
+            client_peer.shutdown().await.unwrap();
+            let mut received = Vec::new();
+            client_peer.read_to_end(&mut received).await.unwrap();
+            received.len()
+        };
+        let target_side = async {
+            target_peer.write_all(&vec![2u8; down]).await.unwrap();
+            target_peer.shutdown().await.unwrap();
+            let mut received = Vec::new();
+            target_peer.read_to_end(&mut received).await.unwrap();
+            received.len()
+        };
+        let (downloaded, uploaded) = tokio::join!(client_side, target_side);
+        let elapsed = start.elapsed();
+
+        let stats = relay.await.unwrap().unwrap();
+        assert_eq!((stats.client_to_target, stats.target_to_client), (up as u64, down as u64));
+        (uploaded, downloaded, elapsed)
+    }
+
+    fn shaping(limits: LimitsConfig) -> Shaping {
+        BandwidthManager::new(&limits).shaping(None)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unlimited() {
+        let (uploaded, downloaded, elapsed) = transfer(Shaping::default(), 100_000, 200_000).await;
+        assert_eq!((uploaded, downloaded), (100_000, 200_000));
+        assert_eq!(elapsed, Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_upload_and_download_limits() {
+        let limits = LimitsConfig {
+            max_upload_per_connection: 10_000,
+            max_download_per_connection: 40_000,
+            ..LimitsConfig::default()
+        };
+
+        // 上行：首个一秒的突发后，剩余 40KB 需要约 4 秒
+        let (uploaded, _, elapsed) = transfer(shaping(limits.clone()), 50_000, 0).await;
+        assert_eq!(uploaded, 50_000);
+        assert!(elapsed >= Duration::from_secs(4), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+        // 下行：剩余 160KB 需要约 4 秒
+        let (_, downloaded, elapsed) = transfer(shaping(limits), 0, 200_000).await;
+        assert_eq!(downloaded, 200_000);
+        assert!(elapsed >= Duration::from_secs(4), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst() {
+        let limits = LimitsConfig {
+            max_upload_per_connection: 10_000,
+            bandwidth_burst: 50_000,
+            ..LimitsConfig::default()
+        };
+
+        // 突发额度内不等待
+        let (_, _, elapsed) = transfer(shaping(limits), 50_000, 1000).await;
+        assert!(elapsed < Duration::from_millis(100), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_user_pool_divided_fairly() {
+        let manager = BandwidthManager::new(&LimitsConfig {
+            max_bandwidth_per_user: 20_000,
+            ..LimitsConfig::default()
+        });
+
+        // 同一用户的两个连接共享 20KB/s：各传 50KB，首个一秒的突发后剩余 80KB 约需 4 秒，
+        // 两个连接应几乎同时完成
+        let first = transfer(manager.shaping(Some("alice")), 50_000, 0);
+        let second = transfer(manager.shaping(Some("alice")), 50_000, 0);
+        let ((_, _, first), (_, _, second)) = tokio::join!(first, second);
+
+        for elapsed in [first, second] {
+            assert!(elapsed >= Duration::from_secs(3), "{:?}", elapsed);
+            assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+        }
+        let gap = first.max(second) - first.min(second);
+        assert!(gap < Duration::from_secs(1), "{:?} vs {:?}", first, second);
+    }
+
+    /// 记录流量，超过上限后中断
+    struct LimitMeter {
+        recorded: std::sync::Mutex<(u64, u64)>,
+        limit: u64,
+    }
+
+    impl TrafficMeter for LimitMeter {
+        fn record(&self, direction: Direction, bytes: u64) -> bool {
+            let mut recorded = self.recorded.lock().unwrap();
+            match direction {
+                Direction::Upload => recorded.0 += bytes,
+                Direction::Download => recorded.1 += bytes,
+            }
+            recorded.0 + recorded.1 < self.limit
+        }
+    }
+
+    #[tokio::test]
+    async fn test_meter() {
+        let meter = Arc::new(LimitMeter {
+            recorded: std::sync::Mutex::new((0, 0)),
+            limit: u64::MAX,
+        });
+        let options = RelayOptions {
+            meters: vec![meter.clone()],
+            ..RelayOptions::default()
+        };
+
+        let (client, mut client_peer) = io::duplex(1024);
+        let (target, mut target_peer) = io::duplex(1024);
+        let relay = tokio::spawn(async move { bidirectional_copy(client, target, &options).await });
+
+        client_peer.write_all(b"hello").await.unwrap();
+        client_peer.shutdown().await.unwrap();
+        target_peer.write_all(b"hi").await.unwrap();
+        target_peer.shutdown().await.unwrap();
+
+        let stats = relay.await.unwrap().unwrap();
+        assert_eq!((stats.client_to_target, stats.target_to_client), (5, 2));
+        assert_eq!(stats.reason, CloseReason::Eof);
+        assert_eq!(*meter.recorded.lock().unwrap(), (5, 2));
+    }
+
+    #[tokio::test]
+    async fn test_meter_cuts_relay() {
+        let options = RelayOptions {
+            meters: vec![Arc::new(LimitMeter {
+                recorded: std::sync::Mutex::new((0, 0)),
+                limit: 10,
+            })],
+            ..RelayOptions::default()
+        };
+
+        let (client, mut client_peer) = io::duplex(1024);
+        let (target, _target_peer) = io::duplex(1024);
+        let relay = tokio::spawn(async move { bidirectional_copy(client, target, &options).await });
+
+        client_peer.write_all(&[0u8; 20]).await.unwrap();
+        let stats = relay.await.unwrap().unwrap();
+        assert_eq!(stats.reason, CloseReason::QuotaExhausted);
+        assert_eq!(stats.client_to_target, 20);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_timeout() {
+        let options = RelayOptions {
+            idle_timeout: Some(Duration::from_secs(10)),
+            ..RelayOptions::default()
+        };
+
+        let (client, mut client_peer) = io::duplex(1024);
+        let (target, mut target_peer) = io::duplex(1024);
+        let relay = tokio::spawn(async move { bidirectional_copy(client, target, &options).await });
+
+        // 只有下行有数据时上行不算空闲
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_secs(6)).await;
+            target_peer.write_all(b"tick").await.unwrap();
+            let mut buf = [0u8; 4];
+            client_peer.read_exact(&mut buf).await.unwrap();
+        }
+        assert!(!relay.is_finished());
+
+        let start = Instant::now();
+        let stats = relay.await.unwrap().unwrap();
+        assert_eq!(stats.reason, CloseReason::IdleTimeout(Direction::Upload));
+        assert_eq!(stats.target_to_client, 12);
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_one_way_transfer_outlives_idle_timeout() {
+        let options = RelayOptions {
+            idle_timeout: Some(Duration::from_secs(10)),
+            ..RelayOptions::default()
+        };
+
+        let (client, mut client_peer) = io::duplex(1024);
+        let (target, mut target_peer) = io::duplex(1024);
+        let relay = tokio::spawn(async move { bidirectional_copy(client, target, &options).await });
+
+        // 客户端全程不发送数据，下行持续 60 秒
+        for _ in 0..20 {
+            tokio::time::sleep(Duration::from_secs(3)).await;
+            target_peer.write_all(b"tick").await.unwrap();
+            let mut buf = [0u8; 4];
+            client_peer.read_exact(&mut buf).await.unwrap();
+        }
+        assert!(!relay.is_finished());
+
+        target_peer.shutdown().await.unwrap();
+        client_peer.shutdown().await.unwrap();
+        let stats = relay.await.unwrap().unwrap();
+        assert_eq!(stats.reason, CloseReason::Eof);
+        assert_eq!((stats.client_to_target, stats.target_to_client), (0, 80));
+    }
+
+    #[tokio::test]
+    async fn test_error_keeps_transferred_bytes() {
+        let limited = RelayOptions {
+            idle_timeout: Some(Duration::from_secs(10)),
+            ..RelayOptions::default()
+        };
+
+        for options in [RelayOptions::default(), limited] {
+            let (client, mut client_peer) = io::duplex(1024);
+            let (target, mut target_peer) = io::duplex(1024);
+            let relay = tokio::spawn(async move { bidirectional_copy(client, target, &options).await });
+
+            client_peer.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            target_peer.read_exact(&mut buf).await.unwrap();
+            target_peer.write_all(b"hi").await.unwrap();
+            let mut buf = [0u8; 2];
+            client_peer.read_exact(&mut buf).await.unwrap();
+
+            // 目标端断开后继续上传，写入目标失败
+            drop(target_peer);
+            client_peer.write_all(b"more").await.unwrap();
+
+            let error = relay.await.unwrap().unwrap_err();
+            assert!(matches!(error.error, ProxyError::Io(_)), "{:?}", error.error);
+            assert_eq!((error.client_to_target, error.target_to_client), (5, 2));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_lifetime() {
+        let options = RelayOptions {
+            max_lifetime: Some(Duration::from_secs(30)),
+            ..RelayOptions::default()
+        };
+
+        let (client, mut client_peer) = io::duplex(1024);
+        let (target, _target_peer) = io::duplex(1024);
+        let relay = tokio::spawn(async move { bidirectional_copy(client, target, &options).await });
 
-    Ok((client_to_target, target_to_client))
+        client_peer.write_all(b"hello").await.unwrap();
+        let start = Instant::now();
+        let stats = relay.await.unwrap().unwrap();
+        assert_eq!(stats.reason, CloseReason::LifetimeExceeded);
+        assert_eq!(stats.client_to_target, 5);
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+    }
 }
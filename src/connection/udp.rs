@@ -0,0 +1,393 @@
+use crate::acl::DestinationAcl;
+use crate::config::RouteAction;
+use crate::connection::bandwidth::Direction;
+use crate::connection::relay::{record_traffic, CloseReason, RelayOptions, RelayStats};
+use crate::error::Result;
+use crate::protocol::udp::{encode_udp_packet, parse_udp_packet};
+use crate::protocol::Address;
+use crate::routing::Router;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info, trace, warn};
+
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// 目标地址解析结果的缓存时间
+const RESOLVE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// 单个关联最多缓存的目标数
+const RESOLVE_CACHE_SIZE: usize = 256;
+
+/// 目标域名解析的超时时间，解析在中继循环中进行，超时前整个关联都在等待
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// UDP 数据报目标的路由和访问控制
+pub struct UdpDestinations<'a> {
+    pub router: &'a Router,
+    pub acl: &'a DestinationAcl,
+    /// 认证用户，用于匹配路由规则
+    pub user: Option<&'a str>,
+}
+
+/// UDP ASSOCIATE 中继
+///
+/// `relay_socket` 接收客户端发来的封装数据报，解包后由出站 socket 转发到目标；
+/// 目标的响应重新封装后回送给客户端。控制 TCP 连接关闭时关联随之结束。
+/// 目标地址经路由规则和 ACL 检查，被拒绝或需要经由上游代理的数据报直接丢弃
+/// （UDP 不经过上游），检查结果按目标缓存。
+/// 转发的数据报载荷计入 `options` 中的计量，任一计量返回 false 时结束关联；
+/// 双向都没有数据报超过空闲超时或超过会话最长持续时间时同样结束，UDP 不限速。
+/// 单个数据报的收发错误只丢弃该数据报，不结束关联。
+pub async fn udp_associate(
+    mut control: TcpStream,
+    relay_socket: UdpSocket,
+    client_ip: IpAddr,
+    client_port: u16,
+    destinations: &UdpDestinations<'_>,
+    options: &RelayOptions,
+) -> Result<RelayStats> {
+    let mut client_addr: Option<SocketAddr> = None;
+    let mut outbound_v4: Option<UdpSocket> = None;
+    let mut outbound_v6: Option<UdpSocket> = None;
+    let mut resolver = ResolveCache::default();
+    let meters = &options.meters;
+
+    let started = Instant::now();
+    let lifetime_deadline = options.max_lifetime.map(|lifetime| started + lifetime);
+    let (mut last_upload, mut last_download) = (started, started);
+    let (mut uploaded, mut downloaded) = (0u64, 0u64);
+
+    let mut control_buf = [0u8; 1];
+    let mut client_buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    let mut v4_buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    let mut v6_buf = vec![0u8; MAX_DATAGRAM_SIZE];
+
+    trace!("Starting UDP relay on {}", relay_socket.local_addr()?);
+
+    let reason = loop {
+        let idle_deadline = options.idle_timeout.map(|idle| last_upload.max(last_download) + idle);
+
+        tokio::select! {
+            // 控制连接关闭即结束关联
+            result = control.read(&mut control_buf) => {
+                match result {
+                    Ok(0) | Err(_) => break CloseReason::Eof,
+                    Ok(_) => continue,
+                }
+            }
+            _ = sleep_until_deadline(idle_deadline) => {
+                let quieter = if last_download < last_upload { Direction::Download } else { Direction::Upload };
+                break CloseReason::IdleTimeout(quieter);
+            }
+            _ = sleep_until_deadline(lifetime_deadline) => break CloseReason::LifetimeExceeded,
+            result = relay_socket.recv_from(&mut client_buf) => {
+                let (n, from) = match result {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!("Failed to receive UDP datagram from client: {}", e);
+                        continue;
+                    }
+                };
+
+                // 只接受来自控制连接同一客户端的数据报
+                if from.ip() != client_ip || (client_port != 0 && from.port() != client_port) {
+                    trace!("Dropping UDP datagram from unexpected source {}", from);
+                    continue;
+                }
+                match client_addr {
+                    Some(addr) if addr != from => {
+                        trace!("Dropping UDP datagram from unexpected source {}", from);
+                        continue;
+                    }
+                    Some(_) => {}
+                    None => client_addr = Some(from),
+                }
+
+                let packet = match parse_udp_packet(&client_buf[..n]) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        debug!("Dropping malformed UDP datagram from {}: {}", from, e);
+                        continue;
+                    }
+                };
+
+                // 不支持分片，按 RFC 1928 直接丢弃
+                if packet.frag != 0 {
+                    trace!("Dropping fragmented UDP datagram (frag={})", packet.frag);
+                    continue;
+                }
+
+                let Some(target) = resolver.resolve(destinations, &packet.address).await else {
+                    continue;
+                };
+
+                let outbound = match target {
+                    SocketAddr::V4(_) => outbound_socket(&mut outbound_v4, Ipv4Addr::UNSPECIFIED.into()).await,
+                    SocketAddr::V6(_) => outbound_socket(&mut outbound_v6, Ipv6Addr::UNSPECIFIED.into()).await,
+                };
+                let outbound = match outbound {
+                    Ok(outbound) => outbound,
+                    Err(e) => {
+                        debug!("Failed to bind UDP outbound socket for {}: {}", target, e);
+                        continue;
+                    }
+                };
+
+                trace!("UDP {} -> {} ({} bytes)", from, target, packet.payload.len());
+                if let Err(e) = outbound.send_to(packet.payload, target).await {
+                    debug!("Failed to send UDP datagram to {}: {}", target, e);
+                    continue;
+                }
+                last_upload = Instant::now();
+                uploaded += packet.payload.len() as u64;
+                if !record_traffic(meters, Direction::Upload, packet.payload.len() as u64) {
+                    break CloseReason::QuotaExhausted;
+                }
+            }
+            result = recv_outbound(&outbound_v4, &mut v4_buf) => {
+                let (n, from) = match result {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!("Failed to receive UDP datagram from target: {}", e);
+                        continue;
+                    }
+                };
+                reply_to_client(&relay_socket, client_addr, from, &v4_buf[..n]).await;
+                last_download = Instant::now();
+                downloaded += n as u64;
+                if !record_traffic(meters, Direction::Download, n as u64) {
+                    break CloseReason::QuotaExhausted;
+                }
+            }
+            result = recv_outbound(&outbound_v6, &mut v6_buf) => {
+                let (n, from) = match result {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!("Failed to receive UDP datagram from target: {}", e);
+                        continue;
+                    }
+                };
+                reply_to_client(&relay_socket, client_addr, from, &v6_buf[..n]).await;
+                last_download = Instant::now();
+                downloaded += n as u64;
+                if !record_traffic(meters, Direction::Download, n as u64) {
+                    break CloseReason::QuotaExhausted;
+                }
+            }
+        }
+    };
+
+    let stats = RelayStats {
+        client_to_target: uploaded,
+        target_to_client: downloaded,
+        reason,
+    };
+    if reason == CloseReason::Eof {
+        debug!("UDP association for {} closed", client_ip);
+    } else {
+        info!("UDP association for {} closed ({})", client_ip, reason);
+    }
+
+    Ok(stats)
+}
+
+/// 等待到截止时间，没有截止时间时永远挂起
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 按目标缓存的地址解析结果
+///
+/// 避免每个数据报都在中继循环中等待 DNS；解析失败、超时、路由和 ACL 拒绝同样缓存，
+/// 缓存期内发往该目标的数据报直接丢弃
+#[derive(Default)]
+struct ResolveCache {
+    entries: HashMap<Address, (Instant, Option<SocketAddr>)>,
+}
+
+impl ResolveCache {
+    async fn resolve(&mut self, destinations: &UdpDestinations<'_>, address: &Address) -> Option<SocketAddr> {
+        if let Some((resolved_at, target)) = self.entries.get(address) {
+            if resolved_at.elapsed() < RESOLVE_CACHE_TTL {
+                if target.is_none() {
+                    trace!("Dropping UDP datagram to {} (cached failure)", address);
+                }
+                return *target;
+            }
+        }
+
+        let target = if is_routed_directly(destinations, address, None) {
+            match tokio::time::timeout(RESOLVE_TIMEOUT, destinations.acl.resolve(address)).await {
+                Ok(Ok(addrs)) => addrs
+                    .into_iter()
+                    .find(|addr| is_routed_directly(destinations, address, Some(addr.ip()))),
+                Ok(Err(e)) => {
+                    debug!("Dropping UDP datagram to {}: {}", address, e);
+                    None
+                }
+                Err(_) => {
+                    debug!("Dropping UDP datagram to {}: resolution timed out after {:?}", address, RESOLVE_TIMEOUT);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        if self.entries.len() >= RESOLVE_CACHE_SIZE {
+            self.entries.retain(|_, (resolved_at, _)| resolved_at.elapsed() < RESOLVE_CACHE_TTL);
+            if self.entries.len() >= RESOLVE_CACHE_SIZE {
+                self.entries.clear();
+            }
+        }
+        self.entries.insert(address.clone(), (Instant::now(), target));
+
+        target
+    }
+}
+
+/// 目标是否按路由规则直连；域名解析后按解析出的地址再匹配一次
+fn is_routed_directly(destinations: &UdpDestinations<'_>, address: &Address, resolved: Option<IpAddr>) -> bool {
+    let route = match resolved {
+        Some(ip) if address.socket_addr().is_none() => destinations.router.route_resolved(address, ip, destinations.user),
+        Some(_) => return true,
+        None => destinations.router.route(address, destinations.user),
+    };
+
+    let rule = route.rule.as_deref().unwrap_or("default");
+    match route.action {
+        RouteAction::Direct => true,
+        RouteAction::Reject => {
+            info!("Dropping UDP datagrams to {} (rule: {})", address, rule);
+            false
+        }
+        RouteAction::Upstream => {
+            info!("Dropping UDP datagrams to {}: UDP is not relayed via upstream (rule: {})", address, rule);
+            false
+        }
+    }
+}
+
+/// 按需创建出站 socket
+async fn outbound_socket(slot: &mut Option<UdpSocket>, ip: IpAddr) -> Result<&UdpSocket> {
+    if slot.is_none() {
+        let socket = UdpSocket::bind(SocketAddr::new(ip, 0)).await?;
+        trace!("Bound UDP outbound socket on {}", socket.local_addr()?);
+        *slot = Some(socket);
+    }
+
+    Ok(slot.as_ref().unwrap())
+}
+
+/// 从出站 socket 接收，尚未创建时永远挂起
+async fn recv_outbound(socket: &Option<UdpSocket>, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+    match socket {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 将目标响应封装后发回客户端
+async fn reply_to_client(relay_socket: &UdpSocket, client_addr: Option<SocketAddr>, from: SocketAddr, payload: &[u8]) {
+    let Some(client_addr) = client_addr else {
+        return;
+    };
+
+    trace!("UDP {} -> {} ({} bytes)", from, client_addr, payload.len());
+    // 来源总是 IP 地址，编码不会失败
+    let Ok(datagram) = encode_udp_packet(&Address::from(from), payload) else {
+        return;
+    };
+    if let Err(e) = relay_socket.send_to(&datagram, client_addr).await {
+        warn!("Failed to send UDP datagram to client {}: {}", client_addr, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AclConfig, RouteRule, RoutingConfig};
+
+    fn rule(cidr: &str, action: RouteAction) -> RouteRule {
+        RouteRule {
+            name: String::new(),
+            domain_suffix: Vec::new(),
+            domain_regex: Vec::new(),
+            cidr: vec![cidr.to_string()],
+            ports: Vec::new(),
+            users: Vec::new(),
+            action,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resolve_cache() {
+        let acl = DestinationAcl::new(&AclConfig::default()).unwrap();
+        let router = Router::new(&RoutingConfig::default(), false).unwrap();
+        let destinations = UdpDestinations {
+            router: &router,
+            acl: &acl,
+            user: None,
+        };
+        let mut resolver = ResolveCache::default();
+        let address = Address::Domain("nonexistent.invalid".to_string(), 53);
+        let cached: SocketAddr = "8.8.8.8:53".parse().unwrap();
+
+        // 缓存期内不重新解析
+        resolver.entries.insert(address.clone(), (Instant::now(), Some(cached)));
+        assert_eq!(resolver.resolve(&destinations, &address).await, Some(cached));
+
+        // 过期后重新解析，失败结果同样缓存
+        tokio::time::advance(RESOLVE_CACHE_TTL).await;
+        assert_eq!(resolver.resolve(&destinations, &address).await, None);
+        assert_eq!(resolver.entries[&address].1, None);
+
+        // ACL 拒绝的地址不转发
+        let private = Address::Ipv4(Ipv4Addr::new(10, 0, 0, 1), 53);
+        assert_eq!(resolver.resolve(&destinations, &private).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_applies_routing() {
+        let acl = DestinationAcl::new(&AclConfig {
+            allow: vec!["127.0.0.1".to_string()],
+            ..AclConfig::default()
+        })
+        .unwrap();
+        let routing = RoutingConfig {
+            default_action: Some(RouteAction::Direct),
+            rules: vec![
+                rule("1.1.1.1", RouteAction::Reject),
+                rule("8.8.8.8", RouteAction::Upstream),
+                rule("127.0.0.0/8", RouteAction::Reject),
+            ],
+        };
+        let router = Router::new(&routing, true).unwrap();
+        let destinations = UdpDestinations {
+            router: &router,
+            acl: &acl,
+            user: None,
+        };
+        let mut resolver = ResolveCache::default();
+
+        // 拒绝和需要经由上游的目标都不直接发送
+        let rejected = Address::Ipv4(Ipv4Addr::new(1, 1, 1, 1), 53);
+        assert_eq!(resolver.resolve(&destinations, &rejected).await, None);
+        let upstream = Address::Ipv4(Ipv4Addr::new(8, 8, 8, 8), 53);
+        assert_eq!(resolver.resolve(&destinations, &upstream).await, None);
+
+        // 域名解析到被拒绝的网段
+        let local = Address::Domain("localhost".to_string(), 53);
+        assert_eq!(resolver.resolve(&destinations, &local).await, None);
+
+        let allowed = Address::Ipv4(Ipv4Addr::new(9, 9, 9, 9), 53);
+        assert_eq!(resolver.resolve(&destinations, &allowed).await, Some("9.9.9.9:53".parse().unwrap()));
+    }
+}
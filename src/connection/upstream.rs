@@ -0,0 +1,247 @@
+use crate::config::{UpstreamProxy, UpstreamType};
+use crate::error::{ProxyError, Result};
+use crate::protocol::{http, request, Address, AuthMethod, Reply, SOCKS_VERSION};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, trace};
+
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const MAX_RESPONSE_HEAD_SIZE: usize = 16 * 1024;
+
+/// 检查上游代理链配置：地址格式，以及 SOCKS5 用户名和密码不超过 255 字节（RFC 1929）
+pub fn validate_chain(chain: &[UpstreamProxy]) -> Result<()> {
+    for proxy in chain {
+        http::parse_authority(&proxy.address, 1080)
+            .map_err(|_| ProxyError::Config(format!("Invalid upstream address: {}", proxy.address)))?;
+        if proxy.proxy_type == UpstreamType::Socks5 {
+            credentials_len(proxy).map_err(|_| {
+                ProxyError::Config(format!(
+                    "Username and password of SOCKS5 upstream {} must not exceed 255 bytes",
+                    proxy.address
+                ))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// SOCKS5 用户名密码认证中的用户名和密码长度，超过 255 字节时返回错误
+fn credentials_len(proxy: &UpstreamProxy) -> Result<(u8, u8)> {
+    let too_long = |_| ProxyError::Upstream(format!("Credentials for {} exceed 255 bytes", proxy.address));
+    Ok((
+        u8::try_from(proxy.username.len()).map_err(too_long)?,
+        u8::try_from(proxy.password.len()).map_err(too_long)?,
+    ))
+}
+
+/// 通过上游代理链连接目标
+///
+/// 先连接链上第一个代理，再由每一跳代理依次连接下一跳，最后一跳连接目标
+pub async fn connect_via_chain(chain: &[UpstreamProxy], target: &Address) -> Result<TcpStream> {
+    let first = chain
+        .first()
+        .ok_or_else(|| ProxyError::Config("Upstream chain is empty".to_string()))?;
+
+    let mut stream = TcpStream::connect(&first.address).await.map_err(|e| {
+        ProxyError::Upstream(format!("Failed to connect to upstream {}: {}", first.address, e))
+    })?;
+
+    for (i, proxy) in chain.iter().enumerate() {
+        let next_hop = match chain.get(i + 1) {
+            Some(next) => http::parse_authority(&next.address, 1080).map_err(|_| {
+                ProxyError::Config(format!("Invalid upstream address: {}", next.address))
+            })?,
+            None => target.clone(),
+        };
+
+        trace!("Upstream hop {} via {:?} proxy {}", next_hop, proxy.proxy_type, proxy.address);
+
+        match proxy.proxy_type {
+            UpstreamType::Socks5 => socks5_connect(&mut stream, proxy, &next_hop).await?,
+            UpstreamType::Http => http_connect(&mut stream, proxy, &next_hop).await?,
+        }
+    }
+
+    debug!("Connected to {} through {} upstream proxies", target, chain.len());
+
+    Ok(stream)
+}
+
+/// 通过 SOCKS5 代理建立到 `target` 的隧道
+async fn socks5_connect(stream: &mut TcpStream, proxy: &UpstreamProxy, target: &Address) -> Result<()> {
+    // 1. 协商认证方法
+    let with_credentials = !proxy.username.is_empty();
+    let greeting: &[u8] = if with_credentials {
+        &[SOCKS_VERSION, 0x02, AuthMethod::NoAuth as u8, AuthMethod::UsernamePassword as u8]
+    } else {
+        &[SOCKS_VERSION, 0x01, AuthMethod::NoAuth as u8]
+    };
+    stream.write_all(greeting).await?;
+
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await?;
+    if response[0] != SOCKS_VERSION {
+        return Err(ProxyError::Upstream(format!(
+            "{} replied with SOCKS version {}",
+            proxy.address, response[0]
+        )));
+    }
+
+    // 2. 用户名密码认证
+    match AuthMethod::from_u8(response[1]) {
+        Some(AuthMethod::NoAuth) => {}
+        Some(AuthMethod::UsernamePassword) if with_credentials => {
+            let (username_len, password_len) = credentials_len(proxy)?;
+            let mut auth = vec![USERNAME_PASSWORD_VERSION, username_len];
+            auth.extend_from_slice(proxy.username.as_bytes());
+            auth.push(password_len);
+            auth.extend_from_slice(proxy.password.as_bytes());
+            stream.write_all(&auth).await?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0x00 {
+                return Err(ProxyError::Upstream(format!(
+                    "Authentication rejected by {}",
+                    proxy.address
+                )));
+            }
+        }
+        _ => {
+            return Err(ProxyError::Upstream(format!(
+                "No acceptable authentication method with {}",
+                proxy.address
+            )));
+        }
+    }
+
+    // 3. 发送 CONNECT 请求
+    let mut connect = vec![SOCKS_VERSION, 0x01, 0x00];
+    target.write_to(&mut connect)?;
+    stream.write_all(&connect).await?;
+
+    // 4. 读取响应 [VER | REP | RSV | ATYP | BND.ADDR | BND.PORT]
+    let mut header = [0u8; 3];
+    stream.read_exact(&mut header).await?;
+    let bind_address = request::read_address(stream).await?;
+
+    match Reply::from_u8(header[1]) {
+        Some(Reply::Succeeded) => {
+            trace!("Upstream {} bound {} for {}", proxy.address, bind_address, target);
+            Ok(())
+        }
+        reply => {
+            debug!("Upstream {} failed to connect to {}: {:?}", proxy.address, target, reply);
+            Err(reply_error(reply))
+        }
+    }
+}
+
+/// 将上游 SOCKS5 失败响应映射为错误，以便向客户端返回相同的响应码
+fn reply_error(reply: Option<Reply>) -> ProxyError {
+    match reply {
+        Some(Reply::ConnectionNotAllowed) => ProxyError::ConnectionNotAllowed,
+        Some(Reply::NetworkUnreachable) => ProxyError::NetworkUnreachable,
+        Some(Reply::HostUnreachable) => ProxyError::HostUnreachable,
+        Some(Reply::ConnectionRefused) => ProxyError::ConnectionRefused,
+        Some(Reply::TtlExpired) => ProxyError::Timeout,
+        Some(Reply::CommandNotSupported) => ProxyError::UnsupportedCommand(0x01),
+        Some(Reply::AddressTypeNotSupported) => ProxyError::UnsupportedAddressType(0x00),
+        reply => ProxyError::Upstream(format!("Upstream replied {:?}", reply)),
+    }
+}
+
+/// 通过 HTTP CONNECT 代理建立到 `target` 的隧道
+async fn http_connect(stream: &mut TcpStream, proxy: &UpstreamProxy, target: &Address) -> Result<()> {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if !proxy.username.is_empty() {
+        let credentials = BASE64.encode(format!("{}:{}", proxy.username, proxy.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // 逐字节读取响应头，避免读走隧道中的数据
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_RESPONSE_HEAD_SIZE {
+            return Err(ProxyError::Upstream(format!(
+                "Response head from {} too large",
+                proxy.address
+            )));
+        }
+        head.push(stream.read_u8().await?);
+    }
+
+    let status_line = String::from_utf8_lossy(&head);
+    let status = status_line
+        .split(' ')
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| ProxyError::Upstream(format!("Invalid response from {}", proxy.address)))?;
+
+    match status {
+        200..=299 => Ok(()),
+        _ => {
+            debug!("Upstream {} failed to connect to {}: HTTP {}", proxy.address, target, status);
+            Err(status_error(status))
+        }
+    }
+}
+
+/// 将上游 HTTP 状态码映射为错误
+fn status_error(status: u16) -> ProxyError {
+    match status {
+        403 => ProxyError::ConnectionNotAllowed,
+        502 | 503 => ProxyError::HostUnreachable,
+        504 => ProxyError::Timeout,
+        _ => ProxyError::Upstream(format!("Upstream replied HTTP {}", status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_error() {
+        assert!(matches!(reply_error(Some(Reply::ConnectionRefused)), ProxyError::ConnectionRefused));
+        assert!(matches!(reply_error(Some(Reply::TtlExpired)), ProxyError::Timeout));
+        assert!(matches!(
+            reply_error(Some(Reply::ConnectionNotAllowed)),
+            ProxyError::ConnectionNotAllowed
+        ));
+        assert!(matches!(reply_error(None), ProxyError::Upstream(_)));
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let proxy = |proxy_type, username: &str| UpstreamProxy {
+            proxy_type,
+            address: "127.0.0.1:1080".to_string(),
+            username: username.to_string(),
+            password: "secret".to_string(),
+        };
+
+        assert!(validate_chain(&[proxy(UpstreamType::Socks5, "alice")]).is_ok());
+        // 超长用户名不能截断长度后发出
+        let long = "a".repeat(256);
+        assert!(matches!(validate_chain(&[proxy(UpstreamType::Socks5, &long)]), Err(ProxyError::Config(_))));
+        assert!(matches!(credentials_len(&proxy(UpstreamType::Socks5, &long)), Err(ProxyError::Upstream(_))));
+        assert!(validate_chain(&[proxy(UpstreamType::Http, &long)]).is_ok());
+
+        let mut invalid = proxy(UpstreamType::Http, "");
+        invalid.address = "[::1".to_string();
+        assert!(validate_chain(&[invalid]).is_err());
+    }
+
+    #[test]
+    fn test_status_error() {
+        assert!(matches!(status_error(403), ProxyError::ConnectionNotAllowed));
+        assert!(matches!(status_error(502), ProxyError::HostUnreachable));
+        assert!(matches!(status_error(504), ProxyError::Timeout));
+        assert!(matches!(status_error(407), ProxyError::Upstream(_)));
+    }
+}
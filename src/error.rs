@@ -15,6 +15,9 @@ pub enum ProxyError {
     #[error("Authentication failed")]
     AuthFailed,
 
+    #[error("Authentication backend error: {0}")]
+    AuthBackend(String),
+
     #[error("Unsupported command: {0}")]
     UnsupportedCommand(u8),
 
@@ -27,6 +30,9 @@ pub enum ProxyError {
     #[error("Connection refused")]
     ConnectionRefused,
 
+    #[error("Connection not allowed")]
+    ConnectionNotAllowed,
+
     #[error("Host unreachable")]
     HostUnreachable,
 
@@ -39,8 +45,39 @@ pub enum ProxyError {
     #[error("Protocol error: {0}")]
     Protocol(String),
 
+    #[error("Upstream proxy error: {0}")]
+    Upstream(String),
+
     #[error("Configuration error: {0}")]
     Config(String),
+
+    #[error("Session killed by administrator")]
+    SessionKilled,
+}
+
+impl ProxyError {
+    /// 错误类型名称，用作指标标签
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProxyError::Io(_) => "io",
+            ProxyError::InvalidVersion(_) => "invalid_version",
+            ProxyError::NoAcceptableAuth => "no_acceptable_auth",
+            ProxyError::AuthFailed => "auth_failed",
+            ProxyError::AuthBackend(_) => "auth_backend",
+            ProxyError::UnsupportedCommand(_) => "unsupported_command",
+            ProxyError::UnsupportedAddressType(_) => "unsupported_address_type",
+            ProxyError::InvalidAddress => "invalid_address",
+            ProxyError::ConnectionRefused => "connection_refused",
+            ProxyError::ConnectionNotAllowed => "connection_not_allowed",
+            ProxyError::HostUnreachable => "host_unreachable",
+            ProxyError::NetworkUnreachable => "network_unreachable",
+            ProxyError::Timeout => "timeout",
+            ProxyError::Protocol(_) => "protocol",
+            ProxyError::Upstream(_) => "upstream",
+            ProxyError::Config(_) => "config",
+            ProxyError::SessionKilled => "killed",
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ProxyError>;
@@ -1,8 +1,17 @@
+pub mod access;
+pub mod access_log;
+pub mod admin;
+pub mod acl;
+pub mod auth;
 pub mod config;
 pub mod connection;
 pub mod error;
+pub mod metrics;
 pub mod protocol;
+pub mod quota;
+pub mod routing;
 pub mod server;
+pub mod session;
 
 pub use config::Config;
 pub use server::ProxyServer;
@@ -1,7 +1,14 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::BufRead;
 use std::path::PathBuf;
 use tracing::{error, info};
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter, Layer, Registry};
+use yun_socket_proxy::access_log::RotatingFile;
+use yun_socket_proxy::config::{LogFormat, LoggingConfig};
+use yun_socket_proxy::auth::password::{hash_password, HashAlgorithm};
+use yun_socket_proxy::session::ACCESS_LOG_TARGET;
 use yun_socket_proxy::{Config, ProxyServer};
 
 #[derive(Parser, Debug)]
@@ -24,37 +31,78 @@ struct Args {
     #[arg(long)]
     auth: bool,
 
-    /// Log level (trace, debug, info, warn, error)
-    #[arg(long, default_value = "info")]
-    log_level: String,
+    /// Log level (trace, debug, info, warn, error), overrides the config file
+    #[arg(long)]
+    log_level: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Hash a password for use in the [auth] users list
+    HashPassword {
+        /// Hash algorithm
+        #[arg(short, long, value_enum, default_value = "argon2")]
+        algorithm: Algorithm,
+
+        /// Password to hash (read from stdin if omitted)
+        password: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Algorithm {
+    Argon2,
+    Bcrypt,
+    Sha512,
+}
+
+impl From<Algorithm> for HashAlgorithm {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Argon2 => HashAlgorithm::Argon2,
+            Algorithm::Bcrypt => HashAlgorithm::Bcrypt,
+            Algorithm::Sha512 => HashAlgorithm::Sha512Crypt,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
 
-    // 初始化日志
-    init_logging(&args.log_level);
-
-    info!("Starting yun-socket-proxy v{}", env!("CARGO_PKG_VERSION"));
+    if let Some(Commands::HashPassword { algorithm, password }) = args.command {
+        run_hash_password(algorithm, password);
+        return;
+    }
 
-    // 加载配置
-    let mut config = if let Some(config_path) = args.config {
-        match Config::from_file(&config_path) {
-            Ok(config) => {
-                info!("Loaded configuration from {:?}", config_path);
-                config
-            }
+    // 加载配置，日志格式和级别取自配置文件，此时日志尚未初始化
+    let mut config = match &args.config {
+        Some(config_path) => match Config::from_file(config_path) {
+            Ok(config) => config,
             Err(e) => {
-                error!("Failed to load configuration file: {}", e);
+                eprintln!("Failed to load configuration file: {}", e);
                 std::process::exit(1);
             }
-        }
-    } else {
-        info!("Using default configuration");
-        Config::default()
+        },
+        None => Config::default(),
     };
 
+    // 初始化日志，命令行参数覆盖配置文件
+    if let Some(level) = args.log_level {
+        config.logging.level = level;
+    }
+    // 守卫须存活到进程退出，保证访问日志写入文件
+    let _access_log_guard = init_logging(&config.logging);
+
+    info!("Starting yun-socket-proxy v{}", env!("CARGO_PKG_VERSION"));
+    match &args.config {
+        Some(config_path) => info!("Loaded configuration from {:?}", config_path),
+        None => info!("Using default configuration"),
+    }
+
     // 命令行参数覆盖配置文件
     if let Some(bind) = args.bind {
         config.server.bind_address = bind;
@@ -75,12 +123,89 @@ async fn main() {
     }
 }
 
-fn init_logging(level: &str) {
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new(level));
+/// 输出密码哈希，未提供密码时从标准输入读取一行
+fn run_hash_password(algorithm: Algorithm, password: Option<String>) {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            let mut line = String::new();
+            if let Err(e) = std::io::stdin().lock().read_line(&mut line) {
+                eprintln!("Failed to read password: {}", e);
+                std::process::exit(1);
+            }
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    if password.is_empty() {
+        eprintln!("Password must not be empty");
+        std::process::exit(1);
+    }
+
+    match hash_password(&password, algorithm.into()) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// 初始化日志；设置了 RUST_LOG 时以环境变量为准
+///
+/// 配置了访问日志文件时，访问日志只写入该文件，返回其后台写入线程的守卫
+fn init_logging(config: &LoggingConfig) -> Option<WorkerGuard> {
+    let access_file = config.access_log && !config.access.path.is_empty();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        // 访问日志不受全局级别影响，只由 access_log 开关控制
+        let access = if config.access_log { "info" } else { "off" };
+        EnvFilter::new(format!("{},{}={}", config.level, ACCESS_LOG_TARGET, access))
+    });
+    let filter = if access_file {
+        filter.add_directive(format!("{}=off", ACCESS_LOG_TARGET).parse().unwrap())
+    } else {
+        filter
+    };
+
+    let mut layers = vec![
+        format_layer(config.format, fmt::layer().with_target(true).with_thread_ids(true))
+            .with_filter(filter)
+            .boxed(),
+    ];
+
+    let mut guard = None;
+    if access_file {
+        let file = match RotatingFile::open(&config.access) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Failed to open access log {:?}: {}", config.access.path, e);
+                std::process::exit(1);
+            }
+        };
+        let (writer, worker_guard) = tracing_appender::non_blocking(file);
+        layers.push(
+            format_layer(config.access.format, fmt::layer().with_writer(writer).with_ansi(false))
+                .with_filter(Targets::new().with_target(ACCESS_LOG_TARGET, LevelFilter::INFO))
+                .boxed(),
+        );
+        guard = Some(worker_guard);
+    }
+
+    tracing_subscriber::registry().with(layers).init();
 
-    tracing_subscriber::registry()
-        .with(fmt::layer().with_target(true).with_thread_ids(true))
-        .with(filter)
-        .init();
+    guard
+}
+
+/// 按日志格式装配 fmt 层
+fn format_layer<W>(format: LogFormat, layer: fmt::Layer<Registry, fmt::format::DefaultFields, fmt::format::Format, W>) -> BoxedLayer
+where
+    W: for<'writer> fmt::MakeWriter<'writer> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Pretty => layer.boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    }
 }
@@ -0,0 +1,387 @@
+use crate::auth::{Authenticator, Identity};
+use crate::connection::ConnectionLimiter;
+use crate::error::{ProxyError, Result};
+use crate::protocol;
+use futures::future::BoxFuture;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+/// 连接目标服务器耗时的直方图分桶（秒）
+const CONNECT_DURATION_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// 按标签分组的计数器，键为渲染好的标签串
+#[derive(Default)]
+struct CounterVec {
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl CounterVec {
+    fn inc(&self, labels: &[(&str, &str)]) {
+        *self.values.lock().unwrap().entry(render_labels(labels)).or_insert(0) += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        write_header(out, name, help, "counter");
+        for (labels, value) in self.values.lock().unwrap().iter() {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+/// 直方图
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: buckets.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(index) = self.buckets.iter().position(|bound| secs <= *bound) {
+            self.counts[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        write_header(out, name, help, "histogram");
+        let mut cumulative = 0;
+        for (bound, count) in self.buckets.iter().zip(&self.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// 单个用户的转发字节数
+#[derive(Default)]
+struct Traffic {
+    upload: AtomicU64,
+    download: AtomicU64,
+}
+
+/// 连接被拒绝的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// 客户端访问控制
+    Access,
+    /// 认证失败锁定
+    Banned,
+    /// 单 IP 并发连接数
+    IpLimit,
+    /// 新连接速率
+    RateLimit,
+    /// 全局并发连接数
+    ConnectionLimit,
+}
+
+impl RejectReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            RejectReason::Access => "access",
+            RejectReason::Banned => "banned",
+            RejectReason::IpLimit => "ip_limit",
+            RejectReason::RateLimit => "rate_limit",
+            RejectReason::ConnectionLimit => "connection_limit",
+        }
+    }
+}
+
+/// 代理运行指标，按 Prometheus 文本格式导出
+pub struct Metrics {
+    enabled: bool,
+    accepted: AtomicU64,
+    rejected: CounterVec,
+    handshake_failures: CounterVec,
+    auth: CounterVec,
+    replies: CounterVec,
+    connect_duration: Histogram,
+    traffic: Mutex<HashMap<String, Traffic>>,
+}
+
+impl Metrics {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            accepted: AtomicU64::new(0),
+            rejected: CounterVec::default(),
+            handshake_failures: CounterVec::default(),
+            auth: CounterVec::default(),
+            replies: CounterVec::default(),
+            connect_duration: Histogram::new(CONNECT_DURATION_BUCKETS),
+            traffic: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn connection_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_rejected(&self, reason: RejectReason) {
+        self.rejected.inc(&[("reason", reason.as_str())]);
+    }
+
+    /// 握手或认证阶段失败，按错误类型计数
+    pub fn handshake_failed(&self, error: &ProxyError) {
+        self.handshake_failures.inc(&[("error", error.kind())]);
+    }
+
+    /// 记录认证结果；失败不按用户名分组，避免任意用户名撑大标签集合
+    pub fn auth_result(&self, result: &Result<Identity>) {
+        match result {
+            Ok(identity) => self.auth.inc(&[("result", "success"), ("user", &identity.username)]),
+            Err(ProxyError::AuthFailed) => self.auth.inc(&[("result", "failure")]),
+            Err(_) => self.auth.inc(&[("result", "error")]),
+        }
+    }
+
+    /// 记录发送给客户端的响应码（SOCKS 响应字节或 HTTP 状态码）
+    pub fn reply_sent(&self, protocol: &str, code: u16) {
+        self.replies.inc(&[("protocol", protocol), ("code", &code.to_string())]);
+    }
+
+    pub fn connect_completed(&self, duration: Duration) {
+        self.connect_duration.observe(duration);
+    }
+
+    /// 会话结束时按用户累计转发字节数
+    pub fn traffic_finished(&self, user: Option<&str>, upload: u64, download: u64) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut traffic = self.traffic.lock().unwrap();
+        let traffic = traffic.entry(user.unwrap_or_default().to_string()).or_default();
+        traffic.upload.fetch_add(upload, Ordering::Relaxed);
+        traffic.download.fetch_add(download, Ordering::Relaxed);
+    }
+
+    /// 渲染 Prometheus 文本格式
+    pub fn render(&self, limiter: &ConnectionLimiter) -> String {
+        let mut out = String::new();
+
+        write_header(&mut out, "proxy_active_connections", "Active client connections", "gauge");
+        let _ = writeln!(out, "proxy_active_connections {}", limiter.active_count());
+        write_header(&mut out, "proxy_max_connections", "Maximum concurrent client connections", "gauge");
+        let _ = writeln!(out, "proxy_max_connections {}", limiter.max_count());
+
+        write_header(&mut out, "proxy_connections_accepted_total", "Accepted client connections", "counter");
+        let _ = writeln!(out, "proxy_connections_accepted_total {}", self.accepted.load(Ordering::Relaxed));
+        self.rejected.render(&mut out, "proxy_connections_rejected_total", "Rejected client connections by reason");
+        self.handshake_failures.render(
+            &mut out,
+            "proxy_handshake_failures_total",
+            "Failed handshakes by error type",
+        );
+        self.auth.render(&mut out, "proxy_auth_total", "Authentication attempts by result");
+        self.replies.render(&mut out, "proxy_replies_total", "Replies sent to clients by protocol and code");
+        self.connect_duration.render(
+            &mut out,
+            "proxy_connect_duration_seconds",
+            "Time to establish connections to targets",
+        );
+
+        let traffic = self.traffic.lock().unwrap();
+        let mut users: Vec<_> = traffic.iter().collect();
+        users.sort_by(|a, b| a.0.cmp(b.0));
+        write_header(&mut out, "proxy_bytes_total", "Relayed bytes by user and direction", "counter");
+        for (user, traffic) in users {
+            for (direction, bytes) in [("upload", &traffic.upload), ("download", &traffic.download)] {
+                let labels = render_labels(&[("user", user), ("direction", direction)]);
+                let _ = writeln!(out, "proxy_bytes_total{{{}}} {}", labels, bytes.load(Ordering::Relaxed));
+            }
+        }
+
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// 统计认证结果的认证后端
+pub struct MeteredAuthenticator {
+    inner: Arc<dyn Authenticator>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredAuthenticator {
+    pub fn new(inner: Arc<dyn Authenticator>, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl Authenticator for MeteredAuthenticator {
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+        client_ip: IpAddr,
+    ) -> BoxFuture<'a, Result<Identity>> {
+        Box::pin(async move {
+            let result = self.inner.authenticate(username, password, client_ip).await;
+            self.metrics.auth_result(&result);
+            result
+        })
+    }
+}
+
+/// 指标 HTTP 服务，只响应 `path` 上的 GET 请求
+///
+/// 请求头须在 `head_timeout` 内读完，与代理端口的握手超时一致
+pub async fn serve(
+    listener: TcpListener,
+    path: String,
+    metrics: Arc<Metrics>,
+    limiter: ConnectionLimiter,
+    head_timeout: Duration,
+) {
+    loop {
+        let (mut stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept metrics connection: {}", e);
+                continue;
+            }
+        };
+
+        let path = path.clone();
+        let metrics = metrics.clone();
+        let limiter = limiter.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_scrape(&mut stream, &path, &metrics, &limiter, head_timeout).await {
+                debug!("Metrics request from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_scrape(
+    stream: &mut TcpStream,
+    path: &str,
+    metrics: &Metrics,
+    limiter: &ConnectionLimiter,
+    head_timeout: Duration,
+) -> Result<()> {
+    let (head, _) = protocol::http::read_request_head_within(stream, head_timeout).await?;
+    let request_path = head.target.split('?').next().unwrap_or_default();
+
+    if !head.method.eq_ignore_ascii_case("GET") || request_path != path {
+        return protocol::http::send_response(stream, 404, "Not Found", &[]).await;
+    }
+
+    let body = metrics.render(limiter);
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_and_labels() {
+        let metrics = Metrics::new(true);
+        metrics.connection_accepted();
+        metrics.connection_accepted();
+        metrics.connection_rejected(RejectReason::RateLimit);
+        metrics.handshake_failed(&ProxyError::Timeout);
+        metrics.auth_result(&Ok(Identity::new("al\"ice")));
+        metrics.auth_result(&Err(ProxyError::AuthFailed));
+        metrics.reply_sent("socks5", 0);
+        metrics.reply_sent("socks5", 0);
+
+        let output = metrics.render(&ConnectionLimiter::new(10));
+        assert!(output.contains("proxy_active_connections 0\n"));
+        assert!(output.contains("proxy_max_connections 10\n"));
+        assert!(output.contains("proxy_connections_accepted_total 2\n"));
+        assert!(output.contains("proxy_connections_rejected_total{reason=\"rate_limit\"} 1\n"));
+        assert!(output.contains("proxy_handshake_failures_total{error=\"timeout\"} 1\n"));
+        assert!(output.contains("proxy_auth_total{result=\"success\",user=\"al\\\"ice\"} 1\n"));
+        assert!(output.contains("proxy_auth_total{result=\"failure\"} 1\n"));
+        assert!(output.contains("proxy_replies_total{protocol=\"socks5\",code=\"0\"} 2\n"));
+        assert!(output.contains("# TYPE proxy_auth_total counter\n"));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let metrics = Metrics::new(true);
+        metrics.connect_completed(Duration::from_millis(3));
+        metrics.connect_completed(Duration::from_millis(40));
+        metrics.connect_completed(Duration::from_secs(30));
+
+        let output = metrics.render(&ConnectionLimiter::new(1));
+        assert!(output.contains("proxy_connect_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(output.contains("proxy_connect_duration_seconds_bucket{le=\"0.05\"} 2\n"));
+        assert!(output.contains("proxy_connect_duration_seconds_bucket{le=\"10\"} 2\n"));
+        assert!(output.contains("proxy_connect_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(output.contains("proxy_connect_duration_seconds_count 3\n"));
+        assert!(output.contains("proxy_connect_duration_seconds_sum 30.043\n"));
+    }
+
+    #[test]
+    fn test_traffic_by_user() {
+        let metrics = Metrics::new(true);
+        metrics.traffic_finished(Some("alice"), 100, 0);
+        metrics.traffic_finished(Some("alice"), 0, 50);
+        metrics.traffic_finished(None, 7, 0);
+
+        let output = metrics.render(&ConnectionLimiter::new(1));
+        assert!(output.contains("proxy_bytes_total{user=\"alice\",direction=\"upload\"} 100\n"));
+        assert!(output.contains("proxy_bytes_total{user=\"alice\",direction=\"download\"} 50\n"));
+        assert!(output.contains("proxy_bytes_total{user=\"\",direction=\"upload\"} 7\n"));
+
+        let disabled = Metrics::new(false);
+        disabled.traffic_finished(Some("alice"), 100, 50);
+        assert!(!disabled.render(&ConnectionLimiter::new(1)).contains("user=\"alice\""));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::AuthConfig;
+use crate::auth::{Authenticator, Identity};
 use crate::error::{ProxyError, Result};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
@@ -12,7 +12,9 @@ const AUTH_FAILURE: u8 = 0x01;
 ///
 /// 客户端发送: [VER(1) | ULEN(1) | UNAME(1-255) | PLEN(1) | PASSWD(1-255)]
 /// 服务器响应: [VER(1) | STATUS(1)]
-pub async fn authenticate(stream: &mut TcpStream, config: &AuthConfig) -> Result<()> {
+///
+/// 认证成功时返回用户身份
+pub async fn authenticate(stream: &mut TcpStream, authenticator: &dyn Authenticator) -> Result<Identity> {
     // 读取认证版本
     let version = stream.read_u8().await?;
     if version != USERNAME_PASSWORD_VERSION {
@@ -34,21 +36,23 @@ pub async fn authenticate(stream: &mut TcpStream, config: &AuthConfig) -> Result
     debug!("Authentication attempt for user: {}", username);
 
     // 验证用户名和密码
-    let authenticated = config.users.iter().any(|user| {
-        user.username == username && user.password == password
-    });
+    let client_ip = stream.peer_addr()?.ip();
+    let result = authenticator.authenticate(&username, &password, client_ip).await;
 
     // 发送认证结果
     stream.write_u8(USERNAME_PASSWORD_VERSION).await?;
-    if authenticated {
-        stream.write_u8(AUTH_SUCCESS).await?;
-        stream.flush().await?;
-        debug!("Authentication successful for user: {}", username);
-        Ok(())
-    } else {
-        stream.write_u8(AUTH_FAILURE).await?;
-        stream.flush().await?;
-        warn!("Authentication failed for user: {}", username);
-        Err(ProxyError::AuthFailed)
+    match result {
+        Ok(identity) => {
+            stream.write_u8(AUTH_SUCCESS).await?;
+            stream.flush().await?;
+            debug!("Authentication successful for user: {}", identity.username);
+            Ok(identity)
+        }
+        Err(e) => {
+            stream.write_u8(AUTH_FAILURE).await?;
+            stream.flush().await?;
+            warn!("Authentication failed for user: {}", username);
+            Err(e)
+        }
     }
 }
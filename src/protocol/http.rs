@@ -0,0 +1,346 @@
+use crate::error::{ProxyError, Result};
+use crate::protocol::{Address, MAX_DOMAIN_LEN};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::trace;
+
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const HEAD_TERMINATOR: &[u8] = b"\r\n\r\n";
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+];
+
+/// HTTP 请求头
+#[derive(Debug, Clone)]
+pub struct HttpRequestHead {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpRequestHead {
+    /// 按名称查找请求头（不区分大小写）
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// 判断首字节是否可能是 HTTP 方法
+pub fn is_http_method_byte(byte: u8) -> bool {
+    byte.is_ascii_uppercase()
+}
+
+/// 在限定时间内读取 HTTP 请求头，`limit` 为 0 时不限时
+pub async fn read_request_head_within<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    limit: Duration,
+) -> Result<(HttpRequestHead, Vec<u8>)> {
+    if limit.is_zero() {
+        return read_request_head(reader).await;
+    }
+    tokio::time::timeout(limit, read_request_head(reader))
+        .await
+        .map_err(|_| ProxyError::Timeout)?
+}
+
+/// 读取 HTTP 请求头
+///
+/// 返回解析后的请求头以及已读入的剩余数据（请求体的开头部分）
+pub async fn read_request_head<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(HttpRequestHead, Vec<u8>)> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+
+    let head_end = loop {
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(ProxyError::Protocol("Unexpected EOF in HTTP request head".to_string()));
+        }
+
+        let search_from = buf.len().saturating_sub(HEAD_TERMINATOR.len() - 1);
+        buf.extend_from_slice(&chunk[..n]);
+
+        if let Some(pos) = buf[search_from..]
+            .windows(HEAD_TERMINATOR.len())
+            .position(|window| window == HEAD_TERMINATOR)
+        {
+            break search_from + pos + HEAD_TERMINATOR.len();
+        }
+
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err(ProxyError::Protocol("HTTP request head too large".to_string()));
+        }
+    };
+
+    let head = parse_request_head(&buf[..head_end])?;
+    let remaining = buf[head_end..].to_vec();
+
+    trace!("Parsed HTTP request - {} {} {}", head.method, head.target, head.version);
+
+    Ok((head, remaining))
+}
+
+/// 解析请求行和请求头
+fn parse_request_head(bytes: &[u8]) -> Result<HttpRequestHead> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|_| ProxyError::Protocol("HTTP request head is not valid UTF-8".to_string()))?;
+    let mut lines = text.split("\r\n");
+
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None)
+            if !method.is_empty() && !target.is_empty() && version.starts_with("HTTP/") =>
+        {
+            (method.to_string(), target.to_string(), version.to_string())
+        }
+        _ => {
+            return Err(ProxyError::Protocol(format!("Invalid HTTP request line: {}", request_line)));
+        }
+    };
+
+    let mut headers = Vec::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| ProxyError::Protocol(format!("Invalid HTTP header: {}", line)))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(HttpRequestHead {
+        method,
+        target,
+        version,
+        headers,
+    })
+}
+
+/// 解析 "host:port" 形式的地址
+pub fn parse_authority(authority: &str, default_port: u16) -> Result<Address> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        // [IPv6]:port
+        let (host, rest) = rest.split_once(']').ok_or(ProxyError::InvalidAddress)?;
+        let port = match rest.strip_prefix(':') {
+            Some(port) => port.parse().map_err(|_| ProxyError::InvalidAddress)?,
+            None if rest.is_empty() => default_port,
+            None => return Err(ProxyError::InvalidAddress),
+        };
+        (host, port)
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| ProxyError::InvalidAddress)?),
+            None => (authority, default_port),
+        }
+    };
+
+    if host.is_empty() || host.len() > MAX_DOMAIN_LEN {
+        return Err(ProxyError::InvalidAddress);
+    }
+
+    Ok(match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => Address::Ipv4(ip, port),
+        Ok(IpAddr::V6(ip)) => Address::Ipv6(ip, port),
+        Err(_) => Address::Domain(host.to_string(), port),
+    })
+}
+
+/// 解析绝对 URI，返回目标地址和 origin-form 路径
+///
+/// 只支持 http:// 协议
+pub fn parse_absolute_uri(uri: &str) -> Result<(Address, String)> {
+    let rest = uri
+        .get(..7)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+        .map(|_| &uri[7..])
+        .ok_or_else(|| ProxyError::Protocol(format!("Unsupported URI: {}", uri)))?;
+
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(pos) if rest[pos..].starts_with('?') => (&rest[..pos], format!("/{}", &rest[pos..])),
+        Some(pos) => (&rest[..pos], rest[pos..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+
+    // 忽略 userinfo
+    let authority = authority.rsplit('@').next().unwrap_or(authority);
+
+    Ok((parse_authority(authority, 80)?, path))
+}
+
+/// 解析 Basic 认证头，返回用户名和密码
+pub fn parse_basic_auth(value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = BASE64.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_string(), password.to_string()))
+}
+
+/// 构造转发给目标服务器的请求头
+///
+/// 请求行改写为 origin-form，去掉逐跳头并强制 `Connection: close`
+pub fn forward_request_head(head: &HttpRequestHead, path: &str) -> Vec<u8> {
+    let mut request = format!("{} {} {}\r\n", head.method, path, head.version);
+    for (name, value) in &head.headers {
+        if HOP_BY_HOP_HEADERS.iter().any(|hop| name.eq_ignore_ascii_case(hop)) {
+            continue;
+        }
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    request.into_bytes()
+}
+
+/// 将连接错误映射为 HTTP 状态码
+pub fn error_status(error: &ProxyError) -> (u16, &'static str) {
+    match error {
+        ProxyError::ConnectionNotAllowed => (403, "Forbidden"),
+        ProxyError::Timeout => (504, "Gateway Timeout"),
+        _ => (502, "Bad Gateway"),
+    }
+}
+
+/// 发送 HTTP 响应（无响应体）
+pub async fn send_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: u16,
+    reason: &str,
+    headers: &[(&str, &str)],
+) -> Result<()> {
+    trace!("Sending HTTP response: {} {}", status, reason);
+
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason);
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if status != 200 {
+        response.push_str("Content-Length: 0\r\nConnection: close\r\n");
+    }
+    response.push_str("\r\n");
+
+    writer.write_all(response.as_bytes()).await?;
+    writer.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[tokio::test]
+    async fn test_read_request_head() {
+        let mut reader: &[u8] =
+            b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\nProxy-Connection: keep-alive\r\n\r\nhello";
+        let (head, remaining) = read_request_head(&mut reader).await.unwrap();
+
+        assert_eq!(head.method, "CONNECT");
+        assert_eq!(head.target, "example.com:443");
+        assert_eq!(head.version, "HTTP/1.1");
+        assert_eq!(head.header("host"), Some("example.com:443"));
+        assert_eq!(head.header("PROXY-CONNECTION"), Some("keep-alive"));
+        assert_eq!(remaining, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_read_request_head_invalid() {
+        let mut reader: &[u8] = b"GARBAGE\r\n\r\n";
+        assert!(read_request_head(&mut reader).await.is_err());
+
+        let mut reader: &[u8] = b"GET / HTTP/1.1\r\nHost: x";
+        assert!(read_request_head(&mut reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_request_head_within() {
+        let (mut client, _server) = tokio::io::duplex(64);
+        let result = read_request_head_within(&mut client, Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(ProxyError::Timeout)));
+
+        let mut reader: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+        let (head, _) = read_request_head_within(&mut reader, Duration::ZERO).await.unwrap();
+        assert_eq!(head.target, "/");
+    }
+
+    #[test]
+    fn test_parse_authority() {
+        assert_eq!(
+            parse_authority("example.com:8443", 443).unwrap(),
+            Address::Domain("example.com".to_string(), 8443)
+        );
+        assert_eq!(
+            parse_authority("10.0.0.1", 80).unwrap(),
+            Address::Ipv4(Ipv4Addr::new(10, 0, 0, 1), 80)
+        );
+        assert_eq!(
+            parse_authority("[::1]:8080", 80).unwrap(),
+            Address::Ipv6(Ipv6Addr::LOCALHOST, 8080)
+        );
+        assert!(parse_authority("example.com:http", 80).is_err());
+        assert!(parse_authority(":80", 80).is_err());
+        assert!(parse_authority(&format!("{}:443", "a".repeat(MAX_DOMAIN_LEN)), 443).is_ok());
+        assert!(parse_authority(&format!("{}:443", "a".repeat(MAX_DOMAIN_LEN + 1)), 443).is_err());
+    }
+
+    #[test]
+    fn test_parse_absolute_uri() {
+        let (address, path) = parse_absolute_uri("http://example.com/index.html?q=1").unwrap();
+        assert_eq!(address, Address::Domain("example.com".to_string(), 80));
+        assert_eq!(path, "/index.html?q=1");
+
+        let (address, path) = parse_absolute_uri("HTTP://127.0.0.1:8080").unwrap();
+        assert_eq!(address, Address::Ipv4(Ipv4Addr::LOCALHOST, 8080));
+        assert_eq!(path, "/");
+
+        let (_, path) = parse_absolute_uri("http://example.com?q=1").unwrap();
+        assert_eq!(path, "/?q=1");
+
+        assert!(parse_absolute_uri("https://example.com/").is_err());
+        assert!(parse_absolute_uri("/relative").is_err());
+    }
+
+    #[test]
+    fn test_forward_request_head() {
+        let head = HttpRequestHead {
+            method: "GET".to_string(),
+            target: "http://example.com/a".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: vec![
+                ("Host".to_string(), "example.com".to_string()),
+                ("Proxy-Authorization".to_string(), "Basic eDp5".to_string()),
+                ("proxy-connection".to_string(), "keep-alive".to_string()),
+                ("Accept".to_string(), "*/*".to_string()),
+            ],
+        };
+
+        assert_eq!(
+            forward_request_head(&head, "/a"),
+            b"GET /a HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_basic_auth() {
+        // "alice:secret"
+        assert_eq!(
+            parse_basic_auth("Basic YWxpY2U6c2VjcmV0"),
+            Some(("alice".to_string(), "secret".to_string()))
+        );
+        assert_eq!(parse_basic_auth("Bearer YWxpY2U6c2VjcmV0"), None);
+        assert_eq!(parse_basic_auth("Basic !!!"), None);
+    }
+}
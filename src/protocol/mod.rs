@@ -1,13 +1,27 @@
 pub mod handshake;
 pub mod auth;
+pub mod http;
 pub mod request;
 pub mod response;
+pub mod socks4;
+pub mod udp;
 
-use std::net::{Ipv4Addr, Ipv6Addr};
+use crate::error::{ProxyError, Result};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::net::TcpStream;
 
 // SOCKS5 协议常量
 pub const SOCKS_VERSION: u8 = 0x05;
 
+// 地址类型 (ATYP)
+pub const ATYP_IPV4: u8 = 0x01;
+pub const ATYP_DOMAIN: u8 = 0x03;
+pub const ATYP_IPV6: u8 = 0x04;
+
+/// 域名最大长度，SOCKS5 用一个字节表示域名长度
+pub const MAX_DOMAIN_LEN: usize = 255;
+
 // 认证方法
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AuthMethod {
@@ -47,7 +61,7 @@ impl Command {
 }
 
 // 地址类型
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Address {
     Ipv4(Ipv4Addr, u16),
     Ipv6(Ipv6Addr, u16),
@@ -63,11 +77,103 @@ impl Address {
         }
     }
 
-    pub fn to_string(&self) -> String {
+    /// 主机部分（IP 或小写域名），不含端口
+    pub fn host(&self) -> String {
+        match self {
+            Address::Ipv4(ip, _) => ip.to_string(),
+            Address::Ipv6(ip, _) => ip.to_string(),
+            Address::Domain(domain, _) => domain.to_ascii_lowercase(),
+        }
+    }
+
+    /// 按 SOCKS5 格式编码地址: [ATYP(1) | ADDR(变长) | PORT(2)]
+    ///
+    /// 域名超过 255 字节时无法编码，返回错误
+    pub fn write_to(&self, buf: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Address::Ipv4(ip, port) => {
+                buf.push(ATYP_IPV4);
+                buf.extend_from_slice(&ip.octets());
+                buf.extend_from_slice(&port.to_be_bytes());
+            }
+            Address::Ipv6(ip, port) => {
+                buf.push(ATYP_IPV6);
+                buf.extend_from_slice(&ip.octets());
+                buf.extend_from_slice(&port.to_be_bytes());
+            }
+            Address::Domain(domain, port) => {
+                let len = u8::try_from(domain.len()).map_err(|_| ProxyError::InvalidAddress)?;
+                buf.push(ATYP_DOMAIN);
+                buf.push(len);
+                buf.extend_from_slice(domain.as_bytes());
+                buf.extend_from_slice(&port.to_be_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// 从 SOCKS5 格式的字节中解码地址: [ATYP(1) | ADDR(变长) | PORT(2)]
+    ///
+    /// 返回地址和其后剩余的字节
+    pub fn read_from(buf: &[u8]) -> Result<(Address, &[u8])> {
+        let (&atyp, rest) = buf.split_first().ok_or_else(truncated)?;
+        let (host, rest) = match atyp {
+            ATYP_IPV4 => (4, rest),
+            ATYP_IPV6 => (16, rest),
+            ATYP_DOMAIN => {
+                let (&len, rest) = rest.split_first().ok_or_else(truncated)?;
+                (len as usize, rest)
+            }
+            _ => return Err(ProxyError::UnsupportedAddressType(atyp)),
+        };
+        if rest.len() < host + 2 {
+            return Err(truncated());
+        }
+
+        let (host, rest) = rest.split_at(host);
+        let (port, rest) = rest.split_at(2);
+        let port = u16::from_be_bytes([port[0], port[1]]);
+        let address = match atyp {
+            ATYP_IPV4 => Address::Ipv4(Ipv4Addr::from(<[u8; 4]>::try_from(host).unwrap()), port),
+            ATYP_IPV6 => Address::Ipv6(Ipv6Addr::from(<[u8; 16]>::try_from(host).unwrap()), port),
+            _ => {
+                let domain = String::from_utf8(host.to_vec()).map_err(|_| ProxyError::InvalidAddress)?;
+                Address::Domain(domain, port)
+            }
+        };
+
+        Ok((address, rest))
+    }
+
+    /// 如果是 IP 地址则转换为 SocketAddr，域名返回 None
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
         match self {
-            Address::Ipv4(ip, port) => format!("{}:{}", ip, port),
-            Address::Ipv6(ip, port) => format!("[{}]:{}", ip, port),
-            Address::Domain(domain, port) => format!("{}:{}", domain, port),
+            Address::Ipv4(ip, port) => Some(SocketAddr::new((*ip).into(), *port)),
+            Address::Ipv6(ip, port) => Some(SocketAddr::new((*ip).into(), *port)),
+            Address::Domain(_, _) => None,
+        }
+    }
+}
+
+fn truncated() -> ProxyError {
+    ProxyError::Protocol("Truncated address".to_string())
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Ipv4(ip, port) => write!(f, "{}:{}", ip, port),
+            Address::Ipv6(ip, port) => write!(f, "[{}]:{}", ip, port),
+            Address::Domain(domain, port) => write!(f, "{}:{}", domain, port),
+        }
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(addr: SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(addr) => Address::Ipv4(*addr.ip(), addr.port()),
+            SocketAddr::V6(addr) => Address::Ipv6(*addr.ip(), addr.port()),
         }
     }
 }
@@ -86,6 +192,65 @@ pub enum Reply {
     AddressTypeNotSupported = 0x08,
 }
 
+impl Reply {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(Reply::Succeeded),
+            0x01 => Some(Reply::GeneralFailure),
+            0x02 => Some(Reply::ConnectionNotAllowed),
+            0x03 => Some(Reply::NetworkUnreachable),
+            0x04 => Some(Reply::HostUnreachable),
+            0x05 => Some(Reply::ConnectionRefused),
+            0x06 => Some(Reply::TtlExpired),
+            0x07 => Some(Reply::CommandNotSupported),
+            0x08 => Some(Reply::AddressTypeNotSupported),
+            _ => None,
+        }
+    }
+}
+
+impl From<&ProxyError> for Reply {
+    fn from(error: &ProxyError) -> Self {
+        match error {
+            ProxyError::ConnectionRefused => Reply::ConnectionRefused,
+            ProxyError::ConnectionNotAllowed => Reply::ConnectionNotAllowed,
+            ProxyError::HostUnreachable => Reply::HostUnreachable,
+            ProxyError::NetworkUnreachable => Reply::NetworkUnreachable,
+            ProxyError::Timeout => Reply::TtlExpired,
+            ProxyError::UnsupportedCommand(_) => Reply::CommandNotSupported,
+            ProxyError::UnsupportedAddressType(_) => Reply::AddressTypeNotSupported,
+            _ => Reply::GeneralFailure,
+        }
+    }
+}
+
+// 客户端使用的 SOCKS 协议版本，决定响应格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocksVersion {
+    V4,
+    V5,
+}
+
+impl SocksVersion {
+    /// 按协议版本发送成功响应
+    pub async fn send_success(self, stream: &mut TcpStream, bind_address: &Address) -> Result<()> {
+        match self {
+            SocksVersion::V4 => socks4::send_reply(stream, socks4::REQUEST_GRANTED, bind_address).await,
+            SocksVersion::V5 => response::send_success(stream, bind_address).await,
+        }
+    }
+
+    /// 按协议版本发送失败响应
+    pub async fn send_failure(self, stream: &mut TcpStream, reply: Reply) -> Result<()> {
+        match self {
+            SocksVersion::V4 => {
+                socks4::send_reply(stream, socks4::reply_code(reply), &response::unspecified_address()).await
+            }
+            SocksVersion::V5 => response::send_failure(stream, reply).await,
+        }
+    }
+}
+
 // SOCKS5 请求
 #[derive(Debug, Clone)]
 pub struct Request {
@@ -125,6 +290,7 @@ mod tests {
         let addr = Address::Ipv6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 8080);
         assert_eq!(addr.port(), 8080);
         assert_eq!(addr.to_string(), "[::1]:8080");
+        assert_eq!(addr.host(), "::1");
     }
 
     #[test]
@@ -132,6 +298,52 @@ mod tests {
         let addr = Address::Domain("example.com".to_string(), 443);
         assert_eq!(addr.port(), 443);
         assert_eq!(addr.to_string(), "example.com:443");
+        assert_eq!(Address::Domain("Example.COM".to_string(), 443).host(), "example.com");
+    }
+
+    #[test]
+    fn test_address_write_to() {
+        let mut buf = Vec::new();
+        Address::Ipv4(Ipv4Addr::new(10, 0, 0, 1), 53).write_to(&mut buf).unwrap();
+        assert_eq!(buf, vec![ATYP_IPV4, 10, 0, 0, 1, 0, 53]);
+
+        let mut buf = Vec::new();
+        Address::Domain("a.io".to_string(), 443).write_to(&mut buf).unwrap();
+        assert_eq!(buf, vec![ATYP_DOMAIN, 4, b'a', b'.', b'i', b'o', 0x01, 0xBB]);
+
+        // 超长域名不能截断长度后写出
+        let mut buf = Vec::new();
+        let long = Address::Domain("a".repeat(MAX_DOMAIN_LEN + 1), 443);
+        assert!(matches!(long.write_to(&mut buf), Err(ProxyError::InvalidAddress)));
+        assert!(Address::Domain("a".repeat(MAX_DOMAIN_LEN), 443).write_to(&mut buf).is_ok());
+    }
+
+    #[test]
+    fn test_address_read_from() {
+        for address in [
+            Address::Ipv4(Ipv4Addr::new(10, 0, 0, 1), 53),
+            Address::Ipv6(Ipv6Addr::LOCALHOST, 8080),
+            Address::Domain("example.com".to_string(), 443),
+        ] {
+            let mut buf = Vec::new();
+            address.write_to(&mut buf).unwrap();
+            buf.extend_from_slice(b"rest");
+            let (decoded, rest) = Address::read_from(&buf).unwrap();
+            assert_eq!(decoded, address);
+            assert_eq!(rest, b"rest");
+        }
+
+        assert!(Address::read_from(&[]).is_err());
+        assert!(Address::read_from(&[ATYP_IPV4, 127, 0]).is_err());
+        assert!(Address::read_from(&[ATYP_DOMAIN, 5, b'a', 0, 80]).is_err());
+        assert!(matches!(Address::read_from(&[0x02, 0, 0]), Err(ProxyError::UnsupportedAddressType(0x02))));
+    }
+
+    #[test]
+    fn test_address_from_socket_addr() {
+        let addr: SocketAddr = "[::1]:8080".parse().unwrap();
+        assert_eq!(Address::from(addr), Address::Ipv6(Ipv6Addr::LOCALHOST, 8080));
+        assert_eq!(Address::from(addr).socket_addr(), Some(addr));
     }
 
     #[test]
@@ -152,4 +364,21 @@ mod tests {
         assert_eq!(Reply::ConnectionRefused as u8, 0x05);
         assert_eq!(Reply::CommandNotSupported as u8, 0x07);
     }
+
+    #[test]
+    fn test_reply_from_error() {
+        assert_eq!(Reply::from(&ProxyError::ConnectionRefused), Reply::ConnectionRefused);
+        assert_eq!(Reply::from(&ProxyError::Timeout), Reply::TtlExpired);
+        assert_eq!(Reply::from(&ProxyError::NetworkUnreachable), Reply::NetworkUnreachable);
+        assert_eq!(Reply::from(&ProxyError::ConnectionNotAllowed), Reply::ConnectionNotAllowed);
+        assert_eq!(Reply::from(&ProxyError::AuthFailed), Reply::GeneralFailure);
+    }
+
+    #[test]
+    fn test_reply_from_u8() {
+        assert_eq!(Reply::from_u8(0x00), Some(Reply::Succeeded));
+        assert_eq!(Reply::from_u8(0x05), Some(Reply::ConnectionRefused));
+        assert_eq!(Reply::from_u8(0x08), Some(Reply::AddressTypeNotSupported));
+        assert_eq!(Reply::from_u8(0x09), None);
+    }
 }
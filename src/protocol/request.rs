@@ -1,14 +1,9 @@
 use crate::error::{ProxyError, Result};
-use crate::protocol::{Address, Command, Request, SOCKS_VERSION};
-use std::net::{Ipv4Addr, Ipv6Addr};
-use tokio::io::{AsyncReadExt};
+use crate::protocol::{Address, Command, Request, ATYP_DOMAIN, ATYP_IPV4, ATYP_IPV6, SOCKS_VERSION};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::net::TcpStream;
 use tracing::trace;
 
-const ATYP_IPV4: u8 = 0x01;
-const ATYP_DOMAIN: u8 = 0x03;
-const ATYP_IPV6: u8 = 0x04;
-
 /// 解析 SOCKS5 请求
 ///
 /// 格式: [VER(1) | CMD(1) | RSV(1) | ATYP(1) | DST.ADDR(变长) | DST.PORT(2)]
@@ -27,38 +22,35 @@ pub async fn parse_request(stream: &mut TcpStream) -> Result<Request> {
     // 读取保留字节
     let _rsv = stream.read_u8().await?;
 
-    // 读取地址类型
-    let atyp = stream.read_u8().await?;
+    // 解析目标地址
+    let address = read_address(stream).await?;
 
-    // 根据地址类型解析目标地址
-    let address = match atyp {
-        ATYP_IPV4 => {
-            let mut octets = [0u8; 4];
-            stream.read_exact(&mut octets).await?;
-            let ip = Ipv4Addr::from(octets);
-            let port = stream.read_u16().await?;
-            Address::Ipv4(ip, port)
-        }
-        ATYP_IPV6 => {
-            let mut octets = [0u8; 16];
-            stream.read_exact(&mut octets).await?;
-            let ip = Ipv6Addr::from(octets);
-            let port = stream.read_u16().await?;
-            Address::Ipv6(ip, port)
-        }
+    trace!("Parsed request - Command: {:?}, Address: {}", command, address);
+
+    Ok(Request { command, address })
+}
+
+/// 读取地址
+///
+/// 格式: [ATYP(1) | DST.ADDR(变长) | DST.PORT(2)]
+pub async fn read_address<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Address> {
+    // 先读出完整的地址字节，再按同一格式解码
+    let mut buf = vec![reader.read_u8().await?];
+    let remaining = match buf[0] {
+        ATYP_IPV4 => 4 + 2,
+        ATYP_IPV6 => 16 + 2,
         ATYP_DOMAIN => {
-            let domain_len = stream.read_u8().await?;
-            let mut domain_bytes = vec![0u8; domain_len as usize];
-            stream.read_exact(&mut domain_bytes).await?;
-            let domain = String::from_utf8(domain_bytes)
-                .map_err(|_| ProxyError::InvalidAddress)?;
-            let port = stream.read_u16().await?;
-            Address::Domain(domain, port)
+            let domain_len = reader.read_u8().await?;
+            buf.push(domain_len);
+            domain_len as usize + 2
         }
-        _ => return Err(ProxyError::UnsupportedAddressType(atyp)),
+        atyp => return Err(ProxyError::UnsupportedAddressType(atyp)),
     };
 
-    trace!("Parsed request - Command: {:?}, Address: {}", command, address.to_string());
+    let start = buf.len();
+    buf.resize(start + remaining, 0);
+    reader.read_exact(&mut buf[start..]).await?;
 
-    Ok(Request { command, address })
+    let (address, _) = Address::read_from(&buf)?;
+    Ok(address)
 }
@@ -1,58 +1,108 @@
 use crate::error::Result;
 use crate::protocol::{Address, Reply, SOCKS_VERSION};
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tracing::trace;
 
 const RSV: u8 = 0x00;
-const ATYP_IPV4: u8 = 0x01;
 
 /// 发送成功响应
 ///
 /// 格式: [VER(1) | REP(1) | RSV(1) | ATYP(1) | BND.ADDR(变长) | BND.PORT(2)]
-pub async fn send_success(stream: &mut TcpStream, _address: &Address) -> Result<()> {
-    send_reply(stream, Reply::Succeeded).await
+pub async fn send_success(stream: &mut TcpStream, bind_address: &Address) -> Result<()> {
+    send_reply(stream, Reply::Succeeded, bind_address).await
 }
 
 /// 发送失败响应
 pub async fn send_failure(stream: &mut TcpStream, reply: Reply) -> Result<()> {
-    send_reply(stream, reply).await
+    send_reply(stream, reply, &unspecified_address()).await
 }
 
 /// 发送响应
-async fn send_reply(stream: &mut TcpStream, reply: Reply) -> Result<()> {
-    trace!("Sending reply: {:?}", reply);
+async fn send_reply(stream: &mut TcpStream, reply: Reply, bind_address: &Address) -> Result<()> {
+    trace!("Sending reply: {:?}, bind address: {}", reply, bind_address);
 
-    // 写入版本号
-    stream.write_u8(SOCKS_VERSION).await?;
+    stream.write_all(&encode_reply(reply, bind_address)?).await?;
+    stream.flush().await?;
 
-    // 写入响应码
-    stream.write_u8(reply as u8).await?;
+    Ok(())
+}
 
-    // 写入保留字节
-    stream.write_u8(RSV).await?;
+/// 编码响应
+fn encode_reply(reply: Reply, bind_address: &Address) -> Result<Vec<u8>> {
+    // [VER | REP | RSV]
+    let mut buf = vec![SOCKS_VERSION, reply as u8, RSV];
 
-    // 写入绑定地址（使用 0.0.0.0:0 作为占位符）
-    stream.write_u8(ATYP_IPV4).await?;
-    stream.write_all(&[0, 0, 0, 0]).await?;
-    stream.write_u16(0).await?;
+    // 写入绑定地址
+    bind_address.write_to(&mut buf)?;
 
-    stream.flush().await?;
+    Ok(buf)
+}
 
-    Ok(())
+/// 占位绑定地址 0.0.0.0:0
+pub fn unspecified_address() -> Address {
+    Address::Ipv4(Ipv4Addr::UNSPECIFIED, 0)
 }
 
-/// 从 SocketAddr 获取绑定地址信息
-pub fn get_bind_address(addr: &SocketAddr) -> (Vec<u8>, u16) {
+/// 从 SocketAddr 获取绑定地址
+///
+/// IPv4 映射的 IPv6 地址（双栈 socket）按 IPv4 返回
+pub fn bind_address(addr: &SocketAddr) -> Address {
     match addr {
-        SocketAddr::V4(addr) => {
-            let octets = addr.ip().octets();
-            (octets.to_vec(), addr.port())
-        }
-        SocketAddr::V6(addr) => {
-            let octets = addr.ip().octets();
-            (octets.to_vec(), addr.port())
-        }
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => Address::Ipv4(ip, v6.port()),
+            None => Address::Ipv6(*v6.ip(), v6.port()),
+        },
+        SocketAddr::V4(_) => Address::from(*addr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ATYP_DOMAIN, ATYP_IPV4, ATYP_IPV6};
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn test_encode_reply_ipv4() {
+        let addr: SocketAddr = "192.168.1.10:40000".parse().unwrap();
+        let buf = encode_reply(Reply::Succeeded, &bind_address(&addr)).unwrap();
+        assert_eq!(buf, vec![0x05, 0x00, 0x00, ATYP_IPV4, 192, 168, 1, 10, 0x9C, 0x40]);
+    }
+
+    #[test]
+    fn test_encode_reply_ipv6() {
+        let addr: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        let buf = encode_reply(Reply::Succeeded, &bind_address(&addr)).unwrap();
+
+        let mut expected = vec![0x05, 0x00, 0x00, ATYP_IPV6];
+        expected.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        expected.extend_from_slice(&[0x01, 0xBB]);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_encode_reply_ipv4_mapped() {
+        let addr: SocketAddr = "[::ffff:10.0.0.1]:80".parse().unwrap();
+        let buf = encode_reply(Reply::Succeeded, &bind_address(&addr)).unwrap();
+        assert_eq!(buf, vec![0x05, 0x00, 0x00, ATYP_IPV4, 10, 0, 0, 1, 0x00, 0x50]);
+    }
+
+    #[test]
+    fn test_encode_reply_domain() {
+        let addr = Address::Domain("proxy.local".to_string(), 1080);
+        let buf = encode_reply(Reply::Succeeded, &addr).unwrap();
+
+        let mut expected = vec![0x05, 0x00, 0x00, ATYP_DOMAIN, 11];
+        expected.extend_from_slice(b"proxy.local");
+        expected.extend_from_slice(&[0x04, 0x38]);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_encode_reply_failure() {
+        let buf = encode_reply(Reply::HostUnreachable, &unspecified_address()).unwrap();
+        assert_eq!(buf, vec![0x05, 0x04, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0]);
     }
 }
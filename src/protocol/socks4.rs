@@ -0,0 +1,156 @@
+use crate::error::{ProxyError, Result};
+use crate::protocol::{Address, Command, Reply};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::trace;
+
+// SOCKS4 协议常量
+pub const SOCKS4_VERSION: u8 = 0x04;
+const REPLY_VERSION: u8 = 0x00;
+
+// SOCKS4 响应码
+pub const REQUEST_GRANTED: u8 = 0x5A;
+pub const REQUEST_REJECTED: u8 = 0x5B;
+pub const REQUEST_REJECTED_USERID: u8 = 0x5D;
+
+const MAX_FIELD_LEN: usize = 255;
+
+/// SOCKS4/4a 请求
+#[derive(Debug, Clone)]
+pub struct Socks4Request {
+    pub command: Command,
+    pub address: Address,
+    pub user_id: String,
+}
+
+/// 解析 SOCKS4/4a 请求
+///
+/// 格式: [VN(1) | CD(1) | DSTPORT(2) | DSTIP(4) | USERID(变长) | NULL(1)]
+/// SOCKS4a: DSTIP 为 0.0.0.x (x != 0) 时，USERID 之后追加 [DOMAIN(变长) | NULL(1)]
+pub async fn parse_request(stream: &mut TcpStream) -> Result<Socks4Request> {
+    // 读取版本号
+    let version = stream.read_u8().await?;
+    if version != SOCKS4_VERSION {
+        return Err(ProxyError::InvalidVersion(version));
+    }
+
+    // 读取命令（SOCKS4 只支持 CONNECT 和 BIND）
+    let cmd = stream.read_u8().await?;
+    let command = match Command::from_u8(cmd) {
+        Some(command @ (Command::Connect | Command::Bind)) => command,
+        _ => return Err(ProxyError::UnsupportedCommand(cmd)),
+    };
+
+    let port = stream.read_u16().await?;
+    let mut octets = [0u8; 4];
+    stream.read_exact(&mut octets).await?;
+    let ip = Ipv4Addr::from(octets);
+
+    let user_id = read_null_terminated(stream).await?;
+
+    let address = if octets[..3] == [0, 0, 0] && octets[3] != 0 {
+        let domain = read_null_terminated(stream).await?;
+        if domain.is_empty() {
+            return Err(ProxyError::InvalidAddress);
+        }
+        Address::Domain(domain, port)
+    } else {
+        Address::Ipv4(ip, port)
+    };
+
+    trace!("Parsed SOCKS4 request - Command: {:?}, Address: {}", command, address);
+
+    Ok(Socks4Request {
+        command,
+        address,
+        user_id,
+    })
+}
+
+/// 读取以 NULL 结尾的字符串
+async fn read_null_terminated<R: AsyncRead + Unpin>(reader: &mut R) -> Result<String> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = reader.read_u8().await?;
+        if byte == 0 {
+            break;
+        }
+        if bytes.len() == MAX_FIELD_LEN {
+            return Err(ProxyError::Protocol("SOCKS4 field too long".to_string()));
+        }
+        bytes.push(byte);
+    }
+
+    String::from_utf8(bytes).map_err(|_| ProxyError::InvalidAddress)
+}
+
+/// 发送 SOCKS4 响应
+///
+/// 格式: [VN(1) | CD(1) | DSTPORT(2) | DSTIP(4)]
+pub async fn send_reply(stream: &mut TcpStream, code: u8, bind_address: &Address) -> Result<()> {
+    trace!("Sending SOCKS4 reply: {:#04x}, bind address: {}", code, bind_address);
+
+    stream.write_all(&encode_reply(code, bind_address)).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// 编码 SOCKS4 响应，非 IPv4 地址以 0.0.0.0:0 代替
+fn encode_reply(code: u8, bind_address: &Address) -> Vec<u8> {
+    let (ip, port) = match bind_address.socket_addr() {
+        Some(SocketAddr::V4(addr)) => (*addr.ip(), addr.port()),
+        _ => (Ipv4Addr::UNSPECIFIED, 0),
+    };
+
+    let mut buf = vec![REPLY_VERSION, code];
+    buf.extend_from_slice(&port.to_be_bytes());
+    buf.extend_from_slice(&ip.octets());
+    buf
+}
+
+/// 将 SOCKS5 响应码映射为 SOCKS4 响应码
+pub fn reply_code(reply: Reply) -> u8 {
+    match reply {
+        Reply::Succeeded => REQUEST_GRANTED,
+        _ => REQUEST_REJECTED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_reply() {
+        let addr = Address::Ipv4(Ipv4Addr::new(10, 0, 0, 1), 8080);
+        assert_eq!(
+            encode_reply(REQUEST_GRANTED, &addr),
+            vec![0x00, 0x5A, 0x1F, 0x90, 10, 0, 0, 1]
+        );
+
+        let addr = Address::Domain("example.com".to_string(), 80);
+        assert_eq!(
+            encode_reply(REQUEST_REJECTED, &addr),
+            vec![0x00, 0x5B, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_reply_code() {
+        assert_eq!(reply_code(Reply::Succeeded), REQUEST_GRANTED);
+        assert_eq!(reply_code(Reply::ConnectionRefused), REQUEST_REJECTED);
+        assert_eq!(reply_code(Reply::TtlExpired), REQUEST_REJECTED);
+    }
+
+    #[tokio::test]
+    async fn test_read_null_terminated() {
+        let mut reader: &[u8] = b"alice\0example.com\0";
+        assert_eq!(read_null_terminated(&mut reader).await.unwrap(), "alice");
+        assert_eq!(read_null_terminated(&mut reader).await.unwrap(), "example.com");
+
+        let mut reader: &[u8] = b"unterminated";
+        assert!(read_null_terminated(&mut reader).await.is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::error::{ProxyError, Result};
+use crate::protocol::Address;
+
+const RSV: [u8; 2] = [0x00, 0x00];
+
+/// SOCKS5 UDP 数据报
+#[derive(Debug)]
+pub struct UdpPacket<'a> {
+    pub frag: u8,
+    pub address: Address,
+    pub payload: &'a [u8],
+}
+
+/// 解析 UDP 请求头
+///
+/// 格式: [RSV(2) | FRAG(1) | ATYP(1) | DST.ADDR(变长) | DST.PORT(2) | DATA(变长)]
+pub fn parse_udp_packet(datagram: &[u8]) -> Result<UdpPacket<'_>> {
+    if datagram.len() < 3 {
+        return Err(ProxyError::Protocol("UDP datagram too short".to_string()));
+    }
+
+    let frag = datagram[2];
+    let (address, payload) = Address::read_from(&datagram[3..])?;
+
+    Ok(UdpPacket {
+        frag,
+        address,
+        payload,
+    })
+}
+
+/// 封装 UDP 数据报（不分片）
+pub fn encode_udp_packet(address: &Address, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(payload.len() + 22);
+    buf.extend_from_slice(&RSV);
+    buf.push(0x00);
+    address.write_to(&mut buf)?;
+    buf.extend_from_slice(payload);
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_udp_packet_roundtrip() {
+        let address = Address::Ipv4(Ipv4Addr::new(8, 8, 8, 8), 53);
+        let datagram = encode_udp_packet(&address, b"query").unwrap();
+
+        let packet = parse_udp_packet(&datagram).unwrap();
+        assert_eq!(packet.frag, 0);
+        assert_eq!(packet.address, address);
+        assert_eq!(packet.payload, b"query");
+    }
+
+    #[test]
+    fn test_udp_packet_domain() {
+        let address = Address::Domain("example.com".to_string(), 443);
+        let datagram = encode_udp_packet(&address, b"").unwrap();
+
+        let packet = parse_udp_packet(&datagram).unwrap();
+        assert_eq!(packet.address, address);
+        assert!(packet.payload.is_empty());
+    }
+
+    #[test]
+    fn test_udp_packet_truncated() {
+        assert!(parse_udp_packet(&[0x00, 0x00]).is_err());
+        assert!(parse_udp_packet(&[0x00, 0x00, 0x00, 0x01, 127, 0]).is_err());
+        assert!(parse_udp_packet(&[0x00, 0x00, 0x00, 0x03, 5, b'a', 0, 80]).is_err());
+        assert!(parse_udp_packet(&[0x00, 0x00, 0x00, 0x02, 0, 0]).is_err());
+    }
+}
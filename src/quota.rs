@@ -0,0 +1,529 @@
+use crate::config::QuotaConfig;
+use crate::connection::bandwidth::Direction;
+use crate::connection::TrafficMeter;
+use crate::error::{ProxyError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// 用户流量用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// 统计日，自 UNIX 纪元起的天数（UTC）
+    pub day: u64,
+    pub daily_bytes: u64,
+    /// 统计月，`年 * 12 + 月 - 1`（UTC）
+    pub month: u64,
+    pub monthly_bytes: u64,
+}
+
+impl Usage {
+    /// 进入新的统计日/月时清零对应计数
+    fn roll_over(&mut self, period: Period) {
+        if self.day != period.day {
+            self.day = period.day;
+            self.daily_bytes = 0;
+        }
+        if self.month != period.month {
+            self.month = period.month;
+            self.monthly_bytes = 0;
+        }
+    }
+}
+
+/// 单个用户的用量计数
+///
+/// 同一用户的连接共享一份计数，转发时原子累计，不经过全局锁；
+/// 只有进入新的统计日/月时才加锁清零
+#[derive(Default)]
+struct UserCounter {
+    day: AtomicU64,
+    daily_bytes: AtomicU64,
+    month: AtomicU64,
+    monthly_bytes: AtomicU64,
+    roll: Mutex<()>,
+}
+
+impl UserCounter {
+    fn new(usage: Usage) -> Self {
+        Self {
+            day: AtomicU64::new(usage.day),
+            daily_bytes: AtomicU64::new(usage.daily_bytes),
+            month: AtomicU64::new(usage.month),
+            monthly_bytes: AtomicU64::new(usage.monthly_bytes),
+            roll: Mutex::new(()),
+        }
+    }
+
+    /// 当前计数，未按周期清零
+    fn snapshot(&self) -> Usage {
+        Usage {
+            day: self.day.load(Ordering::Acquire),
+            daily_bytes: self.daily_bytes.load(Ordering::Relaxed),
+            month: self.month.load(Ordering::Acquire),
+            monthly_bytes: self.monthly_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 累计流量，返回累计后的用量
+    fn add(&self, bytes: u64, period: Period) -> Usage {
+        self.roll_over(period);
+        Usage {
+            day: period.day,
+            daily_bytes: self.daily_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes,
+            month: period.month,
+            monthly_bytes: self.monthly_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes,
+        }
+    }
+
+    fn roll_over(&self, period: Period) {
+        if self.day.load(Ordering::Acquire) == period.day && self.month.load(Ordering::Acquire) == period.month {
+            return;
+        }
+
+        let _roll = self.roll.lock().unwrap();
+        if self.month.load(Ordering::Acquire) != period.month {
+            self.monthly_bytes.store(0, Ordering::Relaxed);
+            self.month.store(period.month, Ordering::Release);
+        }
+        if self.day.load(Ordering::Acquire) != period.day {
+            self.daily_bytes.store(0, Ordering::Relaxed);
+            self.day.store(period.day, Ordering::Release);
+        }
+    }
+}
+
+/// 统计周期
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Period {
+    day: u64,
+    month: u64,
+}
+
+impl Period {
+    fn now() -> Self {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        Self::from_day(secs / SECONDS_PER_DAY)
+    }
+
+    fn from_day(day: u64) -> Self {
+        let (year, month) = year_month_from_days(day);
+        Self {
+            day,
+            month: year * 12 + month - 1,
+        }
+    }
+}
+
+/// 由自 UNIX 纪元起的天数计算公历年月
+fn year_month_from_days(days: u64) -> (u64, u64) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    daily: u64,
+    monthly: u64,
+}
+
+impl Limits {
+    fn allows(&self, usage: &Usage) -> bool {
+        (self.daily == 0 || usage.daily_bytes < self.daily)
+            && (self.monthly == 0 || usage.monthly_bytes < self.monthly)
+    }
+}
+
+/// 用户流量配额
+///
+/// 统计认证用户的上下行流量合计，定期写入状态文件，重启后继续累计
+pub struct QuotaManager {
+    enabled: bool,
+    default: Limits,
+    overrides: HashMap<String, Limits>,
+    cut_live_sessions: bool,
+    state_file: PathBuf,
+    usage: Mutex<HashMap<String, Arc<UserCounter>>>,
+    dirty: AtomicBool,
+}
+
+impl QuotaManager {
+    pub fn new(config: &QuotaConfig) -> Result<Self> {
+        let default = Limits {
+            daily: config.daily_bytes,
+            monthly: config.monthly_bytes,
+        };
+        let overrides = config
+            .users
+            .iter()
+            .map(|(user, quota)| {
+                let limits = Limits {
+                    daily: quota.daily_bytes.unwrap_or(default.daily),
+                    monthly: quota.monthly_bytes.unwrap_or(default.monthly),
+                };
+                (user.clone(), limits)
+            })
+            .collect();
+
+        let usage = if config.enabled {
+            load_state(&config.state_file)?
+                .into_iter()
+                .map(|(user, usage)| (user, Arc::new(UserCounter::new(usage))))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            enabled: config.enabled,
+            default,
+            overrides,
+            cut_live_sessions: config.cut_live_sessions,
+            state_file: config.state_file.clone(),
+            usage: Mutex::new(usage),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 用户配额是否仍有剩余
+    pub fn check(&self, user: &str) -> bool {
+        self.check_at(user, Period::now())
+    }
+
+    /// 用户当前周期的用量
+    pub fn usage(&self, user: &str) -> Option<Usage> {
+        let period = Period::now();
+        self.usage.lock().unwrap().get(user).map(|counter| {
+            let mut usage = counter.snapshot();
+            usage.roll_over(period);
+            usage
+        })
+    }
+
+    /// 为用户的连接创建流量计量，未启用配额时返回 None
+    ///
+    /// 计量持有用户的计数，转发时无锁累计
+    pub fn meter(self: &Arc<Self>, user: &str) -> Option<Arc<dyn TrafficMeter>> {
+        self.enabled.then(|| {
+            Arc::new(QuotaMeter {
+                manager: self.clone(),
+                counter: self.counter(user),
+                limits: self.limits(user),
+                user: user.to_string(),
+            }) as Arc<dyn TrafficMeter>
+        })
+    }
+
+    /// 有未保存的用量时写入状态文件，文件写入放到阻塞线程池中执行
+    pub async fn save(&self) -> Result<()> {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let snapshot: HashMap<String, Usage> = self
+            .usage
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(user, counter)| (user.clone(), counter.snapshot()))
+            .collect();
+        let users = snapshot.len();
+
+        let path = self.state_file.clone();
+        let result = tokio::task::spawn_blocking(move || save_state(&path, &snapshot))
+            .await
+            .unwrap_or_else(|e| Err(ProxyError::Io(std::io::Error::other(e))));
+        if let Err(e) = result {
+            self.dirty.store(true, Ordering::Release);
+            return Err(e);
+        }
+
+        debug!("Saved quota usage for {} users to {:?}", users, self.state_file);
+        Ok(())
+    }
+
+    /// 用户的用量计数，不存在时创建
+    fn counter(&self, user: &str) -> Arc<UserCounter> {
+        let mut map = self.usage.lock().unwrap();
+        match map.get(user) {
+            Some(counter) => counter.clone(),
+            None => map.entry(user.to_string()).or_default().clone(),
+        }
+    }
+
+    fn mark_dirty(&self) {
+        // 已标记时只读不写，避免所有连接反复写同一缓存行
+        if !self.dirty.load(Ordering::Relaxed) {
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    fn limits(&self, user: &str) -> Limits {
+        self.overrides.get(user).copied().unwrap_or(self.default)
+    }
+
+    fn check_at(&self, user: &str, period: Period) -> bool {
+        if !self.enabled {
+            return true;
+        }
+
+        let mut usage = self
+            .usage
+            .lock()
+            .unwrap()
+            .get(user)
+            .map(|counter| counter.snapshot())
+            .unwrap_or_default();
+        usage.roll_over(period);
+        self.limits(user).allows(&usage)
+    }
+
+    /// 累计用户流量，返回累计后配额是否仍有剩余；连接中的流量经 `meter` 累计
+    #[cfg(test)]
+    fn record(&self, user: &str, bytes: u64) -> bool {
+        self.record_at(user, bytes, Period::now())
+    }
+
+    #[cfg(test)]
+    fn record_at(&self, user: &str, bytes: u64, period: Period) -> bool {
+        if !self.enabled {
+            return true;
+        }
+
+        let usage = self.counter(user).add(bytes, period);
+        self.mark_dirty();
+        self.limits(user).allows(&usage)
+    }
+}
+
+/// 定期保存用量
+pub async fn flush_periodically(manager: Arc<QuotaManager>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        if let Err(e) = manager.save().await {
+            warn!("Failed to save quota usage: {}", e);
+        }
+    }
+}
+
+/// 单个连接的流量计量
+struct QuotaMeter {
+    manager: Arc<QuotaManager>,
+    counter: Arc<UserCounter>,
+    limits: Limits,
+    user: String,
+}
+
+impl TrafficMeter for QuotaMeter {
+    fn record(&self, _direction: Direction, bytes: u64) -> bool {
+        let usage = self.counter.add(bytes, Period::now());
+        self.manager.mark_dirty();
+
+        let allowed = self.limits.allows(&usage);
+        if !allowed && self.manager.cut_live_sessions {
+            info!("Cutting session of user {}: traffic quota exhausted", self.user);
+            return false;
+        }
+        true
+    }
+}
+
+fn load_state(path: &Path) -> Result<HashMap<String, Usage>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => {
+            return Err(ProxyError::Config(format!("Failed to read quota state {:?}: {}", path, e)));
+        }
+    };
+
+    serde_json::from_str(&content)
+        .map_err(|e| ProxyError::Config(format!("Invalid quota state {:?}: {}", path, e)))
+}
+
+/// 先写临时文件再重命名，避免中途崩溃留下不完整的文件
+fn save_state(path: &Path, usage: &HashMap<String, Usage>) -> Result<()> {
+    let content = serde_json::to_string_pretty(usage)
+        .map_err(|e| ProxyError::Config(format!("Failed to serialize quota state: {}", e)))?;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserQuota;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("yun-socket-proxy-{}-{}.json", name, std::process::id()))
+    }
+
+    fn manager(path: PathBuf) -> QuotaManager {
+        let mut users = HashMap::new();
+        users.insert(
+            "vip".to_string(),
+            UserQuota {
+                daily_bytes: Some(0),
+                monthly_bytes: None,
+            },
+        );
+
+        QuotaManager::new(&QuotaConfig {
+            enabled: true,
+            daily_bytes: 100,
+            monthly_bytes: 250,
+            users,
+            state_file: path,
+            ..QuotaConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_year_month_from_days() {
+        assert_eq!(year_month_from_days(0), (1970, 1));
+        assert_eq!(year_month_from_days(31), (1970, 2));
+        // 2000-02-29
+        assert_eq!(year_month_from_days(11016), (2000, 2));
+        // 2024-12-31 / 2025-01-01
+        assert_eq!(year_month_from_days(20088), (2024, 12));
+        assert_eq!(year_month_from_days(20089), (2025, 1));
+    }
+
+    #[test]
+    fn test_daily_and_monthly_limits() {
+        let quota = manager(temp_path("quota-limits"));
+        let day = Period::from_day(20089);
+
+        assert!(quota.check_at("alice", day));
+        assert!(quota.record_at("alice", 60, day));
+        assert!(!quota.record_at("alice", 40, day));
+        assert!(!quota.check_at("alice", day));
+
+        // 次日重置日配额，月配额继续累计
+        let next = Period::from_day(20090);
+        assert!(quota.check_at("alice", next));
+        assert!(quota.record_at("alice", 99, next));
+        assert!(!quota.record_at("alice", 60, Period::from_day(20091)));
+        assert!(!quota.check_at("alice", Period::from_day(20092)));
+
+        // 下月重置
+        assert!(quota.check_at("alice", Period::from_day(20089 + 31)));
+
+        // 覆盖配置：不限日配额，沿用默认月配额
+        assert!(quota.record_at("vip", 200, day));
+        assert!(!quota.record_at("vip", 50, day));
+    }
+
+    #[tokio::test]
+    async fn test_persistence() {
+        let path = temp_path("quota-state");
+        let _ = std::fs::remove_file(&path);
+
+        let quota = manager(path.clone());
+        quota.record("alice", 42);
+        quota.save().await.unwrap();
+
+        let reloaded = manager(path.clone());
+        let usage = reloaded.usage("alice").unwrap();
+        assert_eq!(usage.daily_bytes, 42);
+        assert_eq!(usage.monthly_bytes, 42);
+        assert!(reloaded.usage("bob").is_none());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(QuotaManager::new(&QuotaConfig {
+            enabled: true,
+            state_file: path.clone(),
+            ..QuotaConfig::default()
+        })
+        .is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_meter_cuts_only_when_configured() {
+        let quota = Arc::new(manager(temp_path("quota-meter")));
+        let meter = quota.meter("alice").unwrap();
+        assert!(meter.record(Direction::Upload, 500));
+        assert!(!quota.check("alice"));
+
+        let quota = Arc::new(QuotaManager::new(&QuotaConfig {
+            enabled: true,
+            daily_bytes: 100,
+            cut_live_sessions: true,
+            state_file: temp_path("quota-meter-cut"),
+            ..QuotaConfig::default()
+        })
+        .unwrap());
+        let meter = quota.meter("alice").unwrap();
+        assert!(meter.record(Direction::Download, 50));
+        assert!(!meter.record(Direction::Download, 50));
+    }
+
+    #[test]
+    fn test_meters_share_user_counter() {
+        let quota = Arc::new(manager(temp_path("quota-shared")));
+        let first = quota.meter("alice").unwrap();
+        let second = quota.meter("alice").unwrap();
+
+        first.record(Direction::Upload, 30);
+        second.record(Direction::Download, 30);
+        quota.record("alice", 30);
+        assert_eq!(quota.usage("alice").unwrap().daily_bytes, 90);
+        assert!(quota.check("alice"));
+
+        second.record(Direction::Upload, 10);
+        assert!(!quota.check("alice"));
+    }
+
+    #[test]
+    fn test_counter_roll_over() {
+        let counter = UserCounter::default();
+        let day = Period::from_day(20089);
+
+        assert_eq!(counter.add(10, day).daily_bytes, 10);
+        assert_eq!(counter.add(5, day).monthly_bytes, 15);
+
+        let usage = counter.add(1, Period::from_day(20090));
+        assert_eq!((usage.daily_bytes, usage.monthly_bytes), (1, 16));
+
+        let usage = counter.add(1, Period::from_day(20089 + 31));
+        assert_eq!((usage.daily_bytes, usage.monthly_bytes), (1, 1));
+        assert_eq!(counter.snapshot(), usage);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let quota = Arc::new(QuotaManager::new(&QuotaConfig::default()).unwrap());
+        assert!(quota.meter("alice").is_none());
+        assert!(quota.record("alice", u64::MAX / 2));
+        assert!(quota.check("alice"));
+    }
+}
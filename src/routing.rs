@@ -0,0 +1,328 @@
+use crate::config::{PortSpec, RouteAction, RouteRule, RoutingConfig};
+use crate::error::{ProxyError, Result};
+use crate::protocol::Address;
+use ipnet::IpNet;
+use regex::Regex;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+
+/// 路由决策结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub action: RouteAction,
+    /// 命中的规则名，未命中任何规则时为 None
+    pub rule: Option<String>,
+}
+
+/// 路由引擎
+///
+/// 按配置顺序匹配规则，决定请求直连、走上游代理还是拒绝
+pub struct Router {
+    rules: Vec<CompiledRule>,
+    default_action: RouteAction,
+}
+
+struct CompiledRule {
+    name: String,
+    domain_suffix: Vec<String>,
+    domain_regex: Vec<Regex>,
+    cidr: Vec<IpNet>,
+    ports: Vec<RangeInclusive<u16>>,
+    users: Vec<String>,
+    action: RouteAction,
+}
+
+impl Router {
+    pub fn new(config: &RoutingConfig, has_upstream: bool) -> Result<Self> {
+        let default_action = config.default_action.unwrap_or(if has_upstream {
+            RouteAction::Upstream
+        } else {
+            RouteAction::Direct
+        });
+
+        let rules = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| CompiledRule::new(i, rule))
+            .collect::<Result<Vec<_>>>()?;
+
+        let uses_upstream = default_action == RouteAction::Upstream
+            || rules.iter().any(|rule| rule.action == RouteAction::Upstream);
+        if uses_upstream && !has_upstream {
+            return Err(ProxyError::Config(
+                "Routing action \"upstream\" requires a non-empty upstream chain".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            rules,
+            default_action,
+        })
+    }
+
+    /// 为请求选择路由
+    pub fn route(&self, address: &Address, user: Option<&str>) -> Route {
+        self.route_with(address, None, user)
+    }
+
+    /// 按域名解析出的地址重新选择路由，CIDR 条件同时匹配解析结果
+    pub fn route_resolved(&self, address: &Address, resolved: IpAddr, user: Option<&str>) -> Route {
+        self.route_with(address, Some(resolved), user)
+    }
+
+    fn route_with(&self, address: &Address, resolved: Option<IpAddr>, user: Option<&str>) -> Route {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(address, resolved, user))
+            .map(|rule| Route {
+                action: rule.action,
+                rule: Some(rule.name.clone()),
+            })
+            .unwrap_or(Route {
+                action: self.default_action,
+                rule: None,
+            })
+    }
+}
+
+impl CompiledRule {
+    fn new(index: usize, rule: &RouteRule) -> Result<Self> {
+        let name = if rule.name.is_empty() {
+            format!("#{}", index + 1)
+        } else {
+            rule.name.clone()
+        };
+
+        let domain_regex = rule
+            .domain_regex
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| {
+                    ProxyError::Config(format!("Invalid domain regex in rule {}: {}", name, e))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let cidr = rule
+            .cidr
+            .iter()
+            .map(|cidr| parse_cidr(cidr).map_err(|e| ProxyError::Config(format!("{} in rule {}", e, name))))
+            .collect::<Result<Vec<_>>>()?;
+
+        let ports = rule
+            .ports
+            .iter()
+            .map(|spec| parse_port_spec(spec).map_err(|e| ProxyError::Config(format!("{} in rule {}", e, name))))
+            .collect::<Result<Vec<_>>>()?;
+
+        let domain_suffix = rule
+            .domain_suffix
+            .iter()
+            .map(|suffix| suffix.trim_start_matches('.').to_ascii_lowercase())
+            .collect();
+
+        Ok(Self {
+            name,
+            domain_suffix,
+            domain_regex,
+            cidr,
+            ports,
+            users: rule.users.clone(),
+            action: rule.action,
+        })
+    }
+
+    fn matches(&self, address: &Address, resolved: Option<IpAddr>, user: Option<&str>) -> bool {
+        self.matches_destination(address, resolved)
+            && (self.ports.is_empty() || self.ports.iter().any(|range| range.contains(&address.port())))
+            && (self.users.is_empty() || user.is_some_and(|user| self.users.iter().any(|u| u == user)))
+    }
+
+    fn matches_destination(&self, address: &Address, resolved: Option<IpAddr>) -> bool {
+        if self.domain_suffix.is_empty() && self.domain_regex.is_empty() && self.cidr.is_empty() {
+            return true;
+        }
+
+        match address {
+            Address::Domain(domain, _) => {
+                let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+                self.domain_suffix.iter().any(|suffix| domain_has_suffix(&domain, suffix))
+                    || self.domain_regex.iter().any(|regex| regex.is_match(&domain))
+                    || resolved.is_some_and(|ip| self.cidr.iter().any(|net| net.contains(&ip)))
+            }
+            _ => match address.socket_addr() {
+                Some(addr) => self.cidr.iter().any(|net| net.contains(&addr.ip())),
+                None => false,
+            },
+        }
+    }
+}
+
+/// 判断域名是否等于后缀或是其子域名
+fn domain_has_suffix(domain: &str, suffix: &str) -> bool {
+    domain == suffix
+        || domain
+            .strip_suffix(suffix)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// 解析 CIDR，单个 IP 视为主机地址
+pub fn parse_cidr(cidr: &str) -> Result<IpNet> {
+    cidr.parse::<IpNet>()
+        .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| ProxyError::Config(format!("Invalid CIDR: {}", cidr)))
+}
+
+/// 解析端口或端口范围
+pub fn parse_port_spec(spec: &PortSpec) -> Result<RangeInclusive<u16>> {
+    match spec {
+        PortSpec::Single(port) => Ok(*port..=*port),
+        PortSpec::Range(range) => {
+            let invalid = || ProxyError::Config(format!("Invalid port range: {}", range));
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start.trim(), end.trim()),
+                None => (range.trim(), range.trim()),
+            };
+            let start: u16 = start.parse().map_err(|_| invalid())?;
+            let end: u16 = end.parse().map_err(|_| invalid())?;
+            if start > end {
+                return Err(invalid());
+            }
+            Ok(start..=end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn rule(action: RouteAction) -> RouteRule {
+        RouteRule {
+            name: String::new(),
+            domain_suffix: Vec::new(),
+            domain_regex: Vec::new(),
+            cidr: Vec::new(),
+            ports: Vec::new(),
+            users: Vec::new(),
+            action,
+        }
+    }
+
+    fn domain(name: &str, port: u16) -> Address {
+        Address::Domain(name.to_string(), port)
+    }
+
+    #[test]
+    fn test_default_action() {
+        let router = Router::new(&RoutingConfig::default(), false).unwrap();
+        let route = router.route(&domain("example.com", 80), None);
+        assert_eq!(route.action, RouteAction::Direct);
+        assert_eq!(route.rule, None);
+
+        let router = Router::new(&RoutingConfig::default(), true).unwrap();
+        assert_eq!(router.route(&domain("example.com", 80), None).action, RouteAction::Upstream);
+    }
+
+    #[test]
+    fn test_upstream_requires_chain() {
+        let config = RoutingConfig {
+            default_action: None,
+            rules: vec![rule(RouteAction::Upstream)],
+        };
+        assert!(Router::new(&config, false).is_err());
+        assert!(Router::new(&config, true).is_ok());
+    }
+
+    #[test]
+    fn test_domain_suffix_and_regex() {
+        let mut suffix = rule(RouteAction::Reject);
+        suffix.name = "ads".to_string();
+        suffix.domain_suffix = vec![".doubleclick.net".to_string()];
+        let mut regex = rule(RouteAction::Reject);
+        regex.domain_regex = vec![r"^tracker\d+\.".to_string()];
+        let config = RoutingConfig {
+            default_action: None,
+            rules: vec![suffix, regex],
+        };
+        let router = Router::new(&config, false).unwrap();
+
+        let route = router.route(&domain("ad.DoubleClick.net", 443), None);
+        assert_eq!(route.action, RouteAction::Reject);
+        assert_eq!(route.rule.as_deref(), Some("ads"));
+        assert_eq!(router.route(&domain("doubleclick.net.", 443), None).action, RouteAction::Reject);
+        assert_eq!(router.route(&domain("notdoubleclick.net", 443), None).action, RouteAction::Direct);
+
+        let route = router.route(&domain("tracker42.example.com", 80), None);
+        assert_eq!(route.rule.as_deref(), Some("#2"));
+        assert_eq!(router.route(&domain("tracker.example.com", 80), None).action, RouteAction::Direct);
+    }
+
+    #[test]
+    fn test_cidr_ports_and_users() {
+        let mut internal = rule(RouteAction::Reject);
+        internal.cidr = vec!["10.0.0.0/8".to_string(), "192.168.1.1".to_string()];
+        internal.ports = vec![PortSpec::Single(22), PortSpec::Range("8000-8100".to_string())];
+        internal.users = vec!["guest".to_string()];
+        let config = RoutingConfig {
+            default_action: None,
+            rules: vec![internal],
+        };
+        let router = Router::new(&config, false).unwrap();
+
+        let ssh = Address::Ipv4(Ipv4Addr::new(10, 1, 2, 3), 22);
+        assert_eq!(router.route(&ssh, Some("guest")).action, RouteAction::Reject);
+        assert_eq!(router.route(&ssh, Some("admin")).action, RouteAction::Direct);
+        assert_eq!(router.route(&ssh, None).action, RouteAction::Direct);
+
+        let web = Address::Ipv4(Ipv4Addr::new(192, 168, 1, 1), 8080);
+        assert_eq!(router.route(&web, Some("guest")).action, RouteAction::Reject);
+
+        let other = Address::Ipv4(Ipv4Addr::new(192, 168, 1, 2), 8080);
+        assert_eq!(router.route(&other, Some("guest")).action, RouteAction::Direct);
+
+        // 域名不匹配 CIDR 条件，解析结果匹配
+        let internal = domain("10.example.com", 22);
+        assert_eq!(router.route(&internal, Some("guest")).action, RouteAction::Direct);
+        let resolved = IpAddr::V4(Ipv4Addr::new(10, 9, 9, 9));
+        assert_eq!(router.route_resolved(&internal, resolved, Some("guest")).action, RouteAction::Reject);
+        let resolved = IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8));
+        assert_eq!(router.route_resolved(&internal, resolved, Some("guest")).action, RouteAction::Direct);
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let mut allow = rule(RouteAction::Direct);
+        allow.domain_suffix = vec!["good.example.com".to_string()];
+        let mut block = rule(RouteAction::Reject);
+        block.domain_suffix = vec!["example.com".to_string()];
+        let config = RoutingConfig {
+            default_action: Some(RouteAction::Direct),
+            rules: vec![allow, block],
+        };
+        let router = Router::new(&config, false).unwrap();
+
+        assert_eq!(router.route(&domain("a.good.example.com", 80), None).action, RouteAction::Direct);
+        assert_eq!(router.route(&domain("bad.example.com", 80), None).action, RouteAction::Reject);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let mut bad_regex = rule(RouteAction::Reject);
+        bad_regex.domain_regex = vec!["(".to_string()];
+        let mut bad_cidr = rule(RouteAction::Reject);
+        bad_cidr.cidr = vec!["10.0.0.0/33".to_string()];
+        let mut bad_ports = rule(RouteAction::Reject);
+        bad_ports.ports = vec![PortSpec::Range("9000-8000".to_string())];
+
+        for rule in [bad_regex, bad_cidr, bad_ports] {
+            let config = RoutingConfig {
+                default_action: None,
+                rules: vec![rule],
+            };
+            assert!(Router::new(&config, false).is_err());
+        }
+    }
+}
@@ -1,162 +1,1083 @@
+use crate::access::ClientAccess;
+use crate::admin;
+use crate::acl::DestinationAcl;
+use crate::auth::{users, Authenticator, LockoutAuthenticator, LockoutTracker, UserStore, WebhookAuthenticator};
+use crate::config::{AuthBackend, BindConfig, ConnectionLimitPolicy};
 use crate::config::Config;
-use crate::connection::{bidirectional_copy, ConnectionLimiter};
+use crate::connection::{bidirectional_copy, udp, upstream, AcceptRateLimiter, BandwidthManager, ConnectionLimiter, KeyedLimiter, RelayOptions, TrafficMeter};
+use crate::connection::http_forward::{KeepWriteOpen, RequestBody, SingleExchange};
+use crate::connection::limiter::{ConnectionGuard, KeyedGuard};
 use crate::error::{ProxyError, Result};
-use crate::protocol::{self, AuthMethod, Command, Reply};
+use crate::config::RouteAction;
+use crate::protocol::{self, AuthMethod, Command, Reply, SocksVersion};
+use crate::metrics::{self, Metrics, MeteredAuthenticator, RejectReason};
+use crate::quota::{self, QuotaManager};
+use crate::routing::Router;
+use crate::session::{RegisteredSession, Session, SessionRegistry};
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::{TcpListener, TcpStream};
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpListener, TcpStream, UdpSocket};
 use tokio::time::timeout;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, warn, Instrument};
 
-/// SOCKS5 代理服务器
+/// 拒绝客户端时等待其发送请求的最长时间
+const REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 连接数已满时同时排队或等待拒绝的最大连接数，超出后直接关闭
+const MAX_PENDING_CONNECTIONS: usize = 64;
+
+/// 代理服务器（SOCKS4/SOCKS5/HTTP 共用端口）
 pub struct ProxyServer {
     config: Arc<Config>,
     limiter: ConnectionLimiter,
+    pending: ConnectionLimiter,
+    rate_limiter: AcceptRateLimiter,
+    sessions: SessionRegistry,
 }
 
 impl ProxyServer {
     pub fn new(config: Config) -> Self {
         let limiter = ConnectionLimiter::new(config.server.max_connections);
+        let rate_limiter = AcceptRateLimiter::new(&config.limits);
         Self {
             config: Arc::new(config),
             limiter,
+            pending: ConnectionLimiter::new(MAX_PENDING_CONNECTIONS),
+            rate_limiter,
+            sessions: SessionRegistry::new(),
         }
     }
 
     /// 启动服务器
     pub async fn run(&self) -> Result<()> {
+        upstream::validate_chain(&self.config.upstream.chain)?;
+        let router = Router::new(&self.config.routing, !self.config.upstream.chain.is_empty())?;
+        let bind_ip = bind_listen_ip(&self.config.bind)?;
+        let acl = DestinationAcl::new(&self.config.acl)?;
+        let access = ClientAccess::new(&self.config.access)?;
+        let authenticator: Arc<dyn Authenticator> = match self.config.auth.backend {
+            AuthBackend::Local => {
+                let users = Arc::new(UserStore::new(&self.config.auth)?);
+                if let Some(path) = users.file() {
+                    info!("Loaded {} users (users file {:?})", users.len(), path);
+                    if self.config.auth.users_file_reload_secs > 0 {
+                        let interval = Duration::from_secs(self.config.auth.users_file_reload_secs);
+                        tokio::spawn(users::watch_users_file(users.clone(), interval));
+                    }
+                }
+                users
+            }
+            AuthBackend::Webhook => Arc::new(WebhookAuthenticator::new(&self.config.auth.webhook)?),
+        };
+        let lockout = Arc::new(LockoutTracker::new(&self.config.lockout));
+        let authenticator: Arc<dyn Authenticator> = if lockout.is_enabled() {
+            Arc::new(LockoutAuthenticator::new(authenticator, lockout.clone()))
+        } else {
+            authenticator
+        };
+        let metrics = Arc::new(Metrics::new(self.config.metrics.enabled));
+        let authenticator: Arc<dyn Authenticator> = if metrics.is_enabled() {
+            let listener = TcpListener::bind(&self.config.metrics.listen).await?;
+            info!("Metrics listening on http://{}{}", self.config.metrics.listen, self.config.metrics.path);
+            tokio::spawn(metrics::serve(
+                listener,
+                self.config.metrics.path.clone(),
+                metrics.clone(),
+                self.limiter.clone(),
+                Duration::from_secs(self.config.server.handshake_timeout_secs),
+            ));
+            Arc::new(MeteredAuthenticator::new(authenticator, metrics.clone()))
+        } else {
+            authenticator
+        };
+        if self.config.admin.enabled {
+            if self.config.admin.token.is_empty() {
+                return Err(ProxyError::Config("admin.token must be set when the admin API is enabled".to_string()));
+            }
+            let listener = TcpListener::bind(&self.config.admin.listen).await?;
+            info!("Admin API listening on http://{}", self.config.admin.listen);
+            tokio::spawn(admin::serve(
+                listener,
+                self.config.admin.token.clone(),
+                self.sessions.clone(),
+                lockout.clone(),
+                Duration::from_secs(self.config.server.handshake_timeout_secs),
+            ));
+        }
+        let quota = Arc::new(QuotaManager::new(&self.config.quota)?);
+        if quota.is_enabled() {
+            info!("Traffic quotas enabled (state file {:?})", self.config.quota.state_file);
+            let interval = Duration::from_secs(self.config.quota.flush_interval_secs.max(1));
+            tokio::spawn(quota::flush_periodically(quota.clone(), interval));
+        }
+        let ctx = Arc::new(ServerContext {
+            config: self.config.clone(),
+            router,
+            acl,
+            access,
+            authenticator,
+            lockout,
+            bandwidth: BandwidthManager::new(&self.config.limits),
+            quota,
+            ip_limiter: KeyedLimiter::new(self.config.limits.max_connections_per_ip),
+            user_limiter: KeyedLimiter::new(self.config.limits.max_connections_per_user),
+            destination_limiter: KeyedLimiter::new(self.config.limits.max_connections_per_destination),
+            metrics,
+            sessions: self.sessions.clone(),
+            bind_ip,
+        });
+
         let bind_addr = format!("{}:{}", self.config.server.bind_address, self.config.server.port);
         let listener = TcpListener::bind(&bind_addr).await?;
 
-        info!("SOCKS5 proxy server listening on {}", bind_addr);
+        info!("Proxy server (SOCKS4/SOCKS5/HTTP) listening on {}", bind_addr);
         info!("Max connections: {}", self.config.server.max_connections);
         info!("Authentication: {}", if self.config.auth.enabled { "enabled" } else { "disabled" });
 
+        // 收到退出信号时停止接受连接，保存配额用量后返回
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
+
         loop {
-            match listener.accept().await {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = &mut shutdown => break,
+            };
+            match accepted {
                 Ok((stream, addr)) => {
                     debug!("New connection from {}", addr);
+                    ctx.metrics.connection_accepted();
+
+                    // 检查客户端来源 IP，拒绝时直接关闭连接
+                    if !ctx.access.check(addr.ip()) {
+                        ctx.metrics.connection_rejected(RejectReason::Access);
+                        debug!(
+                            "Rejected connection from {} by access list (total rejected: {})",
+                            addr,
+                            ctx.access.rejected_count()
+                        );
+                        continue;
+                    }
 
-                    // 检查连接限制
-                    let guard = match self.limiter.acquire().await {
+                    // 拒绝因认证失败过多而被封禁的客户端
+                    if ctx.lockout.is_ip_banned(addr.ip()) {
+                        ctx.metrics.connection_rejected(RejectReason::Banned);
+                        debug!("Rejected connection from banned client {}", addr);
+                        continue;
+                    }
+
+                    // 单客户端 IP 并发连接数限制
+                    let ip_guard = match ctx.ip_limiter.try_acquire(addr.ip()) {
                         Some(guard) => guard,
                         None => {
-                            warn!("Connection limit reached, rejecting connection from {}", addr);
+                            ctx.metrics.connection_rejected(RejectReason::IpLimit);
+                            debug!(
+                                "Per-IP connection limit ({}) reached, rejecting connection from {}",
+                                ctx.ip_limiter.max_count(),
+                                addr
+                            );
                             continue;
                         }
                     };
 
-                    let config = self.config.clone();
+                    // 新连接速率限制，超出时丢弃或延迟处理
+                    let delay = match self.rate_limiter.check(addr.ip()) {
+                        Some(delay) => delay,
+                        None => {
+                            ctx.metrics.connection_rejected(RejectReason::RateLimit);
+                            debug!(
+                                "Connection rate limit exceeded, dropping connection from {} (total dropped: {})",
+                                addr,
+                                self.rate_limiter.dropped_count()
+                            );
+                            continue;
+                        }
+                    };
 
-                    // 为每个连接创建独立的异步任务
+                    // 检查连接限制，接受循环中不等待许可
+                    let guard = self.limiter.try_acquire();
+                    // reject/wait 策略下排队或等待拒绝的连接也占用任务和套接字，数量有限，超出时按 close 处理
+                    let pending = match guard {
+                        Some(_) => None,
+                        None if self.config.server.connection_limit_policy == ConnectionLimitPolicy::Close => None,
+                        None => self.pending.try_acquire(),
+                    };
+                    if guard.is_none() && pending.is_none() {
+                        ctx.metrics.connection_rejected(RejectReason::ConnectionLimit);
+                        warn!("Connection limit reached, closing connection from {}", addr);
+                        continue;
+                    }
+
+                    let ctx = ctx.clone();
+                    let limiter = self.limiter.clone();
+                    let session = ctx.new_session(addr);
+                    let span = session.span();
+
+                    // 为每个连接创建独立的异步任务，任务内的日志都在会话 span 中
                     tokio::spawn(async move {
-                        let _guard = guard; // 保持守卫直到任务结束
+                        let _ip_guard = ip_guard;
+
+                        if !delay.is_zero() {
+                            debug!("Delaying connection from {} by {:?}", addr, delay);
+                            tokio::time::sleep(delay).await;
+                        }
 
-                        if let Err(e) = handle_client(stream, config).await {
-                            error!("Error handling client {}: {}", addr, e);
+                        // 保持守卫直到任务结束
+                        let _guard = match guard {
+                            Some(guard) => guard,
+                            None => match wait_for_permit(&limiter, &ctx.config).await {
+                                Some(guard) => {
+                                    drop(pending);
+                                    guard
+                                }
+                                None => {
+                                    warn!("Connection limit reached, rejecting connection from {}", addr);
+                                    ctx.metrics.connection_rejected(RejectReason::ConnectionLimit);
+                                    if let Err(e) = reject_client(stream, &session).await {
+                                        debug!("Failed to send rejection to {}: {}", addr, e);
+                                    }
+                                    session.finish(&Err(ProxyError::ConnectionNotAllowed));
+                                    return;
+                                }
+                            },
+                        };
+
+                        // 会话被管理接口终止时丢弃处理过程，连接随之关闭
+                        let result = tokio::select! {
+                            result = handle_client(stream, ctx, &session) => result,
+                            _ = session.killed() => Err(ProxyError::SessionKilled),
+                        };
+                        match &result {
+                            Err(ProxyError::SessionKilled) => info!("Session killed by administrator"),
+                            Err(e) => error!("Error handling client {}: {}", addr, e),
+                            Ok(()) => {}
                         }
+                        session.finish(&result);
 
                         debug!("Connection from {} closed", addr);
-                    });
+                    }.instrument(span));
                 }
                 Err(e) => {
                     error!("Failed to accept connection: {}", e);
                 }
             }
         }
+
+        info!("Shutting down");
+        if let Err(e) = ctx.quota.save().await {
+            error!("Failed to save quota usage: {}", e);
+        }
+
+        Ok(())
+    }
+}
+
+/// 等待 Ctrl-C 或 SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// 连接处理共享的服务器上下文
+struct ServerContext {
+    config: Arc<Config>,
+    router: Router,
+    acl: DestinationAcl,
+    access: ClientAccess,
+    authenticator: Arc<dyn Authenticator>,
+    lockout: Arc<LockoutTracker>,
+    bandwidth: BandwidthManager,
+    quota: Arc<QuotaManager>,
+    ip_limiter: KeyedLimiter<IpAddr>,
+    user_limiter: KeyedLimiter<String>,
+    destination_limiter: KeyedLimiter<String>,
+    metrics: Arc<Metrics>,
+    sessions: SessionRegistry,
+    bind_ip: Option<IpAddr>,
+}
+
+/// 单用户、单目标主机的并发连接许可，会话结束时释放
+struct SessionPermit {
+    _user: Option<KeyedGuard<String>>,
+    _destination: Option<KeyedGuard<String>>,
+}
+
+impl ServerContext {
+    /// 为新连接创建会话并登记到会话表
+    fn new_session(&self, client: SocketAddr) -> RegisteredSession {
+        self.sessions.register(client, self.metrics.clone())
+    }
+
+    /// 认证用户的流量配额是否已用尽
+    fn check_quota(&self, user: Option<&str>) -> Result<()> {
+        match user {
+            Some(user) if !self.quota.check(user) => {
+                warn!("Traffic quota exhausted for user {}, refusing connection", user);
+                Err(ProxyError::ConnectionNotAllowed)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 获取单用户和单目标主机的并发连接许可
+    fn acquire_session(&self, user: Option<&str>, destination: Option<&protocol::Address>) -> Result<SessionPermit> {
+        let user_guard = match user {
+            Some(user) => match self.user_limiter.try_acquire(user.to_string()) {
+                Some(guard) => Some(guard),
+                None => {
+                    warn!(
+                        "Per-user connection limit ({}) reached for user {}",
+                        self.user_limiter.max_count(),
+                        user
+                    );
+                    return Err(ProxyError::ConnectionNotAllowed);
+                }
+            },
+            None => None,
+        };
+
+        let destination_guard = match destination {
+            Some(address) => match self.destination_limiter.try_acquire(address.host()) {
+                Some(guard) => Some(guard),
+                None => {
+                    warn!(
+                        "Per-destination connection limit ({}) reached for {}",
+                        self.destination_limiter.max_count(),
+                        address
+                    );
+                    return Err(ProxyError::ConnectionNotAllowed);
+                }
+            },
+            None => None,
+        };
+
+        Ok(SessionPermit {
+            _user: user_guard,
+            _destination: destination_guard,
+        })
+    }
+
+    /// 认证用户的配额和会话实时流量计量；指标中的字节数在会话结束时按总量累计
+    fn meters(&self, session: &Session, user: Option<&str>) -> Vec<Arc<dyn TrafficMeter>> {
+        let quota = user.and_then(|user| self.quota.meter(user));
+        // 启用管理接口时才统计会话实时流量，否则保留零拷贝转发
+        let live = self.config.admin.enabled.then(|| session.traffic_meter());
+
+        quota.into_iter().chain(live).collect()
+    }
+
+    /// 为连接生成转发选项：限速、超时，以及认证用户和会话的流量计量
+    fn relay_options(&self, session: &Session, user: Option<&str>) -> RelayOptions {
+        let secs = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+
+        RelayOptions {
+            shaping: self.bandwidth.shaping(user),
+            meters: self.meters(session, user),
+            idle_timeout: secs(self.config.server.idle_timeout_secs),
+            max_lifetime: secs(self.config.server.max_session_secs),
+        }
+    }
+}
+
+/// 连接数已满时按 wait 策略排队等待许可
+async fn wait_for_permit(limiter: &ConnectionLimiter, config: &Config) -> Option<ConnectionGuard> {
+    if config.server.connection_limit_policy != ConnectionLimitPolicy::Wait {
+        return None;
+    }
+
+    let queue_timeout = Duration::from_millis(config.server.connection_queue_timeout_ms);
+    timeout(queue_timeout, limiter.acquire()).await.ok().flatten()
+}
+
+/// 连接数已满时拒绝客户端：按协议返回失败响应后关闭
+async fn reject_client(mut client_stream: TcpStream, session: &Session) -> Result<()> {
+    let reject = async {
+        let mut version = [0u8; 1];
+        if client_stream.peek(&mut version).await? == 0 {
+            return Ok(());
+        }
+
+        match version[0] {
+            protocol::socks4::SOCKS4_VERSION => {
+                protocol::socks4::parse_request(&mut client_stream).await?;
+                session.send_socks_failure(SocksVersion::V4, &mut client_stream, Reply::GeneralFailure).await
+            }
+            byte if protocol::http::is_http_method_byte(byte) => {
+                protocol::http::read_request_head(&mut client_stream).await?;
+                session.send_http_response(&mut client_stream, 503, "Service Unavailable", &[]).await
+            }
+            _ => {
+                // 不校验凭据，客户端只提供用户名密码认证时以 NoAcceptable 拒绝
+                protocol::handshake::negotiate_auth(&mut client_stream, false).await?;
+                protocol::request::parse_request(&mut client_stream).await?;
+                session.send_socks_failure(SocksVersion::V5, &mut client_stream, Reply::GeneralFailure).await
+            }
+        }
+    };
+
+    timeout(REJECT_TIMEOUT, reject).await.map_err(|_| ProxyError::Timeout)?
+}
+
+/// 执行握手和认证阶段并统计失败
+///
+/// 加上超时，避免不发送请求的客户端一直占用连接许可
+async fn handshake_timeout<T>(ctx: &ServerContext, handshake: impl Future<Output = Result<T>>) -> Result<T> {
+    let secs = ctx.config.server.handshake_timeout_secs;
+    let result = if secs == 0 {
+        handshake.await
+    } else {
+        match timeout(Duration::from_secs(secs), handshake).await {
+            Ok(result) => result,
+            Err(_) => {
+                info!("Connection closed (handshake timeout after {}s)", secs);
+                Err(ProxyError::Timeout)
+            }
+        }
+    };
+
+    if let Err(e) = &result {
+        ctx.metrics.handshake_failed(e);
     }
+    result
 }
 
 /// 处理客户端连接
-async fn handle_client(mut client_stream: TcpStream, config: Arc<Config>) -> Result<()> {
+async fn handle_client(client_stream: TcpStream, ctx: Arc<ServerContext>, session: &Session) -> Result<()> {
     // 设置 TCP 选项
-    if config.performance.tcp_nodelay {
+    if ctx.config.performance.tcp_nodelay {
         client_stream.set_nodelay(true)?;
     }
 
-    // 1. 握手阶段 - 协商认证方法
-    let auth_method = protocol::handshake::negotiate_auth(
-        &mut client_stream,
-        config.auth.enabled,
-    ).await?;
+    // 根据首字节识别协议版本
+    let mut version = [0u8; 1];
+    if handshake_timeout(&ctx, async { Ok(client_stream.peek(&mut version).await?) }).await? == 0 {
+        return Ok(());
+    }
 
-    // 2. 认证阶段（如果需要）
-    if auth_method == AuthMethod::UsernamePassword {
-        protocol::auth::authenticate(&mut client_stream, &config.auth).await?;
+    match version[0] {
+        protocol::socks4::SOCKS4_VERSION => handle_socks4(client_stream, ctx, session).await,
+        byte if protocol::http::is_http_method_byte(byte) => handle_http(client_stream, ctx, session).await,
+        _ => handle_socks5(client_stream, ctx, session).await,
     }
+}
+
+/// 处理 SOCKS5 客户端
+async fn handle_socks5(mut client_stream: TcpStream, ctx: Arc<ServerContext>, session: &Session) -> Result<()> {
+    let handshake = async {
+        // 1. 握手阶段 - 协商认证方法
+        let auth_method = protocol::handshake::negotiate_auth(
+            &mut client_stream,
+            ctx.config.auth.enabled,
+        ).await?;
+
+        // 2. 认证阶段（如果需要）
+        let user = if auth_method == AuthMethod::UsernamePassword {
+            let identity = protocol::auth::authenticate(&mut client_stream, ctx.authenticator.as_ref()).await?;
+            Some(identity.username)
+        } else {
+            None
+        };
 
-    // 3. 请求阶段 - 解析目标地址
-    let request = protocol::request::parse_request(&mut client_stream).await?;
+        // 3. 请求阶段 - 解析目标地址
+        let request = protocol::request::parse_request(&mut client_stream).await?;
+        Ok((user, request))
+    };
+    let (user, request) = handshake_timeout(&ctx, handshake).await?;
+    session.set_user(user.as_deref());
+    session.set_target(&request.address);
 
     // 4. 处理命令
     match request.command {
         Command::Connect => {
-            handle_connect(client_stream, request.address, config).await
+            handle_connect(client_stream, request.address, ctx, session, SocksVersion::V5, user).await
         }
         Command::Bind => {
-            protocol::response::send_failure(&mut client_stream, Reply::CommandNotSupported).await?;
-            Err(ProxyError::UnsupportedCommand(Command::Bind as u8))
+            handle_bind(client_stream, request.address, ctx, session, SocksVersion::V5, user).await
         }
         Command::UdpAssociate => {
-            protocol::response::send_failure(&mut client_stream, Reply::CommandNotSupported).await?;
-            Err(ProxyError::UnsupportedCommand(Command::UdpAssociate as u8))
+            handle_udp_associate(client_stream, request.address, ctx, session, user).await
+        }
+    }
+}
+
+/// 处理 SOCKS4/4a 客户端
+async fn handle_socks4(mut client_stream: TcpStream, ctx: Arc<ServerContext>, session: &Session) -> Result<()> {
+    let handshake = async {
+        let request = match protocol::socks4::parse_request(&mut client_stream).await {
+            Ok(request) => request,
+            Err(e @ ProxyError::UnsupportedCommand(_)) => {
+                session.send_socks_failure(SocksVersion::V4, &mut client_stream, Reply::CommandNotSupported).await?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+
+        // 启用认证时 USERID 须为 "用户名:密码"
+        let mut user = None;
+        if ctx.config.auth.enabled {
+            if let Some((username, password)) = request.user_id.split_once(':') {
+                let client_ip = client_stream.peer_addr()?.ip();
+                user = ctx
+                    .authenticator
+                    .authenticate(username, password, client_ip)
+                    .await
+                    .map(|identity| identity.username)
+                    .ok();
+            }
         }
+        Ok((request, user))
+    };
+    let (request, user) = handshake_timeout(&ctx, handshake).await?;
+    session.set_user(user.as_deref());
+    session.set_target(&request.address);
+
+    if ctx.config.auth.enabled && user.is_none() {
+        // USERID 为 "用户名:密码"，只记录用户名
+        let username = request.user_id.split(':').next().unwrap_or_default();
+        warn!("SOCKS4 authentication failed for user: {}", username);
+        session.reply_sent("socks4", protocol::socks4::REQUEST_REJECTED_USERID as u16);
+        protocol::socks4::send_reply(
+            &mut client_stream,
+            protocol::socks4::REQUEST_REJECTED_USERID,
+            &protocol::response::unspecified_address(),
+        ).await?;
+        return Err(ProxyError::AuthFailed);
     }
+
+    match request.command {
+        Command::Connect => {
+            handle_connect(client_stream, request.address, ctx, session, SocksVersion::V4, user).await
+        }
+        Command::Bind => {
+            handle_bind(client_stream, request.address, ctx, session, SocksVersion::V4, user).await
+        }
+        Command::UdpAssociate => Err(ProxyError::UnsupportedCommand(Command::UdpAssociate as u8)),
+    }
+}
+
+/// 处理 HTTP 代理客户端
+///
+/// 支持 CONNECT 隧道和绝对 URI 形式的普通 HTTP 转发
+async fn handle_http(mut client_stream: TcpStream, ctx: Arc<ServerContext>, session: &Session) -> Result<()> {
+    let handshake = async {
+        let (head, remaining) = protocol::http::read_request_head(&mut client_stream).await?;
+
+        // 代理认证
+        let mut user = None;
+        if ctx.config.auth.enabled {
+            let credentials = head
+                .header("Proxy-Authorization")
+                .and_then(protocol::http::parse_basic_auth);
+            if let Some((username, password)) = credentials {
+                let client_ip = client_stream.peer_addr()?.ip();
+                user = ctx
+                    .authenticator
+                    .authenticate(&username, &password, client_ip)
+                    .await
+                    .map(|identity| identity.username)
+                    .ok();
+            }
+        }
+        Ok((head, remaining, user))
+    };
+    let (head, remaining, user) = handshake_timeout(&ctx, handshake).await?;
+    session.set_user(user.as_deref());
+
+    if ctx.config.auth.enabled && user.is_none() {
+        warn!("HTTP proxy authentication failed");
+        session.send_http_response(
+            &mut client_stream,
+            407,
+            "Proxy Authentication Required",
+            &[("Proxy-Authenticate", "Basic realm=\"yun-socket-proxy\"")],
+        ).await?;
+        return Err(ProxyError::AuthFailed);
+    }
+
+    let is_connect = head.method.eq_ignore_ascii_case("CONNECT");
+    let target = if is_connect {
+        protocol::http::parse_authority(&head.target, 443).map(|address| (address, None))
+    } else {
+        protocol::http::parse_absolute_uri(&head.target)
+            .and_then(|(address, path)| Ok((address, Some((path, RequestBody::from_head(&head)?)))))
+    };
+    let (address, path) = match target {
+        Ok(target) => target,
+        Err(e) => {
+            session.send_http_response(&mut client_stream, 400, "Bad Request", &[]).await?;
+            return Err(e);
+        }
+    };
+
+    info!("HTTP {} to {}", head.method, address);
+    session.set_target(&address);
+
+    let _permit = match ctx.acquire_session(user.as_deref(), Some(&address)) {
+        Ok(permit) => permit,
+        Err(e) => {
+            let (status, reason) = protocol::http::error_status(&e);
+            session.send_http_response(&mut client_stream, status, reason, &[]).await?;
+            return Err(e);
+        }
+    };
+
+    let mut target_stream = match connect_target(&address, &ctx, user.as_deref()).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            let (status, reason) = protocol::http::error_status(&e);
+            session.send_http_response(&mut client_stream, status, reason, &[]).await?;
+            return Err(e);
+        }
+    };
+    session.set_resolved(target_stream.peer_addr().ok());
+
+    let options = ctx.relay_options(session, user.as_deref());
+    let result = match path {
+        // CONNECT 隧道
+        None => {
+            session.send_http_response(&mut client_stream, 200, "Connection Established", &[]).await?;
+            target_stream.write_all(&remaining).await?;
+            bidirectional_copy(client_stream, target_stream, &options).await
+        }
+        // 普通转发，请求头改写后发往目标；只转发这一个请求，之后关闭连接
+        Some((path, body)) => {
+            let mut exchange = SingleExchange::new(client_stream, body);
+            let request = protocol::http::forward_request_head(&head, &path);
+            target_stream.write_all(&request).await?;
+            target_stream.write_all(exchange.body_prefix(&remaining)).await?;
+            bidirectional_copy(exchange, KeepWriteOpen(target_stream), &options).await
+        }
+    };
+    let stats = session.relay_finished(result)?;
+    debug!(
+        "HTTP transfer completed ({}) - Sent: {} bytes, Received: {} bytes",
+        stats.reason, stats.client_to_target, stats.target_to_client
+    );
+
+    Ok(())
 }
 
 /// 处理 CONNECT 命令
 async fn handle_connect(
     mut client_stream: TcpStream,
     address: protocol::Address,
-    config: Arc<Config>,
+    ctx: Arc<ServerContext>,
+    session: &Session,
+    version: SocksVersion,
+    user: Option<String>,
 ) -> Result<()> {
-    info!("Connecting to {}", address.to_string());
+    info!("Connecting to {}", address);
+
+    let _permit = match ctx.acquire_session(user.as_deref(), Some(&address)) {
+        Ok(permit) => permit,
+        Err(e) => {
+            session.send_socks_failure(version, &mut client_stream, Reply::from(&e)).await?;
+            return Err(e);
+        }
+    };
 
     // 连接到目标服务器
-    let target_addr = address.to_string();
-    let connect_timeout = Duration::from_secs(config.server.connection_timeout_secs);
+    let target_stream = match connect_target(&address, &ctx, user.as_deref()).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            session.send_socks_failure(version, &mut client_stream, Reply::from(&e)).await?;
+            return Err(e);
+        }
+    };
+
+    session.set_resolved(target_stream.peer_addr().ok());
 
-    let target_stream = match timeout(connect_timeout, TcpStream::connect(&target_addr)).await {
-        Ok(Ok(stream)) => stream,
+    // 发送成功响应，BND 为出站连接的本地地址
+    let bind_address = protocol::response::bind_address(&target_stream.local_addr()?);
+    session.send_socks_success(version, &mut client_stream, &bind_address).await?;
+
+    info!("Successfully connected to {}", address);
+
+    // 双向数据转发
+    let result = bidirectional_copy(client_stream, target_stream, &ctx.relay_options(session, user.as_deref())).await;
+    match session.relay_finished(result) {
+        Ok(stats) => {
+            debug!(
+                "Data transfer completed ({}) - Sent: {} bytes, Received: {} bytes",
+                stats.reason, stats.client_to_target, stats.target_to_client
+            );
+            Ok(())
+        }
+        Err(e) => {
+            error!("Error during data transfer: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// 连接目标服务器
+///
+/// 由路由引擎决定直连、经由上游代理链连接或拒绝；连接失败时返回可映射为响应码的错误
+async fn connect_target(
+    address: &protocol::Address,
+    ctx: &ServerContext,
+    user: Option<&str>,
+) -> Result<TcpStream> {
+    ctx.check_quota(user)?;
+
+    let route = ctx.router.route(address, user);
+    let rule = route.rule.as_deref().unwrap_or("default");
+    match route.action {
+        RouteAction::Reject => {
+            info!("Rejected connection to {} (rule: {})", address, rule);
+            return Err(ProxyError::ConnectionNotAllowed);
+        }
+        action => debug!("Routing {} via {:?} (rule: {})", address, action, rule),
+    }
+
+    let connect_timeout = Duration::from_secs(ctx.config.server.connection_timeout_secs);
+
+    let connect = async {
+        match route.action {
+            RouteAction::Upstream => {
+                // 域名交由上游解析，只能检查端口和 IP 字面量
+                let allowed = match address.socket_addr() {
+                    Some(addr) => ctx.acl.is_allowed(&addr),
+                    None => ctx.acl.is_port_allowed(address.port()),
+                };
+                if !allowed {
+                    return Err(ProxyError::ConnectionNotAllowed);
+                }
+                upstream::connect_via_chain(&ctx.config.upstream.chain, address).await
+            }
+            _ => {
+                // 先解析再检查，只连接通过检查的地址，避免域名绕过
+                let addrs = ctx.acl.resolve(address).await?;
+                let addrs = filter_rejected(address, addrs, ctx, user)?;
+                connect_direct(&addrs).await
+            }
+        }
+    };
+
+    let started = tokio::time::Instant::now();
+    let target_stream = match timeout(connect_timeout, connect).await {
+        Ok(Ok(stream)) => {
+            ctx.metrics.connect_completed(started.elapsed());
+            stream
+        }
         Ok(Err(e)) => {
-            error!("Failed to connect to {}: {}", target_addr, e);
-            let reply = match e.kind() {
-                std::io::ErrorKind::ConnectionRefused => Reply::ConnectionRefused,
-                std::io::ErrorKind::TimedOut => Reply::TtlExpired,
-                _ => Reply::HostUnreachable,
-            };
-            protocol::response::send_failure(&mut client_stream, reply).await?;
-            return Err(ProxyError::from(e));
+            error!("Failed to connect to {}: {}", address, e);
+            return Err(e);
         }
         Err(_) => {
-            error!("Connection timeout to {}", target_addr);
-            protocol::response::send_failure(&mut client_stream, Reply::TtlExpired).await?;
+            error!("Connection timeout to {}", address);
             return Err(ProxyError::Timeout);
         }
     };
 
     // 设置目标连接的 TCP 选项
-    if config.performance.tcp_nodelay {
+    if ctx.config.performance.tcp_nodelay {
         target_stream.set_nodelay(true)?;
     }
 
-    // 发送成功响应
-    protocol::response::send_success(&mut client_stream, &address).await?;
+    Ok(target_stream)
+}
 
-    info!("Successfully connected to {}", target_addr);
+/// 按解析出的地址重新匹配路由规则，去掉命中拒绝规则的地址
+///
+/// 域名只能在解析后匹配 CIDR 规则，否则指向内网网段的域名会绕过拒绝规则
+fn filter_rejected(
+    address: &protocol::Address,
+    addrs: Vec<SocketAddr>,
+    ctx: &ServerContext,
+    user: Option<&str>,
+) -> Result<Vec<SocketAddr>> {
+    if address.socket_addr().is_some() {
+        return Ok(addrs);
+    }
 
-    // 双向数据转发
-    match bidirectional_copy(client_stream, target_stream).await {
-        Ok((client_to_target, target_to_client)) => {
-            debug!(
-                "Data transfer completed - Sent: {} bytes, Received: {} bytes",
-                client_to_target, target_to_client
-            );
-            Ok(())
+    let allowed: Vec<SocketAddr> = addrs
+        .into_iter()
+        .filter(|addr| {
+            let route = ctx.router.route_resolved(address, addr.ip(), user);
+            if route.action != RouteAction::Reject {
+                return true;
+            }
+            let rule = route.rule.as_deref().unwrap_or("default");
+            info!("Rejected connection to {} ({}) (rule: {})", address, addr.ip(), rule);
+            false
+        })
+        .collect();
+
+    if allowed.is_empty() {
+        return Err(ProxyError::ConnectionNotAllowed);
+    }
+    Ok(allowed)
+}
+
+/// 直连目标服务器，依次尝试解析出的地址
+async fn connect_direct(addrs: &[SocketAddr]) -> Result<TcpStream> {
+    let mut last_error = ProxyError::HostUnreachable;
+
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                debug!("Direct connection to {} failed: {}", addr, e);
+                last_error = match e.kind() {
+                    std::io::ErrorKind::ConnectionRefused => ProxyError::ConnectionRefused,
+                    std::io::ErrorKind::TimedOut => ProxyError::Timeout,
+                    std::io::ErrorKind::NetworkUnreachable => ProxyError::NetworkUnreachable,
+                    _ => ProxyError::HostUnreachable,
+                };
+            }
         }
+    }
+
+    Err(last_error)
+}
+
+/// 处理 BIND 命令
+///
+/// 第一次响应返回监听地址，对端连入后第二次响应返回对端地址，随后开始转发
+async fn handle_bind(
+    mut client_stream: TcpStream,
+    address: protocol::Address,
+    ctx: Arc<ServerContext>,
+    session: &Session,
+    version: SocksVersion,
+    user: Option<String>,
+) -> Result<()> {
+    let permit = ctx
+        .check_quota(user.as_deref())
+        .and_then(|_| ctx.acquire_session(user.as_deref(), None));
+    let _permit = match permit {
+        Ok(permit) => permit,
         Err(e) => {
-            error!("Error during data transfer: {}", e);
-            Err(e)
+            session.send_socks_failure(version, &mut client_stream, Reply::from(&e)).await?;
+            return Err(e);
+        }
+    };
+
+    // DST.ADDR 为期望的对端地址，未指定时接受任意对端；域名解析失败时拒绝，不能放行任意对端
+    let expected_peers: Vec<IpAddr> = match &address {
+        protocol::Address::Domain(..) => {
+            let resolved: Vec<IpAddr> = lookup_host(address.to_string())
+                .await
+                .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+                .unwrap_or_default();
+            if resolved.is_empty() {
+                warn!("BIND failed to resolve expected peer {}", address);
+                session.send_socks_failure(version, &mut client_stream, Reply::HostUnreachable).await?;
+                return Err(ProxyError::HostUnreachable);
+            }
+            resolved
         }
+        _ => address
+            .socket_addr()
+            .map(|addr| addr.ip())
+            .filter(|ip| !ip.is_unspecified())
+            .into_iter()
+            .collect(),
+    };
+
+    let listen_ip = match ctx.bind_ip {
+        Some(ip) => ip,
+        None => client_stream.local_addr()?.ip(),
+    };
+
+    let listener = match bind_listener(listen_ip, &ctx.config).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to open BIND listener on {}: {}", listen_ip, e);
+            session.send_socks_failure(version, &mut client_stream, Reply::GeneralFailure).await?;
+            return Err(e);
+        }
+    };
+    let listen_addr = listener.local_addr()?;
+
+    session.send_socks_success(version, &mut client_stream, &protocol::response::bind_address(&listen_addr)).await?;
+
+    info!("BIND listening on {} for {}", listen_addr, address);
+
+    let accept_timeout = Duration::from_secs(ctx.config.bind.accept_timeout_secs);
+    let accepted = timeout(accept_timeout, async {
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+            if expected_peers.is_empty() || expected_peers.contains(&peer_addr.ip()) {
+                return Ok::<_, std::io::Error>((stream, peer_addr));
+            }
+            warn!("BIND rejected unexpected peer {} (expected {})", peer_addr, address);
+        }
+    })
+    .await;
+
+    let (peer_stream, peer_addr) = match accepted {
+        Ok(Ok(accepted)) => accepted,
+        Ok(Err(e)) => {
+            error!("BIND accept failed on {}: {}", listen_addr, e);
+            session.send_socks_failure(version, &mut client_stream, Reply::GeneralFailure).await?;
+            return Err(ProxyError::from(e));
+        }
+        Err(_) => {
+            warn!("BIND timed out waiting for peer on {}", listen_addr);
+            session.send_socks_failure(version, &mut client_stream, Reply::TtlExpired).await?;
+            return Err(ProxyError::Timeout);
+        }
+    };
+    drop(listener);
+    session.set_resolved(Some(peer_addr));
+
+    if ctx.config.performance.tcp_nodelay {
+        peer_stream.set_nodelay(true)?;
+    }
+
+    session.send_socks_success(version, &mut client_stream, &protocol::response::bind_address(&peer_addr)).await?;
+
+    info!("BIND peer {} connected on {}", peer_addr, listen_addr);
+
+    let result = bidirectional_copy(client_stream, peer_stream, &ctx.relay_options(session, user.as_deref())).await;
+    let stats = session.relay_finished(result)?;
+    debug!(
+        "BIND transfer completed ({}) - Sent: {} bytes, Received: {} bytes",
+        stats.reason, stats.client_to_target, stats.target_to_client
+    );
+
+    Ok(())
+}
+
+/// 校验 BIND 配置，返回指定的监听地址（未指定时为 None，使用客户端连入的地址）
+fn bind_listen_ip(config: &BindConfig) -> Result<Option<IpAddr>> {
+    let (start, end) = (config.port_range_start, config.port_range_end);
+    if !(start == 0 && end == 0) && (start == 0 || start > end) {
+        return Err(ProxyError::Config(format!("Invalid bind port range: {}-{}", start, end)));
+    }
+
+    if config.listen_address.is_empty() {
+        return Ok(None);
+    }
+    config
+        .listen_address
+        .parse::<IpAddr>()
+        .map(Some)
+        .map_err(|_| ProxyError::Config(format!("Invalid bind listen address: {}", config.listen_address)))
+}
+
+/// 按配置的端口范围打开 BIND 监听 socket，端口范围已在启动时校验
+async fn bind_listener(ip: IpAddr, config: &Config) -> Result<TcpListener> {
+    let (start, end) = (config.bind.port_range_start, config.bind.port_range_end);
+    if start == 0 && end == 0 {
+        return Ok(TcpListener::bind(SocketAddr::new(ip, 0)).await?);
+    }
+
+    for port in start..=end {
+        if let Ok(listener) = TcpListener::bind(SocketAddr::new(ip, port)).await {
+            return Ok(listener);
+        }
+    }
+
+    Err(ProxyError::Protocol(format!("No free port in bind range {}-{}", start, end)))
+}
+
+/// 处理 UDP ASSOCIATE 命令
+async fn handle_udp_associate(
+    mut client_stream: TcpStream,
+    address: protocol::Address,
+    ctx: Arc<ServerContext>,
+    session: &Session,
+    user: Option<String>,
+) -> Result<()> {
+    // 配额和单用户并发连接数限制同样适用于 UDP 关联
+    let permit = ctx
+        .check_quota(user.as_deref())
+        .and_then(|_| ctx.acquire_session(user.as_deref(), None));
+    let _permit = match permit {
+        Ok(permit) => permit,
+        Err(e) => {
+            session.send_socks_failure(SocksVersion::V5, &mut client_stream, Reply::from(&e)).await?;
+            return Err(e);
+        }
+    };
+
+    // 在客户端连入的同一网卡上分配中继 socket
+    let local_ip = client_stream.local_addr()?.ip();
+    let client_ip = client_stream.peer_addr()?.ip();
+
+    let relay_socket = match UdpSocket::bind(SocketAddr::new(local_ip, 0)).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to bind UDP relay socket: {}", e);
+            session.send_socks_failure(SocksVersion::V5, &mut client_stream, Reply::GeneralFailure).await?;
+            return Err(ProxyError::from(e));
+        }
+    };
+    let relay_addr = relay_socket.local_addr()?;
+
+    // DST.ADDR 可能是客户端的 NAT 内地址，只采用其端口做来源过滤
+    let client_port = address.port();
+
+    session.send_socks_success(SocksVersion::V5, &mut client_stream, &protocol::response::bind_address(&relay_addr)).await?;
+
+    info!("UDP association established for {} on {}", client_ip, relay_addr);
+
+    let destinations = udp::UdpDestinations {
+        router: &ctx.router,
+        acl: &ctx.acl,
+        user: user.as_deref(),
+    };
+    let options = ctx.relay_options(session, user.as_deref());
+    let stats = udp::udp_associate(client_stream, relay_socket, client_ip, client_port, &destinations, &options).await?;
+    session.set_relay(&stats);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_listen_ip() {
+        let mut config = BindConfig::default();
+        assert_eq!(bind_listen_ip(&config).unwrap(), None);
+
+        config.listen_address = "10.0.0.1".to_string();
+        config.port_range_start = 40000;
+        config.port_range_end = 40100;
+        assert_eq!(bind_listen_ip(&config).unwrap(), Some("10.0.0.1".parse().unwrap()));
+
+        config.listen_address = "not-an-ip".to_string();
+        assert!(bind_listen_ip(&config).is_err());
+
+        config.listen_address.clear();
+        config.port_range_start = 40100;
+        config.port_range_end = 40000;
+        assert!(bind_listen_ip(&config).is_err());
+
+        config.port_range_start = 0;
+        config.port_range_end = 40000;
+        assert!(bind_listen_ip(&config).is_err());
     }
 }
@@ -0,0 +1,492 @@
+use crate::connection::bandwidth::Direction;
+use crate::connection::relay::RelayError;
+use crate::connection::{RelayStats, TrafficMeter};
+use crate::error::Result;
+use crate::metrics::Metrics;
+use crate::protocol::{self, Reply, SocksVersion};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tracing::{info, info_span, Span};
+
+/// 访问日志使用的 tracing target
+pub const ACCESS_LOG_TARGET: &str = "access";
+
+/// 会话过程中逐步填充的信息
+#[derive(Debug, Default)]
+struct SessionInfo {
+    user: Option<String>,
+    target: Option<String>,
+    resolved: Option<SocketAddr>,
+    protocol: Option<&'static str>,
+    reply: Option<u16>,
+    relay: Option<RelayStats>,
+    /// 转发出错时已转发的字节数
+    transferred: Option<(u64, u64)>,
+}
+
+/// 会话实时转发字节数
+#[derive(Default)]
+struct LiveTraffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl LiveTraffic {
+    fn totals(&self) -> (u64, u64) {
+        (self.sent.load(Ordering::Relaxed), self.received.load(Ordering::Relaxed))
+    }
+}
+
+impl TrafficMeter for LiveTraffic {
+    fn record(&self, direction: Direction, bytes: u64) -> bool {
+        match direction {
+            Direction::Upload => self.sent.fetch_add(bytes, Ordering::Relaxed),
+            Direction::Download => self.received.fetch_add(bytes, Ordering::Relaxed),
+        };
+        true
+    }
+}
+
+/// 管理接口返回的会话快照
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSnapshot {
+    pub id: u64,
+    pub client: String,
+    pub user: Option<String>,
+    pub destination: Option<String>,
+    /// 会话开始时间（UNIX 时间戳，秒）
+    pub started_at: u64,
+    pub duration_secs: u64,
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// 单个客户端连接的会话
+///
+/// 记录用户、目标、响应码和流量，会话结束时输出一条访问日志
+pub struct Session {
+    id: u64,
+    client: SocketAddr,
+    started: Instant,
+    started_at: SystemTime,
+    metrics: Arc<Metrics>,
+    info: Mutex<SessionInfo>,
+    traffic: Arc<LiveTraffic>,
+    kill: watch::Sender<bool>,
+}
+
+impl Session {
+    pub fn new(id: u64, client: SocketAddr, metrics: Arc<Metrics>) -> Self {
+        Self {
+            id,
+            client,
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            metrics,
+            info: Mutex::new(SessionInfo::default()),
+            traffic: Arc::new(LiveTraffic::default()),
+            kill: watch::Sender::new(false),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn client(&self) -> SocketAddr {
+        self.client
+    }
+
+    /// 会话的 tracing span，连接处理过程中的日志都带有会话 ID 和客户端地址
+    pub fn span(&self) -> Span {
+        info_span!("session", id = self.id, client = %self.client)
+    }
+
+    pub fn set_user(&self, user: Option<&str>) {
+        self.info.lock().unwrap().user = user.map(str::to_string);
+    }
+
+    pub fn set_target(&self, target: &protocol::Address) {
+        self.info.lock().unwrap().target = Some(target.to_string());
+    }
+
+    /// 实际连接的地址（直连为目标 IP，经上游代理时为上游代理地址）
+    pub fn set_resolved(&self, resolved: Option<SocketAddr>) {
+        self.info.lock().unwrap().resolved = resolved;
+    }
+
+    pub fn set_relay(&self, stats: &RelayStats) {
+        self.info.lock().unwrap().relay = Some(*stats);
+    }
+
+    /// 记录转发结果，出错时保留出错前已转发的字节数
+    pub fn relay_finished(&self, result: std::result::Result<RelayStats, RelayError>) -> Result<RelayStats> {
+        match result {
+            Ok(stats) => {
+                self.set_relay(&stats);
+                Ok(stats)
+            }
+            Err(e) => {
+                self.info.lock().unwrap().transferred = Some((e.client_to_target, e.target_to_client));
+                Err(e.error)
+            }
+        }
+    }
+
+    /// 统计会话实时转发字节数的计量
+    pub fn traffic_meter(&self) -> Arc<dyn TrafficMeter> {
+        self.traffic.clone()
+    }
+
+    /// 要求终止会话
+    pub fn kill(&self) {
+        self.kill.send_replace(true);
+    }
+
+    /// 会话被终止时完成
+    pub async fn killed(&self) {
+        let mut killed = self.kill.subscribe();
+        // 发送端随会话存活，不会关闭
+        let _ = killed.wait_for(|killed| *killed).await;
+    }
+
+    pub fn snapshot(&self) -> SessionSnapshot {
+        let info = self.info.lock().unwrap();
+        let (sent, received) = self.traffic.totals();
+        SessionSnapshot {
+            id: self.id,
+            client: self.client.to_string(),
+            user: info.user.clone(),
+            destination: info.target.clone(),
+            started_at: self
+                .started_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            duration_secs: self.started.elapsed().as_secs(),
+            sent,
+            received,
+        }
+    }
+
+    /// 记录发送给客户端的响应码（SOCKS 响应字节或 HTTP 状态码）
+    pub fn reply_sent(&self, protocol: &'static str, code: u16) {
+        let mut info = self.info.lock().unwrap();
+        info.protocol = Some(protocol);
+        info.reply = Some(code);
+        drop(info);
+
+        self.metrics.reply_sent(protocol, code);
+    }
+
+    /// 发送 SOCKS 成功响应并记录
+    pub async fn send_socks_success(
+        &self,
+        version: SocksVersion,
+        stream: &mut TcpStream,
+        bind_address: &protocol::Address,
+    ) -> Result<()> {
+        let code = match version {
+            SocksVersion::V4 => protocol::socks4::REQUEST_GRANTED,
+            SocksVersion::V5 => Reply::Succeeded as u8,
+        };
+        self.reply_sent(socks_label(version), code as u16);
+        version.send_success(stream, bind_address).await
+    }
+
+    /// 发送 SOCKS 失败响应并记录
+    pub async fn send_socks_failure(&self, version: SocksVersion, stream: &mut TcpStream, reply: Reply) -> Result<()> {
+        let code = match version {
+            SocksVersion::V4 => protocol::socks4::reply_code(reply),
+            SocksVersion::V5 => reply as u8,
+        };
+        self.reply_sent(socks_label(version), code as u16);
+        version.send_failure(stream, reply).await
+    }
+
+    /// 发送 HTTP 响应（无响应体）并记录
+    pub async fn send_http_response(
+        &self,
+        stream: &mut TcpStream,
+        status: u16,
+        reason: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<()> {
+        self.reply_sent("http", status);
+        protocol::http::send_response(stream, status, reason, headers).await
+    }
+
+    /// 会话结束，输出访问日志
+    pub fn finish(&self, result: &Result<()>) {
+        let info = self.info.lock().unwrap();
+        // 转发中途被终止时没有转发统计，使用实时计数
+        let (sent, received) = info
+            .relay
+            .map(|stats| (stats.client_to_target, stats.target_to_client))
+            .or(info.transferred)
+            .unwrap_or_else(|| self.traffic.totals());
+        let reason = close_reason(result, info.relay.as_ref());
+        self.metrics.traffic_finished(info.user.as_deref(), sent, received);
+
+        info!(
+            target: ACCESS_LOG_TARGET,
+            session = self.id,
+            client = %self.client,
+            user = info.user.as_deref().unwrap_or("-"),
+            destination = info.target.as_deref().unwrap_or("-"),
+            resolved = %info.resolved.map(|addr| addr.ip().to_string()).unwrap_or_else(|| "-".to_string()),
+            protocol = info.protocol.unwrap_or("-"),
+            reply = info.reply,
+            sent,
+            received,
+            duration_ms = self.started.elapsed().as_millis() as u64,
+            reason = %reason,
+            "session closed"
+        );
+    }
+}
+
+/// 活动会话表，供管理接口查看和终止会话
+#[derive(Clone, Default)]
+pub struct SessionRegistry {
+    next_id: Arc<AtomicU64>,
+    sessions: Arc<Mutex<HashMap<u64, Arc<Session>>>>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 创建并登记新会话，分配递增的会话 ID；返回的句柄释放时注销会话
+    pub fn register(&self, client: SocketAddr, metrics: Arc<Metrics>) -> RegisteredSession {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let session = Arc::new(Session::new(id, client, metrics));
+        self.sessions.lock().unwrap().insert(id, session.clone());
+        RegisteredSession {
+            session,
+            registry: self.clone(),
+        }
+    }
+
+    /// 活动会话数
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 按会话 ID 排序的活动会话快照
+    pub fn list(&self) -> Vec<SessionSnapshot> {
+        let mut snapshots: Vec<_> = self.sessions.lock().unwrap().values().map(|session| session.snapshot()).collect();
+        snapshots.sort_by_key(|snapshot| snapshot.id);
+        snapshots
+    }
+
+    /// 终止指定会话，会话不存在时返回 false
+    pub fn kill(&self, id: u64) -> bool {
+        match self.sessions.lock().unwrap().get(&id) {
+            Some(session) => {
+                session.kill();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 终止指定用户的所有会话，返回终止的会话数
+    pub fn kill_user(&self, user: &str) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        let mut killed = 0;
+        for session in sessions.values() {
+            if session.info.lock().unwrap().user.as_deref() == Some(user) {
+                session.kill();
+                killed += 1;
+            }
+        }
+        killed
+    }
+}
+
+/// 已登记的会话，释放时从会话表中移除
+pub struct RegisteredSession {
+    session: Arc<Session>,
+    registry: SessionRegistry,
+}
+
+impl Deref for RegisteredSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl Drop for RegisteredSession {
+    fn drop(&mut self) {
+        self.registry.sessions.lock().unwrap().remove(&self.session.id);
+    }
+}
+
+/// 会话关闭原因：出错时为错误类型，否则为转发结束的原因
+fn close_reason(result: &Result<()>, relay: Option<&RelayStats>) -> String {
+    match (result, relay) {
+        (Err(e), _) => e.kind().to_string(),
+        (Ok(()), Some(stats)) => stats.reason.to_string(),
+        (Ok(()), None) => "closed".to_string(),
+    }
+}
+
+fn socks_label(version: SocksVersion) -> &'static str {
+    match version {
+        SocksVersion::V4 => "socks4",
+        SocksVersion::V5 => "socks5",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::CloseReason;
+    use crate::connection::ConnectionLimiter;
+    use crate::error::ProxyError;
+
+    #[test]
+    fn test_close_reason() {
+        let stats = RelayStats {
+            client_to_target: 10,
+            target_to_client: 20,
+            reason: CloseReason::IdleTimeout(Direction::Download),
+        };
+
+        assert_eq!(close_reason(&Ok(()), None), "closed");
+        assert_eq!(close_reason(&Ok(()), Some(&stats)), "download idle timeout");
+        assert_eq!(close_reason(&Err(ProxyError::Timeout), Some(&stats)), "timeout");
+        assert_eq!(close_reason(&Err(ProxyError::AuthFailed), None), "auth_failed");
+    }
+
+    #[test]
+    fn test_relay_error_keeps_transferred_bytes() {
+        let session = Session::new(1, "127.0.0.1:5000".parse().unwrap(), Arc::new(Metrics::new(false)));
+        let result = session.relay_finished(Err(RelayError {
+            client_to_target: 10,
+            target_to_client: 20,
+            error: ProxyError::Io(std::io::ErrorKind::ConnectionReset.into()),
+        }));
+
+        assert!(matches!(result, Err(ProxyError::Io(_))));
+        let info = session.info.lock().unwrap();
+        assert_eq!(info.relay, None);
+        assert_eq!(info.transferred, Some((10, 20)));
+    }
+
+    #[test]
+    fn test_span_carries_session_id() {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+
+        let session = Session::new(42, "127.0.0.1:5000".parse().unwrap(), Arc::new(Metrics::new(false)));
+        tracing::subscriber::with_default(subscriber, || {
+            let _entered = session.span().entered();
+            info!("handshake started");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("session{id=42 client=127.0.0.1:5000}"), "{}", output);
+        assert!(output.contains("handshake started"));
+    }
+
+    #[tokio::test]
+    async fn test_registry_kill() {
+        let registry = SessionRegistry::new();
+        let metrics = Arc::new(Metrics::new(false));
+
+        let first = registry.register("127.0.0.1:5000".parse().unwrap(), metrics.clone());
+        let second = registry.register("127.0.0.1:5001".parse().unwrap(), metrics.clone());
+        let third = registry.register("127.0.0.1:5002".parse().unwrap(), metrics);
+        first.set_user(Some("alice"));
+        second.set_user(Some("alice"));
+        third.set_user(Some("bob"));
+        third.traffic_meter().record(Direction::Upload, 100);
+        third.traffic_meter().record(Direction::Download, 200);
+
+        let list = registry.list();
+        assert_eq!(list.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(list[2].user.as_deref(), Some("bob"));
+        assert_eq!((list[2].sent, list[2].received), (100, 200));
+
+        assert_eq!(registry.kill_user("alice"), 2);
+        first.killed().await;
+        second.killed().await;
+        assert!(registry.kill(3));
+        third.killed().await;
+        assert!(!registry.kill(4));
+
+        drop(first);
+        assert_eq!(registry.len(), 2);
+        drop(second);
+        drop(third);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_reply_sent_records_metrics() {
+        let metrics = Arc::new(Metrics::new(true));
+        let session = Session::new(7, "127.0.0.1:5000".parse().unwrap(), metrics.clone());
+        session.set_user(Some("alice"));
+        session.reply_sent("socks5", 0);
+
+        let info = session.info.lock().unwrap();
+        assert_eq!(info.user.as_deref(), Some("alice"));
+        assert_eq!(info.protocol, Some("socks5"));
+        assert_eq!(info.reply, Some(0));
+        drop(info);
+
+        let output = metrics.render(&ConnectionLimiter::new(10));
+        assert!(output.contains("proxy_replies_total{protocol=\"socks5\",code=\"0\"} 1"));
+    }
+
+    #[test]
+    fn test_finish_records_traffic() {
+        let metrics = Arc::new(Metrics::new(true));
+        let session = Session::new(8, "127.0.0.1:5000".parse().unwrap(), metrics.clone());
+        session.set_user(Some("alice"));
+        session.set_relay(&RelayStats {
+            client_to_target: 100,
+            target_to_client: 200,
+            reason: CloseReason::Eof,
+        });
+        session.finish(&Ok(()));
+
+        let output = metrics.render(&ConnectionLimiter::new(10));
+        assert!(output.contains("proxy_bytes_total{user=\"alice\",direction=\"upload\"} 100\n"));
+        assert!(output.contains("proxy_bytes_total{user=\"alice\",direction=\"download\"} 200\n"));
+    }
+}
@@ -1,7 +1,15 @@
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::time::{timeout, Duration};
 
+/// 创建监听指定端口、允许访问本机目标的测试配置
+fn local_test_config(port: u16) -> yun_socket_proxy::config::Config {
+    let mut config = yun_socket_proxy::config::Config::default();
+    config.server.port = port;
+    config.acl.allow.push("127.0.0.1/32".to_string());
+    config
+}
+
 /// 创建一个简单的 echo 服务器用于测试
 async fn start_echo_server(port: u16) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
@@ -25,6 +33,47 @@ async fn start_echo_server(port: u16) -> tokio::task::JoinHandle<()> {
     })
 }
 
+/// 创建一个 UDP echo 服务器用于测试
+async fn start_udp_echo_server() -> std::net::SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65535];
+        while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&buf[..n], from).await;
+        }
+    });
+
+    addr
+}
+
+/// 完成握手并发送 UDP ASSOCIATE 请求，返回控制连接和中继地址
+async fn udp_associate(proxy_port: u16) -> (TcpStream, std::net::SocketAddr) {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(response, [0x05, 0x00]);
+
+    // [VER | CMD(0x03) | RSV | ATYP(0x01) | 0.0.0.0 | 0]
+    stream
+        .write_all(&[0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await
+        .unwrap();
+
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00); // Success
+    assert_eq!(reply[3], 0x01); // IPv4
+
+    let ip = std::net::Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+    let port = u16::from_be_bytes([reply[8], reply[9]]);
+
+    (stream, std::net::SocketAddr::from((ip, port)))
+}
+
 /// 测试 SOCKS5 握手（无认证）
 #[tokio::test]
 async fn test_socks5_handshake_no_auth() {
@@ -62,8 +111,7 @@ async fn test_socks5_connect_flow() {
     tokio::time::sleep(Duration::from_millis(100)).await;
 
     // 启动代理服务器
-    let mut config = yun_socket_proxy::config::Config::default();
-    config.server.port = 1081;
+    let config = local_test_config(1081);
     let server = yun_socket_proxy::server::ProxyServer::new(config);
 
     tokio::spawn(async move {
@@ -93,6 +141,9 @@ async fn test_socks5_connect_flow() {
     stream.read_exact(&mut connect_response).await.unwrap();
     assert_eq!(connect_response[0], 0x05); // SOCKS version
     assert_eq!(connect_response[1], 0x00); // Success
+    assert_eq!(connect_response[3], 0x01); // IPv4
+    assert_eq!(&connect_response[4..8], &[127, 0, 0, 1]); // 出站连接的本地地址
+    assert_ne!(u16::from_be_bytes([connect_response[8], connect_response[9]]), 0);
 
     // 4. 测试数据传输
     let test_data = b"Hello, SOCKS5!";
@@ -108,8 +159,7 @@ async fn test_socks5_connect_flow() {
 /// 测试连接限制
 #[tokio::test]
 async fn test_connection_limit() {
-    let mut config = yun_socket_proxy::config::Config::default();
-    config.server.port = 1082;
+    let mut config = local_test_config(1082);
     config.server.max_connections = 2;
     let server = yun_socket_proxy::server::ProxyServer::new(config);
 
@@ -135,8 +185,7 @@ async fn test_connection_limit() {
 /// 测试域名解析
 #[tokio::test]
 async fn test_domain_resolution() {
-    let mut config = yun_socket_proxy::config::Config::default();
-    config.server.port = 1083;
+    let config = local_test_config(1083);
     let server = yun_socket_proxy::server::ProxyServer::new(config);
 
     tokio::spawn(async move {
@@ -169,3 +218,1474 @@ async fn test_domain_resolution() {
     // 只要能收到响应就算测试通过
     assert!(result.is_ok());
 }
+
+/// 测试 UDP ASSOCIATE 中继
+#[tokio::test]
+async fn test_udp_associate_relay() {
+    let echo_addr = start_udp_echo_server().await;
+
+    let config = local_test_config(1084);
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (_control, relay_addr) = udp_associate(1084).await;
+    assert_ne!(relay_addr.port(), 0);
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    // [RSV(2) | FRAG | ATYP | DST.ADDR | DST.PORT | DATA]
+    let mut datagram = vec![0x00, 0x00, 0x00, 0x01, 127, 0, 0, 1];
+    datagram.extend_from_slice(&echo_addr.port().to_be_bytes());
+    datagram.extend_from_slice(b"Hello, UDP!");
+    client.send_to(&datagram, relay_addr).await.unwrap();
+
+    let mut buf = vec![0u8; 1024];
+    let (n, from) = timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(from, relay_addr);
+    // 响应头携带 echo 服务器地址
+    assert_eq!(&buf[..n], &datagram[..]);
+
+    // 分片数据报被丢弃
+    let mut fragment = datagram.clone();
+    fragment[2] = 0x01;
+    client.send_to(&fragment, relay_addr).await.unwrap();
+    let result = timeout(Duration::from_millis(300), client.recv_from(&mut buf)).await;
+    assert!(result.is_err());
+}
+
+/// 测试控制连接关闭后 UDP 关联被拆除
+#[tokio::test]
+async fn test_udp_associate_teardown() {
+    let echo_addr = start_udp_echo_server().await;
+
+    let config = local_test_config(1085);
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (control, relay_addr) = udp_associate(1085).await;
+    drop(control);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut datagram = vec![0x00, 0x00, 0x00, 0x01, 127, 0, 0, 1];
+    datagram.extend_from_slice(&echo_addr.port().to_be_bytes());
+    datagram.extend_from_slice(b"ping");
+    let _ = client.send_to(&datagram, relay_addr).await;
+
+    let mut buf = vec![0u8; 1024];
+    let result = timeout(Duration::from_millis(500), client.recv_from(&mut buf)).await;
+    assert!(!matches!(result, Ok(Ok(_))));
+}
+
+/// 测试 UDP 关联的空闲超时和会话最长持续时间
+#[tokio::test]
+async fn test_udp_associate_timeouts() {
+    let echo_addr = start_udp_echo_server().await;
+
+    let mut idle_config = local_test_config(1115);
+    idle_config.server.idle_timeout_secs = 1;
+    let mut lifetime_config = local_test_config(1116);
+    lifetime_config.server.max_session_secs = 1;
+    for config in [idle_config, lifetime_config] {
+        let server = yun_socket_proxy::server::ProxyServer::new(config);
+        tokio::spawn(async move {
+            let _ = server.run().await;
+        });
+    }
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut datagram = vec![0x00, 0x00, 0x00, 0x01, 127, 0, 0, 1];
+    datagram.extend_from_slice(&echo_addr.port().to_be_bytes());
+    datagram.extend_from_slice(b"ping");
+
+    // 有数据报时不会空闲超时，空闲后关联被关闭
+    let (mut control, relay_addr) = udp_associate(1115).await;
+    let mut buf = vec![0u8; 1024];
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        client.send_to(&datagram, relay_addr).await.unwrap();
+        timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    let n = timeout(Duration::from_secs(3), control.read(&mut buf)).await.unwrap().unwrap_or(0);
+    assert_eq!(n, 0);
+
+    // 持续有数据报时仍在超过最长持续时间后关闭
+    let (mut control, relay_addr) = udp_associate(1116).await;
+    let keepalive = async {
+        loop {
+            let _ = client.send_to(&datagram, relay_addr).await;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    };
+    let closed = tokio::select! {
+        result = timeout(Duration::from_secs(3), control.read(&mut buf)) => result.unwrap().unwrap_or(0),
+        _ = keepalive => unreachable!(),
+    };
+    assert_eq!(closed, 0);
+}
+
+/// 完成握手并发送 BIND 请求，返回控制连接和监听地址
+async fn bind_request(proxy_port: u16) -> (TcpStream, std::net::SocketAddr) {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(response, [0x05, 0x00]);
+
+    // [VER | CMD(0x02) | RSV | ATYP(0x01) | 127.0.0.1 | 0]
+    stream
+        .write_all(&[0x05, 0x02, 0x00, 0x01, 127, 0, 0, 1, 0, 0])
+        .await
+        .unwrap();
+
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00); // Success
+    assert_eq!(reply[3], 0x01); // IPv4
+
+    let ip = std::net::Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+    let port = u16::from_be_bytes([reply[8], reply[9]]);
+
+    (stream, std::net::SocketAddr::from((ip, port)))
+}
+
+/// 测试 BIND 命令接受对端连接并转发
+#[tokio::test]
+async fn test_bind_flow() {
+    let mut config = local_test_config(1086);
+    config.bind.port_range_start = 41080;
+    config.bind.port_range_end = 41089;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (mut control, listen_addr) = bind_request(1086).await;
+    assert_eq!(listen_addr.ip(), std::net::Ipv4Addr::LOCALHOST);
+    assert!((41080..=41089).contains(&listen_addr.port()));
+
+    // 对端连入后收到第二次响应
+    let mut peer = TcpStream::connect(listen_addr).await.unwrap();
+    let mut reply = [0u8; 10];
+    timeout(Duration::from_secs(2), control.read_exact(&mut reply))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply[1], 0x00);
+    let peer_port = u16::from_be_bytes([reply[8], reply[9]]);
+    assert_eq!(peer_port, peer.local_addr().unwrap().port());
+
+    // 双向转发
+    control.write_all(b"from client").await.unwrap();
+    let mut buf = [0u8; 11];
+    peer.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"from client");
+
+    peer.write_all(b"from peer").await.unwrap();
+    let mut buf = [0u8; 9];
+    control.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"from peer");
+}
+
+/// 测试 BIND 等待对端超时
+#[tokio::test]
+async fn test_bind_timeout() {
+    let mut config = local_test_config(1087);
+    config.bind.accept_timeout_secs = 1;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (mut control, _listen_addr) = bind_request(1087).await;
+
+    let mut reply = [0u8; 10];
+    timeout(Duration::from_secs(3), control.read_exact(&mut reply))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply[1], 0x06); // TTL expired
+}
+
+/// 测试 BIND 期望对端域名无法解析时拒绝请求，而不是接受任意对端
+#[tokio::test]
+async fn test_bind_unresolvable_peer() {
+    let server = yun_socket_proxy::server::ProxyServer::new(local_test_config(1111));
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:1111").await.unwrap();
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+
+    let domain = b"nonexistent.invalid";
+    let mut request = vec![0x05, 0x02, 0x00, 0x03, domain.len() as u8];
+    request.extend_from_slice(domain);
+    request.extend_from_slice(&0u16.to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+
+    let mut reply = [0u8; 10];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut reply))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply[1], 0x04); // Host unreachable
+}
+
+/// 测试 SOCKS4 CONNECT 与 SOCKS4a 域名请求
+#[tokio::test]
+async fn test_socks4_connect() {
+    let echo_port = 9998;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let config = local_test_config(1088);
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // SOCKS4: [VN(0x04) | CD(0x01) | DSTPORT | DSTIP | USERID | NULL]
+    let mut stream = TcpStream::connect("127.0.0.1:1088").await.unwrap();
+    let mut request = vec![0x04, 0x01];
+    request.extend_from_slice(&echo_port.to_be_bytes());
+    request.extend_from_slice(&[127, 0, 0, 1]);
+    request.extend_from_slice(b"user\0");
+    stream.write_all(&request).await.unwrap();
+
+    let mut reply = [0u8; 8];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0], 0x00);
+    assert_eq!(reply[1], 0x5A); // Granted
+
+    stream.write_all(b"Hello, SOCKS4!").await.unwrap();
+    let mut echo_response = [0u8; 14];
+    timeout(Duration::from_secs(2), stream.read_exact(&mut echo_response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&echo_response, b"Hello, SOCKS4!");
+
+    // SOCKS4a: DSTIP = 0.0.0.1，域名追加在 USERID 之后
+    let mut stream = TcpStream::connect("127.0.0.1:1088").await.unwrap();
+    let mut request = vec![0x04, 0x01];
+    request.extend_from_slice(&echo_port.to_be_bytes());
+    request.extend_from_slice(&[0, 0, 0, 1]);
+    request.extend_from_slice(b"\0localhost\0");
+    stream.write_all(&request).await.unwrap();
+
+    let mut reply = [0u8; 8];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x5A);
+
+    stream.write_all(b"4a").await.unwrap();
+    let mut echo_response = [0u8; 2];
+    timeout(Duration::from_secs(2), stream.read_exact(&mut echo_response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&echo_response, b"4a");
+}
+
+/// 测试启用认证时 SOCKS4 USERID 映射到配置用户
+#[tokio::test]
+async fn test_socks4_auth() {
+    let echo_port = 9997;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = local_test_config(1089);
+    config.auth.enabled = true;
+    config.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+    });
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for (user_id, expected) in [(&b"alice:wrong"[..], 0x5D), (b"alice", 0x5D), (b"alice:secret", 0x5A)] {
+        let mut stream = TcpStream::connect("127.0.0.1:1089").await.unwrap();
+        let mut request = vec![0x04, 0x01];
+        request.extend_from_slice(&echo_port.to_be_bytes());
+        request.extend_from_slice(&[127, 0, 0, 1]);
+        request.extend_from_slice(user_id);
+        request.push(0);
+        stream.write_all(&request).await.unwrap();
+
+        let mut reply = [0u8; 8];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], expected);
+    }
+}
+
+/// 创建一个简单的 HTTP 服务器，把收到的请求头原样放入响应体
+async fn start_http_origin_server() -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", head.len());
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.write_all(&head).await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// 读取直到对端关闭连接
+async fn read_to_close(stream: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(2), stream.read_to_end(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    String::from_utf8(response).unwrap()
+}
+
+/// 测试 HTTP CONNECT 隧道和普通 HTTP 转发
+#[tokio::test]
+async fn test_http_proxy() {
+    let echo_port = 9996;
+    let _echo_server = start_echo_server(echo_port).await;
+    let origin_addr = start_http_origin_server().await;
+
+    let config = local_test_config(1090);
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // CONNECT 隧道
+    let mut stream = TcpStream::connect("127.0.0.1:1090").await.unwrap();
+    let request = format!(
+        "CONNECT 127.0.0.1:{0} HTTP/1.1\r\nHost: 127.0.0.1:{0}\r\n\r\n",
+        echo_port
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let expected = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+    let mut response = vec![0u8; expected.len()];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, expected);
+
+    stream.write_all(b"tunnel").await.unwrap();
+    let mut echo_response = [0u8; 6];
+    timeout(Duration::from_secs(2), stream.read_exact(&mut echo_response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&echo_response, b"tunnel");
+
+    // 普通 HTTP 转发，请求行改写为 origin-form
+    let mut stream = TcpStream::connect("127.0.0.1:1090").await.unwrap();
+    let request = format!(
+        "GET http://{0}/path?q=1 HTTP/1.1\r\nHost: {0}\r\nProxy-Connection: keep-alive\r\n\r\n",
+        origin_addr
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let response = read_to_close(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("GET /path?q=1 HTTP/1.1\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(!response.contains("Proxy-Connection"));
+
+    // 相对 URI 不是合法的代理请求
+    let mut stream = TcpStream::connect("127.0.0.1:1090").await.unwrap();
+    stream.write_all(b"GET /path HTTP/1.1\r\n\r\n").await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 400"));
+}
+
+/// 测试 HTTP 代理 Basic 认证
+#[tokio::test]
+async fn test_http_proxy_auth() {
+    let origin_addr = start_http_origin_server().await;
+
+    let mut config = local_test_config(1091);
+    config.auth.enabled = true;
+    config.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+    });
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 缺少认证
+    let mut stream = TcpStream::connect("127.0.0.1:1091").await.unwrap();
+    let request = format!("GET http://{}/ HTTP/1.1\r\n\r\n", origin_addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let response = read_to_close(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.1 407"));
+    assert!(response.contains("Proxy-Authenticate: Basic"));
+
+    // "alice:secret"
+    let mut stream = TcpStream::connect("127.0.0.1:1091").await.unwrap();
+    let request = format!(
+        "GET http://{}/ HTTP/1.1\r\nProxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n",
+        origin_addr
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let response = read_to_close(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(!response.contains("Proxy-Authorization"));
+}
+
+/// 测试普通 HTTP 转发只转发连接上的第一个请求
+#[tokio::test]
+async fn test_http_proxy_single_request() {
+    let origin_addr = start_http_origin_server().await;
+
+    let server = yun_socket_proxy::server::ProxyServer::new(local_test_config(1117));
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 流水线中的第二个请求不能原样发往第一个目标
+    let mut stream = TcpStream::connect("127.0.0.1:1117").await.unwrap();
+    let request = format!(
+        "GET http://{0}/first HTTP/1.1\r\nHost: {0}\r\n\r\n\
+         GET http://other.example/second HTTP/1.1\r\nProxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n",
+        origin_addr
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let response = read_to_close(&mut stream).await;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    assert!(head.ends_with("Connection: close"));
+    assert!(body.contains("GET /first HTTP/1.1\r\n"));
+    assert!(!body.contains("second"));
+    assert!(!body.contains("Proxy-Authorization"));
+}
+
+/// 通过代理发送 SOCKS5 CONNECT 请求到 127.0.0.1:port，返回连接和响应码
+async fn socks5_connect_local(proxy_port: u16, target_port: u16) -> (TcpStream, u8) {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(response, [0x05, 0x00]);
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    request.extend_from_slice(&target_port.to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+
+    let mut reply = [0u8; 10];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut reply))
+        .await
+        .unwrap()
+        .unwrap();
+
+    (stream, reply[1])
+}
+
+/// 测试经由上游代理链连接目标
+#[tokio::test]
+async fn test_upstream_chain() {
+    use yun_socket_proxy::config::{UpstreamProxy, UpstreamType};
+
+    let echo_port = 9995;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    // 需要认证的上游代理
+    let mut parent = local_test_config(1092);
+    parent.auth.enabled = true;
+    parent.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "corp".to_string(),
+        password: "egress".to_string(),
+    });
+
+    let upstream = |proxy_type| UpstreamProxy {
+        proxy_type,
+        address: "127.0.0.1:1092".to_string(),
+        username: "corp".to_string(),
+        password: "egress".to_string(),
+    };
+
+    let chains = [
+        (1093, vec![upstream(UpstreamType::Socks5)]),
+        (1094, vec![upstream(UpstreamType::Http)]),
+        (1095, vec![upstream(UpstreamType::Socks5), upstream(UpstreamType::Http)]),
+    ];
+
+    let mut configs = vec![parent];
+    for (port, chain) in &chains {
+        let mut config = local_test_config(*port);
+        config.upstream.chain = chain.clone();
+        configs.push(config);
+    }
+    for config in configs {
+        let server = yun_socket_proxy::server::ProxyServer::new(config);
+        tokio::spawn(async move {
+            let _ = server.run().await;
+        });
+    }
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for (port, _) in &chains {
+        let (mut stream, reply) = socks5_connect_local(*port, echo_port).await;
+        assert_eq!(reply, 0x00);
+
+        stream.write_all(b"chained").await.unwrap();
+        let mut echo_response = [0u8; 7];
+        timeout(Duration::from_secs(2), stream.read_exact(&mut echo_response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&echo_response, b"chained");
+    }
+
+    // 上游连接失败的响应码透传给客户端
+    let closed_port = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let (_stream, reply) = socks5_connect_local(1093, closed_port).await;
+    assert_eq!(reply, 0x05); // Connection refused
+}
+
+/// 测试路由规则拒绝请求
+#[tokio::test]
+async fn test_routing_reject() {
+    use yun_socket_proxy::config::{PortSpec, RouteAction, RouteRule};
+
+    let echo_port = 9994;
+    let blocked_port = 9993;
+    let _echo_server = start_echo_server(echo_port).await;
+    let _blocked_server = start_echo_server(blocked_port).await;
+
+    let mut config = local_test_config(1096);
+    config.routing.rules.push(RouteRule {
+        name: "block-port".to_string(),
+        domain_suffix: Vec::new(),
+        domain_regex: Vec::new(),
+        cidr: vec!["127.0.0.0/8".to_string()],
+        ports: vec![PortSpec::Single(blocked_port)],
+        users: Vec::new(),
+        action: RouteAction::Reject,
+    });
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (_stream, reply) = socks5_connect_local(1096, blocked_port).await;
+    assert_eq!(reply, 0x02); // Connection not allowed
+
+    let (_stream, reply) = socks5_connect_local(1096, echo_port).await;
+    assert_eq!(reply, 0x00);
+
+    let mut stream = TcpStream::connect("127.0.0.1:1096").await.unwrap();
+    let request = format!("CONNECT 127.0.0.1:{} HTTP/1.1\r\n\r\n", blocked_port);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 403"));
+}
+
+/// 通过代理发送 SOCKS5 CONNECT 请求到 domain:port，返回连接和响应码
+async fn socks5_connect_domain(proxy_port: u16, domain: &str, target_port: u16) -> (TcpStream, u8) {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(response, [0x05, 0x00]);
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, domain.len() as u8];
+    request.extend_from_slice(domain.as_bytes());
+    request.extend_from_slice(&target_port.to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+
+    let mut reply = [0u8; 10];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut reply))
+        .await
+        .unwrap()
+        .unwrap();
+
+    (stream, reply[1])
+}
+
+/// 测试域名解析到被拒绝的网段时命中 CIDR 规则
+#[tokio::test]
+async fn test_routing_reject_resolved_domain() {
+    use yun_socket_proxy::config::{PortSpec, RouteAction, RouteRule};
+
+    let echo_port = 9983;
+    let blocked_port = 9982;
+    let _echo_server = start_echo_server(echo_port).await;
+    let _blocked_server = start_echo_server(blocked_port).await;
+
+    let mut config = local_test_config(1114);
+    config.routing.rules.push(RouteRule {
+        name: "block-loopback".to_string(),
+        domain_suffix: Vec::new(),
+        domain_regex: Vec::new(),
+        cidr: vec!["127.0.0.0/8".to_string()],
+        ports: vec![PortSpec::Single(blocked_port)],
+        users: Vec::new(),
+        action: RouteAction::Reject,
+    });
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (_stream, reply) = socks5_connect_domain(1114, "localhost", blocked_port).await;
+    assert_eq!(reply, 0x02); // Connection not allowed
+
+    let (_stream, reply) = socks5_connect_domain(1114, "localhost", echo_port).await;
+    assert_eq!(reply, 0x00);
+}
+
+/// 测试默认目标访问控制拒绝本机和内网地址（域名解析后检查）
+#[tokio::test]
+async fn test_destination_acl_blocks_private() {
+    let echo_port = 9992;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = yun_socket_proxy::config::Config::default();
+    config.server.port = 1097;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (_stream, reply) = socks5_connect_local(1097, echo_port).await;
+    assert_eq!(reply, 0x02); // Connection not allowed
+
+    // 域名解析到回环地址同样被拒绝
+    let mut stream = TcpStream::connect("127.0.0.1:1097").await.unwrap();
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, 9];
+    request.extend_from_slice(b"localhost");
+    request.extend_from_slice(&echo_port.to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x02);
+
+    // HTTP 代理同样受限
+    let mut stream = TcpStream::connect("127.0.0.1:1097").await.unwrap();
+    let request = format!("GET http://localhost:{}/ HTTP/1.1\r\n\r\n", echo_port);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 403"));
+}
+
+/// 测试客户端来源 IP 访问控制
+#[tokio::test]
+async fn test_client_access_list() {
+    let mut config = local_test_config(1098);
+    config.access.deny.push("127.0.0.0/8".to_string());
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 被拒绝的客户端在握手前即被关闭
+    let mut stream = TcpStream::connect("127.0.0.1:1098").await.unwrap();
+    let _ = stream.write_all(&[0x05, 0x01, 0x00]).await;
+    let mut response = [0u8; 2];
+    let result = timeout(Duration::from_secs(2), stream.read(&mut response))
+        .await
+        .unwrap();
+    assert!(matches!(result, Ok(0) | Err(_)));
+}
+
+/// 测试哈希存储的密码认证
+#[tokio::test]
+async fn test_socks5_hashed_password_auth() {
+    use yun_socket_proxy::auth::password::{hash_password, HashAlgorithm};
+
+    let mut config = local_test_config(1099);
+    config.auth.enabled = true;
+    config.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "alice".to_string(),
+        password: hash_password("secret", HashAlgorithm::Argon2).unwrap(),
+    });
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for (password, status) in [("secret", 0x00), ("wrong", 0x01)] {
+        let mut stream = TcpStream::connect("127.0.0.1:1099").await.unwrap();
+        stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [0x05, 0x02]);
+
+        let mut auth = vec![0x01, 5];
+        auth.extend_from_slice(b"alice");
+        auth.push(password.len() as u8);
+        auth.extend_from_slice(password.as_bytes());
+        stream.write_all(&auth).await.unwrap();
+
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [0x01, status]);
+    }
+}
+
+/// 测试用户文件变更后自动重新加载
+#[tokio::test]
+async fn test_users_file_reload() {
+    let origin_addr = start_http_origin_server().await;
+    let path = std::env::temp_dir().join(format!("yun-socket-proxy-htpasswd-{}", std::process::id()));
+    std::fs::write(&path, "alice:secret\n").unwrap();
+
+    let mut config = local_test_config(1100);
+    config.auth.enabled = true;
+    config.auth.users_file = Some(path.clone());
+    config.auth.users_file_reload_secs = 1;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // "alice:secret"
+    let request = format!(
+        "GET http://{}/ HTTP/1.1\r\nProxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n",
+        origin_addr
+    );
+
+    let mut stream = TcpStream::connect("127.0.0.1:1100").await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 200 OK"));
+
+    std::fs::write(&path, "alice:changed\n").unwrap();
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:1100").await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 407"));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+/// 测试 HTTP 回调认证后端
+#[tokio::test]
+async fn test_webhook_auth_backend() {
+    // 本地认证服务：只接受 alice/secret
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let auth_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            let request = String::from_utf8_lossy(&request);
+            let response = if request.contains(r#""username":"alice""#) && request.contains(r#""password":"secret""#) {
+                "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+            } else {
+                "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n"
+            };
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+
+    let mut config = local_test_config(1101);
+    config.auth.enabled = true;
+    config.auth.backend = yun_socket_proxy::config::AuthBackend::Webhook;
+    config.auth.webhook.url = format!("http://{}/auth", auth_addr);
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for (password, status) in [("secret", 0x00), ("wrong", 0x01)] {
+        let mut stream = TcpStream::connect("127.0.0.1:1101").await.unwrap();
+        stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [0x05, 0x02]);
+
+        let mut auth = vec![0x01, 5];
+        auth.extend_from_slice(b"alice");
+        auth.push(password.len() as u8);
+        auth.extend_from_slice(password.as_bytes());
+        stream.write_all(&auth).await.unwrap();
+
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [0x01, status]);
+    }
+}
+
+/// 测试认证失败过多后封禁客户端 IP
+#[tokio::test]
+async fn test_auth_lockout() {
+    let mut config = local_test_config(1102);
+    config.auth.enabled = true;
+    config.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+    });
+    config.lockout.enabled = true;
+    config.lockout.max_failures = 2;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect("127.0.0.1:1102").await.unwrap();
+        stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await.unwrap();
+
+        stream.write_all(b"\x01\x05alice\x05wrong").await.unwrap();
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [0x01, 0x01]);
+    }
+
+    // 被封禁后连接在接受时即被关闭
+    let mut stream = TcpStream::connect("127.0.0.1:1102").await.unwrap();
+    let _ = stream.write_all(&[0x05, 0x01, 0x02]).await;
+    let mut buf = [0u8; 2];
+    let result = timeout(Duration::from_secs(5), stream.read(&mut buf)).await.unwrap();
+    assert!(matches!(result, Ok(0) | Err(_)));
+}
+
+/// 测试新连接速率限制
+#[tokio::test]
+async fn test_accept_rate_limit() {
+    let mut config = local_test_config(1103);
+    config.limits.max_connections_per_sec_per_ip = 2;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut streams = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect("127.0.0.1:1103").await.unwrap();
+        stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [0x05, 0x00]);
+        streams.push(stream);
+    }
+
+    // 超出速率的连接被直接关闭
+    let mut stream = TcpStream::connect("127.0.0.1:1103").await.unwrap();
+    let _ = stream.write_all(&[0x05, 0x01, 0x00]).await;
+    let mut buf = [0u8; 2];
+    let result = timeout(Duration::from_secs(5), stream.read(&mut buf)).await.unwrap();
+    assert!(matches!(result, Ok(0) | Err(_)));
+}
+
+/// 测试单连接带宽限制
+#[tokio::test]
+async fn test_bandwidth_limit() {
+    let echo_port = 9991;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = local_test_config(1104);
+    config.limits.max_bandwidth_per_connection = 20_000;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (mut stream, reply) = socks5_connect_local(1104, echo_port).await;
+    assert_eq!(reply, 0x00);
+
+    // 首个一秒的突发之后，剩余 10KB 至少需要 0.5 秒
+    let start = std::time::Instant::now();
+    let data = vec![0x42u8; 30_000];
+    let (mut reader, mut writer) = stream.split();
+    let (_, received) = tokio::join!(writer.write_all(&data), async {
+        let mut received = vec![0u8; data.len()];
+        timeout(Duration::from_secs(10), reader.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        received
+    });
+
+    assert_eq!(received, data);
+    assert!(start.elapsed() >= Duration::from_millis(400), "{:?}", start.elapsed());
+}
+
+/// 测试流量配额用尽后中断会话并拒绝新连接
+#[tokio::test]
+async fn test_traffic_quota() {
+    let echo_port = 9990;
+    let _echo_server = start_echo_server(echo_port).await;
+    let state_file = std::env::temp_dir().join(format!("yun-socket-proxy-quota-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&state_file);
+
+    let mut config = local_test_config(1105);
+    config.auth.enabled = true;
+    config.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+    });
+    config.quota.enabled = true;
+    config.quota.daily_bytes = 1000;
+    config.quota.cut_live_sessions = true;
+    config.quota.state_file = state_file.clone();
+    config.quota.flush_interval_secs = 1;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // "alice:secret"
+    let request = format!(
+        "CONNECT 127.0.0.1:{0} HTTP/1.1\r\nHost: 127.0.0.1:{0}\r\nProxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n",
+        echo_port
+    );
+
+    let mut stream = TcpStream::connect("127.0.0.1:1105").await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = vec![0u8; 1024];
+    let n = stream.read(&mut response).await.unwrap();
+    assert!(String::from_utf8_lossy(&response[..n]).starts_with("HTTP/1.1 200"));
+
+    // 超出配额后会话被中断
+    let _ = stream.write_all(&[0x42u8; 600]).await;
+    let _ = stream.write_all(&[0x42u8; 600]).await;
+    let closed = timeout(Duration::from_secs(5), async {
+        let mut buf = [0u8; 1024];
+        while let Ok(n) = stream.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok());
+
+    // 新连接被拒绝
+    let mut stream = TcpStream::connect("127.0.0.1:1105").await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 403"));
+
+    // 用量定期写入状态文件
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let state = std::fs::read_to_string(&state_file).unwrap();
+    assert!(state.contains("alice"));
+
+    std::fs::remove_file(state_file).unwrap();
+}
+
+/// 测试单目标主机并发连接数限制
+#[tokio::test]
+async fn test_per_destination_connection_limit() {
+    let echo_port = 9989;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = local_test_config(1106);
+    config.limits.max_connections_per_destination = 1;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (first, reply) = socks5_connect_local(1106, echo_port).await;
+    assert_eq!(reply, 0x00);
+
+    // 同一目标的第二个连接被拒绝
+    let (_, reply) = socks5_connect_local(1106, echo_port).await;
+    assert_eq!(reply, 0x02);
+
+    // 第一个连接关闭后释放许可
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (_, reply) = socks5_connect_local(1106, echo_port).await;
+    assert_eq!(reply, 0x00);
+}
+
+/// 测试连接数达到上限时立即拒绝新连接
+#[tokio::test]
+async fn test_connection_limit_reject() {
+    let echo_port = 9988;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = local_test_config(1107);
+    config.server.max_connections = 1;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (first, reply) = socks5_connect_local(1107, echo_port).await;
+    assert_eq!(reply, 0x00);
+
+    // 已满时返回 GeneralFailure，而不是挂起
+    let (_, reply) = socks5_connect_local(1107, echo_port).await;
+    assert_eq!(reply, 0x01);
+
+    let mut stream = TcpStream::connect("127.0.0.1:1107").await.unwrap();
+    stream.write_all(b"GET http://127.0.0.1/ HTTP/1.1\r\n\r\n").await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 503"));
+
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (_, reply) = socks5_connect_local(1107, echo_port).await;
+    assert_eq!(reply, 0x00);
+}
+
+/// 测试等待拒绝的连接数有上限，超出后直接关闭
+#[tokio::test]
+async fn test_connection_limit_pending_cap() {
+    let echo_port = 9984;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = local_test_config(1113);
+    config.server.max_connections = 1;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (_first, reply) = socks5_connect_local(1113, echo_port).await;
+    assert_eq!(reply, 0x00);
+
+    // 不发送请求的客户端占满等待拒绝的名额（64 个）
+    let mut pending = Vec::new();
+    for _ in 0..64 {
+        pending.push(TcpStream::connect("127.0.0.1:1113").await.unwrap());
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 名额已满时按 close 处理，不等待请求
+    let mut stream = TcpStream::connect("127.0.0.1:1113").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = timeout(Duration::from_secs(1), stream.read(&mut buf)).await.unwrap().unwrap_or(0);
+    assert_eq!(n, 0);
+
+    // 等待中的连接仍能收到拒绝响应
+    let mut stream = pending.pop().unwrap();
+    stream.write_all(b"GET http://127.0.0.1/ HTTP/1.1\r\n\r\n").await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 503"));
+}
+
+/// 测试握手超时和空闲超时
+#[tokio::test]
+async fn test_handshake_and_idle_timeout() {
+    let echo_port = 9987;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = local_test_config(1108);
+    config.server.handshake_timeout_secs = 1;
+    config.server.idle_timeout_secs = 1;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 不发送握手的客户端被关闭
+    let mut stream = TcpStream::connect("127.0.0.1:1108").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = timeout(Duration::from_secs(3), stream.read(&mut buf)).await.unwrap().unwrap_or(0);
+    assert_eq!(n, 0);
+
+    // 有数据时不会超时，空闲后被关闭
+    let (mut stream, reply) = socks5_connect_local(1108, echo_port).await;
+    assert_eq!(reply, 0x00);
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+    let n = timeout(Duration::from_secs(3), stream.read(&mut buf)).await.unwrap().unwrap_or(0);
+    assert_eq!(n, 0);
+}
+
+/// 测试 Prometheus 指标
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    let echo_port = 9986;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = local_test_config(1109);
+    config.auth.enabled = true;
+    config.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+    });
+    config.metrics.enabled = true;
+    config.metrics.listen = "127.0.0.1:9290".to_string();
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for password in ["secret", "wrong"] {
+        let mut stream = TcpStream::connect("127.0.0.1:1109").await.unwrap();
+        stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await.unwrap();
+
+        let mut auth = vec![0x01, 5];
+        auth.extend_from_slice(b"alice");
+        auth.push(password.len() as u8);
+        auth.extend_from_slice(password.as_bytes());
+        stream.write_all(&auth).await.unwrap();
+        stream.read_exact(&mut response).await.unwrap();
+        if response[1] != 0x00 {
+            continue;
+        }
+
+        let mut request = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+        request.extend_from_slice(&echo_port.to_be_bytes());
+        stream.write_all(&request).await.unwrap();
+        let mut reply = [0u8; 10];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], 0x00);
+
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:9290").await.unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let response = read_to_close(&mut stream).await;
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("proxy_connections_accepted_total 2\n"));
+    assert!(response.contains("proxy_active_connections 0\n"));
+    assert!(response.contains("proxy_auth_total{result=\"success\",user=\"alice\"} 1\n"));
+    assert!(response.contains("proxy_auth_total{result=\"failure\"} 1\n"));
+    assert!(response.contains("proxy_handshake_failures_total{error=\"auth_failed\"} 1\n"));
+    assert!(response.contains("proxy_replies_total{protocol=\"socks5\",code=\"0\"} 1\n"));
+    assert!(response.contains("proxy_bytes_total{user=\"alice\",direction=\"upload\"} 5\n"));
+    assert!(response.contains("proxy_connect_duration_seconds_count 1\n"));
+
+    let mut stream = TcpStream::connect("127.0.0.1:9290").await.unwrap();
+    stream.write_all(b"GET /other HTTP/1.1\r\n\r\n").await.unwrap();
+    assert!(read_to_close(&mut stream).await.starts_with("HTTP/1.1 404"));
+}
+
+/// 以 alice/secret 认证后通过代理连接本地端口
+async fn socks5_connect_as_alice(proxy_port: u16, target_port: u16) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+    stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+
+    let mut auth = vec![0x01, 5];
+    auth.extend_from_slice(b"alice");
+    auth.push(6);
+    auth.extend_from_slice(b"secret");
+    stream.write_all(&auth).await.unwrap();
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(response[1], 0x00);
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    request.extend_from_slice(&target_port.to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00);
+
+    stream
+}
+
+async fn admin_request(request: &str) -> String {
+    let mut stream = TcpStream::connect("127.0.0.1:9291").await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    read_to_close(&mut stream).await
+}
+
+async fn assert_closed(stream: &mut TcpStream) {
+    let mut buf = [0u8; 16];
+    let n = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf))
+        .await
+        .expect("session was not closed")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+}
+
+#[tokio::test]
+async fn test_admin_api() {
+    let echo_port = 9985;
+    let _echo_server = start_echo_server(echo_port).await;
+
+    let mut config = local_test_config(1110);
+    config.auth.enabled = true;
+    config.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+    });
+    config.admin.enabled = true;
+    config.admin.listen = "127.0.0.1:9291".to_string();
+    config.admin.token = "admin-token".to_string();
+    config.lockout.enabled = true;
+    config.lockout.max_failures = 1;
+    config.lockout.per_ip = false;
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut first = socks5_connect_as_alice(1110, echo_port).await;
+    let mut second = socks5_connect_as_alice(1110, echo_port).await;
+    first.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    first.read_exact(&mut buf).await.unwrap();
+
+    // 未携带令牌
+    let response = admin_request("GET /sessions HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 401"));
+
+    let response = admin_request("GET /sessions HTTP/1.1\r\nAuthorization: Bearer admin-token\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let sessions: Vec<serde_json::Value> = serde_json::from_str(body).unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0]["user"], "alice");
+    assert_eq!(sessions[0]["destination"], format!("127.0.0.1:{}", echo_port));
+    assert_eq!(sessions[0]["sent"], 5);
+    assert_eq!(sessions[0]["received"], 5);
+    assert!(sessions[0]["started_at"].as_u64().unwrap() > 0);
+
+    let first_id = sessions[0]["id"].as_u64().unwrap();
+    let response = admin_request(&format!(
+        "DELETE /sessions/{} HTTP/1.1\r\nAuthorization: Bearer admin-token\r\n\r\n",
+        first_id
+    ))
+    .await;
+    assert!(response.contains("{\"killed\":1}"));
+    assert_closed(&mut first).await;
+
+    let response = admin_request("DELETE /sessions/999 HTTP/1.1\r\nAuthorization: Bearer admin-token\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 404"));
+
+    let response =
+        admin_request("DELETE /users/%61lice/sessions HTTP/1.1\r\nAuthorization: Bearer admin-token\r\n\r\n").await;
+    assert!(response.contains("{\"killed\":1}"));
+    assert_closed(&mut second).await;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = admin_request("GET /sessions HTTP/1.1\r\nAuthorization: Bearer admin-token\r\n\r\n").await;
+    assert!(response.ends_with("[]"));
+
+    // 认证失败后 mallory 被锁定
+    let mut stream = TcpStream::connect("127.0.0.1:1110").await.unwrap();
+    stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+    let mut auth = vec![0x01, 7];
+    auth.extend_from_slice(b"mallory");
+    auth.push(5);
+    auth.extend_from_slice(b"wrong");
+    stream.write_all(&auth).await.unwrap();
+    stream.read_exact(&mut response).await.unwrap();
+    assert_ne!(response[1], 0x00);
+
+    let response = admin_request("GET /bans HTTP/1.1\r\nAuthorization: Bearer admin-token\r\n\r\n").await;
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let bans: Vec<serde_json::Value> = serde_json::from_str(body).unwrap();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0]["type"], "user");
+    assert_eq!(bans[0]["target"], "mallory");
+    assert!(bans[0]["remaining_secs"].as_u64().unwrap() > 0);
+}
+
+/// 以 alice/secret 认证后发送 UDP ASSOCIATE 请求，返回控制连接和响应码
+async fn udp_associate_as_alice(proxy_port: u16) -> (TcpStream, [u8; 10]) {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+    stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.unwrap();
+
+    let mut auth = vec![0x01, 5];
+    auth.extend_from_slice(b"alice");
+    auth.push(6);
+    auth.extend_from_slice(b"secret");
+    stream.write_all(&auth).await.unwrap();
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(response[1], 0x00);
+
+    stream
+        .write_all(&[0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await
+        .unwrap();
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+
+    (stream, reply)
+}
+
+/// 测试 UDP 中继计入流量配额，配额用尽后拒绝新的 UDP 关联
+#[tokio::test]
+async fn test_udp_quota() {
+    let echo_addr = start_udp_echo_server().await;
+
+    let mut config = local_test_config(1112);
+    config.auth.enabled = true;
+    config.auth.users.push(yun_socket_proxy::config::UserCredential {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+    });
+    config.quota.enabled = true;
+    config.quota.daily_bytes = 1000;
+    config.quota.cut_live_sessions = true;
+    config.quota.state_file =
+        std::env::temp_dir().join(format!("yun-socket-proxy-udp-quota-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&config.quota.state_file);
+    let state_file = config.quota.state_file.clone();
+    let server = yun_socket_proxy::server::ProxyServer::new(config);
+
+    tokio::spawn(async move {
+        let _ = server.run().await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (mut control, reply) = udp_associate_as_alice(1112).await;
+    assert_eq!(reply[1], 0x00);
+    let relay_addr = std::net::SocketAddr::from((
+        std::net::Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]),
+        u16::from_be_bytes([reply[8], reply[9]]),
+    ));
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut datagram = vec![0x00, 0x00, 0x00, 0x01, 127, 0, 0, 1];
+    datagram.extend_from_slice(&echo_addr.port().to_be_bytes());
+    datagram.extend_from_slice(&[0x42u8; 600]);
+    client.send_to(&datagram, relay_addr).await.unwrap();
+
+    // 上下行合计超出配额后关联被中断
+    let mut buf = [0u8; 16];
+    let n = timeout(Duration::from_secs(2), control.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+
+    let (_control, reply) = udp_associate_as_alice(1112).await;
+    assert_eq!(reply[1], 0x02); // Connection not allowed
+
+    let _ = std::fs::remove_file(state_file);
+}